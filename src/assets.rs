@@ -1,50 +1,566 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use bevy::{asset::AssetLoader, prelude::*};
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext},
+    prelude::*,
+    render::texture::{CompressedImageFormats, ImageSampler, ImageType},
+};
 use futures_lite::AsyncReadExt;
-use serde::Deserialize;
+use ron::error::Position;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::project::{asset_file, ProjectRoots};
+
 pub struct AssetPlugin;
 
 impl Plugin for AssetPlugin {
     fn build(&self, app: &mut App) {
-        app.init_asset::<TileDefinition>()
+        app.init_resource::<DefinitionDiagnostics>()
+            .init_asset::<TileDefinition>()
             .init_asset_loader::<TileDefinitionLoader>();
     }
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq)]
+/// How atlas frames are sized when drawn on the map.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum TileRenderSize {
+    /// Scaled to fit the map cell, keeping the frame's aspect ratio.
+    #[default]
+    Grid,
+    /// Drawn at the frame's own size in pixels, overhanging the cell if larger.
+    Tile,
+}
+
+/// The point of a frame pinned to the matching point of its cell, named after
+/// Tiled's object alignments.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum TileAnchor {
+    /// Bottom left, or bottom center on isometric maps.
+    #[default]
+    Unspecified,
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl TileAnchor {
+    /// Offset from the center as a fraction of the size, y up.
+    pub fn offset(self, isometric: bool) -> Vec2 {
+        match self {
+            TileAnchor::Unspecified if isometric => Vec2::new(0., -0.5),
+            TileAnchor::Unspecified => Vec2::new(-0.5, -0.5),
+            TileAnchor::TopLeft => Vec2::new(-0.5, 0.5),
+            TileAnchor::Top => Vec2::new(0., 0.5),
+            TileAnchor::TopRight => Vec2::new(0.5, 0.5),
+            TileAnchor::Left => Vec2::new(-0.5, 0.),
+            TileAnchor::Center => Vec2::ZERO,
+            TileAnchor::Right => Vec2::new(0.5, 0.),
+            TileAnchor::BottomLeft => Vec2::new(-0.5, -0.5),
+            TileAnchor::Bottom => Vec2::new(0., -0.5),
+            TileAnchor::BottomRight => Vec2::new(0.5, -0.5),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct AtlasDefinition {
     pub tile_size: Vec2,
     pub columns: usize,
     pub rows: usize,
     pub padding: Option<Vec2>,
     pub offsest: Option<Vec2>,
+    #[serde(default)]
+    pub render_size: TileRenderSize,
+    #[serde(default)]
+    pub anchor: TileAnchor,
+    #[serde(default)]
+    pub animations: Vec<TileAnimation>,
+}
+
+impl AtlasDefinition {
+    pub fn animation(&self, index: usize) -> Option<&TileAnimation> {
+        self.animations.iter().find(|a| a.index == index)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AnimationFrame {
+    pub index: usize,
+    /// How long the frame is shown, in milliseconds.
+    pub duration: u32,
+}
+
+/// Frames shown in turn wherever the tile at `index` is painted.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TileAnimation {
+    pub index: usize,
+    pub frames: Vec<AnimationFrame>,
+}
+
+impl TileAnimation {
+    /// The atlas index to show after `elapsed`. Every copy of a tile runs off
+    /// the same clock, so they stay in step.
+    pub fn frame_at(&self, elapsed: Duration) -> usize {
+        let total: u64 = self.frames.iter().map(|f| f.duration as u64).sum();
+        if total == 0 {
+            return self.frames.first().map_or(self.index, |f| f.index);
+        }
+        let mut time = elapsed.as_millis() as u64 % total;
+        for frame in &self.frames {
+            if time < frame.duration as u64 {
+                return frame.index;
+            }
+            time -= frame.duration as u64;
+        }
+        self.index
+    }
+}
+
+/// How a terrain set turns a cell's neighbours into a bitmask. Bits are
+/// numbered clockwise from the top of the cell as seen on screen.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum TerrainKind {
+    /// 47-tile blob set over all eight neighbours: N=1, NE=2, E=4, SE=8,
+    /// S=16, SW=32, W=64, NW=128. Corner bits only count when both
+    /// neighbouring edges are set.
+    Blob,
+    /// 16-tile Wang set over the four edges: N=1, E=2, S=4, W=8.
+    Edge,
+    /// 16-tile Wang set over the four corners: NE=1, SE=2, SW=4, NW=8. A
+    /// corner is set when all three cells around it share the terrain.
+    Corner,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TerrainSet {
+    pub name: String,
+    pub kind: TerrainKind,
+    /// Atlas index to use for each neighbour mask.
+    pub tiles: HashMap<u8, usize>,
+}
+
+/// Frames allowed next to each other, used by the wave function collapse
+/// generator instead of learning them from a painted map.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Adjacency {
+    /// Pairs `(a, b)` where frame `b` may sit to the right of frame `a`.
+    #[serde(default)]
+    pub horizontal: Vec<(usize, usize)>,
+    /// Pairs `(a, b)` where frame `b` may sit below frame `a`.
+    #[serde(default)]
+    pub vertical: Vec<(usize, usize)>,
+    /// Relative frequency of frames, 1 when missing.
+    #[serde(default)]
+    pub weights: HashMap<usize, f32>,
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq)]
+impl Adjacency {
+    pub fn is_empty(&self) -> bool {
+        self.horizontal.is_empty() && self.vertical.is_empty()
+    }
+}
+
+/// A tileset made of separate images, one per tile, which are packed into an
+/// atlas once they have loaded.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ImageCollection {
+    /// Images in tile order, relative to the tile's `path`. When empty, every
+    /// image in that folder is used, sorted by name.
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub render_size: TileRenderSize,
+    #[serde(default)]
+    pub anchor: TileAnchor,
+    #[serde(default)]
+    pub animations: Vec<TileAnimation>,
+}
+
+impl ImageCollection {
+    /// Asset path of one of the collection's files, keeping the folder's
+    /// source, e.g. `project://trees/oak.png`.
+    pub fn file_path(folder: &str, file: &str) -> String {
+        if folder.is_empty() || folder.ends_with('/') {
+            format!("{folder}{file}")
+        } else {
+            format!("{folder}/{file}")
+        }
+    }
+
+    /// Asset paths of the collection's images, in tile order.
+    pub fn paths<'a>(&'a self, folder: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.files
+            .iter()
+            .map(move |file| Self::file_path(folder, file))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Tile {
     pub name: String,
+    /// The spritesheet, or the folder holding the images of a collection.
     pub path: String,
     pub atlas_definition: Option<AtlasDefinition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<ImageCollection>,
+    #[serde(default)]
+    pub terrains: Vec<TerrainSet>,
+    #[serde(default, skip_serializing_if = "Adjacency::is_empty")]
+    pub adjacency: Adjacency,
 }
 
-#[derive(Asset, TypePath, Debug, Deserialize)]
+#[derive(Asset, TypePath, Debug, Serialize, Deserialize)]
 pub struct TileDefinition {
     pub tiles: Vec<Tile>,
 }
 
-#[derive(Default)]
-struct TileDefinitionLoader;
+/// Problems found in definition files, shared between the loader and the
+/// diagnostics panel. Loading a file replaces the entries for that file.
+#[derive(Resource, Clone, Default)]
+pub struct DefinitionDiagnostics(Arc<Mutex<Vec<Diagnostic>>>);
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub position: Option<Position>,
+    pub message: String,
+}
+
+impl DefinitionDiagnostics {
+    pub fn entries(&self) -> Vec<Diagnostic> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn replace(&self, file: &Path, errors: &[DefinitionFileError]) {
+        let mut entries = self.0.lock().unwrap();
+        entries.retain(|entry| entry.file != file);
+        entries.extend(errors.iter().map(|error| Diagnostic {
+            file: file.to_path_buf(),
+            position: error.position(),
+            message: error.to_string(),
+        }));
+    }
+}
+
+struct TileDefinitionLoader {
+    diagnostics: DefinitionDiagnostics,
+    roots: ProjectRoots,
+}
+
+impl FromWorld for TileDefinitionLoader {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            diagnostics: world.resource::<DefinitionDiagnostics>().clone(),
+            roots: world.resource::<ProjectRoots>().clone(),
+        }
+    }
+}
 
 #[non_exhaustive]
 #[derive(Debug, Error)]
 pub enum DefinitionFileError {
     #[error("Could not load: {0}")]
     Io(#[from] std::io::Error),
-    #[error("Could not parse data file: {0}")]
+    #[error("Could not parse data file: {}", .0.code)]
     RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("Could not write data file: {0}")]
+    Ron(#[from] ron::Error),
+    #[error("Could not find the list of tiles")]
+    NoTileList,
+    #[error("Tile name {name:?} is already used")]
+    DuplicateName { name: String, position: Position },
+    #[error("Could not read image {path:?} for tile {name:?}")]
+    MissingImage {
+        name: String,
+        path: String,
+        position: Position,
+    },
+    #[error("Atlas for tile {name:?} has no columns or rows")]
+    EmptyAtlas { name: String, position: Position },
+    #[error("Image collection for tile {name:?} has no images")]
+    EmptyCollection { name: String, position: Position },
+    #[error("Atlas for tile {name:?} needs a {}x{} image but it is {}x{}", needed.x, needed.y, actual.x, actual.y)]
+    AtlasTooLarge {
+        name: String,
+        needed: UVec2,
+        actual: UVec2,
+        position: Position,
+    },
+}
+
+impl DefinitionFileError {
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            DefinitionFileError::Io(_)
+            | DefinitionFileError::Ron(_)
+            | DefinitionFileError::NoTileList => None,
+            DefinitionFileError::RonSpannedError(e) => Some(e.position),
+            DefinitionFileError::DuplicateName { position, .. }
+            | DefinitionFileError::MissingImage { position, .. }
+            | DefinitionFileError::EmptyAtlas { position, .. }
+            | DefinitionFileError::EmptyCollection { position, .. }
+            | DefinitionFileError::AtlasTooLarge { position, .. } => Some(*position),
+        }
+    }
+}
+
+/// Adds a tile to the definition file at `path`. The tile is written at the
+/// end of the `tiles` list, so comments and formatting are kept.
+pub fn append_tile(path: &Path, tile: Tile) -> Result<(), DefinitionFileError> {
+    let source = std::fs::read_to_string(path)?;
+    std::fs::write(path, with_tile_appended(&source, &tile)?)?;
+    Ok(())
+}
+
+fn with_tile_appended(source: &str, tile: &Tile) -> Result<String, DefinitionFileError> {
+    // Only edit files that parse, so the scan below sees well-formed RON.
+    ron::de::from_str::<TileDefinition>(source)?;
+    let (last, close) = tiles_end(source).ok_or(DefinitionFileError::NoTileList)?;
+    let line_start = source[..close].rfind('\n').map_or(0, |i| i + 1);
+    let closing_indent = &source[line_start..close];
+    let own_line = closing_indent.trim().is_empty();
+    let closing_indent: String = closing_indent
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect();
+    let indent = format!("{closing_indent}    ");
+    let config = ron::ser::PrettyConfig::default().struct_names(true);
+    let text = ron::ser::to_string_pretty(tile, config)?.replace('\n', &format!("\n{indent}"));
+    let mut out = String::with_capacity(source.len() + text.len() + 16);
+    out.push_str(&source[..=last]);
+    if !matches!(source.as_bytes()[last], b'[' | b',') {
+        out.push(',');
+    }
+    if own_line {
+        out.push_str(&source[last + 1..line_start]);
+    } else {
+        out.push_str(&source[last + 1..close]);
+        out.push('\n');
+    }
+    out.push_str(&format!("{indent}{text},\n"));
+    if own_line {
+        out.push_str(&source[line_start..]);
+    } else {
+        out.push_str(&closing_indent);
+        out.push_str(&source[close..]);
+    }
+    Ok(out)
+}
+
+/// Byte offsets of the last token inside the `tiles` list, or of its `[` when
+/// it is empty, and of the `]` closing it. Strings and comments are skipped.
+fn tiles_end(source: &str) -> Option<(usize, usize)> {
+    let bytes = source.as_bytes();
+    let mut depth = 0;
+    let mut in_tiles = false;
+    let mut last = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                last = i;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i + 1 < bytes.len() && bytes[i + 1] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Block comments nest in RON.
+                let mut comments = 0;
+                while i + 1 < bytes.len() {
+                    match &bytes[i..i + 2] {
+                        b"/*" => comments += 1,
+                        b"*/" => comments -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                    if comments == 0 {
+                        break;
+                    }
+                }
+            }
+            b'[' if depth == 1 => {
+                let key = source[..i].trim_end().strip_suffix(':').unwrap_or_default();
+                in_tiles = key.trim_end().ends_with("tiles");
+                depth += 1;
+                last = i;
+            }
+            b']' if depth == 2 && in_tiles => return Some((last, i)),
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                last = i;
+            }
+            b')' | b']' | b'}' => {
+                depth -= 1;
+                last = i;
+            }
+            c if c.is_ascii_whitespace() => {}
+            _ => last = i,
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Where the `nth` occurrence of a quoted string starts in the source, or the
+/// start of the file if it cannot be found.
+fn find_string(source: &str, value: &str, nth: usize) -> Position {
+    let needle = format!("{value:?}");
+    let offset = source
+        .match_indices(&needle)
+        .nth(nth)
+        .map_or(0, |(offset, _)| offset);
+    let before = &source[..offset];
+    Position {
+        line: before.matches('\n').count() + 1,
+        col: before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1,
+    }
+}
+
+async fn read_image(load_context: &mut LoadContext<'_>, path: &str) -> Option<Image> {
+    let bytes = load_context.read_asset_bytes(path.to_string()).await.ok()?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    Image::from_buffer(
+        &bytes,
+        ImageType::Extension(extension),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+    )
+    .ok()
+}
+
+/// PNG files directly inside a collection's folder, sorted by name.
+fn list_images(folder: &str, roots: &ProjectRoots) -> Vec<String> {
+    let Some(dir) = asset_file(roots, &AssetPath::parse(folder)) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+        })
+        .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Checks each tile, dropping the ones that could not be drawn. Collections
+/// listing a whole folder get their files filled in.
+async fn validate(
+    definition: TileDefinition,
+    source: &str,
+    load_context: &mut LoadContext<'_>,
+    roots: &ProjectRoots,
+) -> (TileDefinition, Vec<DefinitionFileError>) {
+    let mut errors = Vec::new();
+    let mut names = HashMap::<&str, usize>::new();
+    let mut seen = HashSet::new();
+    let mut tiles = Vec::new();
+    for tile in &definition.tiles {
+        let occurrence = names.entry(&tile.name).or_default();
+        let position = find_string(source, &tile.name, *occurrence);
+        *occurrence += 1;
+        if !seen.insert(&tile.name) {
+            errors.push(DefinitionFileError::DuplicateName {
+                name: tile.name.clone(),
+                position,
+            });
+            continue;
+        }
+        if let Some(collection) = &tile.images {
+            let mut collection = collection.clone();
+            if collection.files.is_empty() {
+                collection.files = list_images(&tile.path, roots);
+            }
+            if collection.files.is_empty() {
+                errors.push(DefinitionFileError::EmptyCollection {
+                    name: tile.name.clone(),
+                    position,
+                });
+                continue;
+            }
+            let mut missing = false;
+            for (file, path) in collection.files.iter().zip(collection.paths(&tile.path)) {
+                if read_image(load_context, &path).await.is_none() {
+                    errors.push(DefinitionFileError::MissingImage {
+                        name: tile.name.clone(),
+                        path,
+                        position: find_string(source, file, 0),
+                    });
+                    missing = true;
+                }
+            }
+            if !missing {
+                tiles.push(Tile {
+                    images: Some(collection),
+                    ..tile.clone()
+                });
+            }
+            continue;
+        }
+        let Some(image) = read_image(load_context, &tile.path).await else {
+            errors.push(DefinitionFileError::MissingImage {
+                name: tile.name.clone(),
+                path: tile.path.clone(),
+                position: find_string(source, &tile.path, 0),
+            });
+            continue;
+        };
+        if let Some(atlas) = &tile.atlas_definition {
+            if atlas.columns == 0 || atlas.rows == 0 {
+                errors.push(DefinitionFileError::EmptyAtlas {
+                    name: tile.name.clone(),
+                    position,
+                });
+                continue;
+            }
+            let grid = Vec2::new(atlas.columns as f32, atlas.rows as f32);
+            let needed = atlas.offsest.unwrap_or_default()
+                + atlas.tile_size * grid
+                + atlas.padding.unwrap_or_default() * (grid - 1.);
+            let needed = needed.ceil().as_uvec2();
+            if needed.cmpgt(image.size()).any() {
+                errors.push(DefinitionFileError::AtlasTooLarge {
+                    name: tile.name.clone(),
+                    needed,
+                    actual: image.size(),
+                    position,
+                });
+                continue;
+            }
+        }
+        tiles.push(tile.clone());
+    }
+    (TileDefinition { tiles }, errors)
 }
 
 impl AssetLoader for TileDefinitionLoader {
@@ -61,9 +577,33 @@ impl AssetLoader for TileDefinitionLoader {
         load_context: &'a mut bevy::asset::LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
+            let file = load_context.path().to_path_buf();
             let mut bytes = Vec::new();
-            reader.read_to_end(&mut bytes).await?;
-            let definition = ron::de::from_bytes::<TileDefinition>(&bytes)?;
+            if let Err(e) = reader.read_to_end(&mut bytes).await {
+                let error = DefinitionFileError::from(e);
+                self.diagnostics
+                    .replace(&file, std::slice::from_ref(&error));
+                return Err(error);
+            }
+            let definition = match ron::de::from_bytes::<TileDefinition>(&bytes) {
+                Ok(definition) => definition,
+                Err(e) => {
+                    let error = DefinitionFileError::from(e);
+                    self.diagnostics
+                        .replace(&file, std::slice::from_ref(&error));
+                    return Err(error);
+                }
+            };
+            let source = String::from_utf8_lossy(&bytes);
+            let (definition, errors) =
+                validate(definition, &source, load_context, &self.roots).await;
+            for error in &errors {
+                match error.position() {
+                    Some(position) => warn!("{}:{position}: {error}", file.display()),
+                    None => warn!("{}: {error}", file.display()),
+                }
+            }
+            self.diagnostics.replace(&file, &errors);
 
             Ok(definition)
         })
@@ -73,3 +613,72 @@ impl AssetLoader for TileDefinitionLoader {
         &["ron"]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(name: &str) -> Tile {
+        Tile {
+            name: name.into(),
+            path: format!("tiles/{name}.png"),
+            atlas_definition: Some(AtlasDefinition {
+                tile_size: Vec2::splat(16.),
+                columns: 2,
+                rows: 1,
+                padding: None,
+                offsest: None,
+                render_size: default(),
+                anchor: default(),
+                animations: Vec::new(),
+            }),
+            images: None,
+            terrains: Vec::new(),
+            adjacency: default(),
+        }
+    }
+
+    fn names(source: &str) -> Vec<String> {
+        let definition: TileDefinition = ron::de::from_str(source).unwrap();
+        definition.tiles.into_iter().map(|tile| tile.name).collect()
+    }
+
+    #[test]
+    fn appending_keeps_comments_and_formatting() {
+        let source = r#"// Our tiles.
+TileDefinition(
+    tiles: [
+        // Ground, see "docs/ground.md" ]
+        Tile(
+            name: "grass",
+            path: "tiles/grass.png",
+            atlas_definition: None, /* no atlas [yet] */
+        ) // last one
+    ],
+)
+"#;
+        let appended = with_tile_appended(source, &tile("water")).unwrap();
+        assert_eq!(names(&appended), ["grass", "water"]);
+        assert!(appended.starts_with(&source[..source.find(") // last one").unwrap()]));
+        assert!(appended.contains("), // last one\n        Tile(\n"));
+        assert!(appended.ends_with("\n    ],\n)\n"));
+    }
+
+    #[test]
+    fn appending_to_empty_and_inline_lists() {
+        let appended = with_tile_appended("(tiles: [])", &tile("grass")).unwrap();
+        assert_eq!(names(&appended), ["grass"]);
+        let appended = with_tile_appended(&appended, &tile("water")).unwrap();
+        assert_eq!(names(&appended), ["grass", "water"]);
+        let source = "TileDefinition(\n    tiles: [\n    ],\n)";
+        assert_eq!(
+            names(&with_tile_appended(source, &tile("grass")).unwrap()),
+            ["grass"]
+        );
+    }
+
+    #[test]
+    fn appending_to_a_broken_file_fails() {
+        assert!(with_tile_appended("(tiles: [", &tile("grass")).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Rule-based automapping, in the spirit of Tiled's automapping.
+//!
+//! Rules live in `assets/data/rules.ron` next to `tiles.ron` and are read
+//! again every time automapping runs, so they can be tweaked while the editor
+//! is open. Each rule compares cells around an anchor cell against its input
+//! pattern and, where everything matches, writes one of its output patterns.
+//! Offsets are in map cells, with `y` counting the same way as the grid.
+
+use std::path::Path;
+
+use bevy::{asset::io::file::FileAssetReader, prelude::*};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    grid::{rect, GridSize, MapMode},
+    map::{MapData, PaintedTile},
+};
+
+pub const RULES_PATH: &str = "data/rules.ron";
+
+pub struct AutomapPlugin;
+
+impl Plugin for AutomapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AutomapEvent>().add_systems(Update, automap);
+    }
+}
+
+/// Run every rule over the map.
+#[derive(Event)]
+pub struct AutomapEvent {
+    /// Cells to look for matches in, e.g. the selection; the whole map when
+    /// `None`.
+    pub area: Option<Vec<IVec2>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    /// Every cell here must match for the rule to apply. Rules without input
+    /// never apply.
+    pub input: Vec<InputCell>,
+    /// Alternative outputs, one of which is picked by weight for each match.
+    pub output: Vec<OutputPattern>,
+    /// Chance of applying the rule at each matching anchor.
+    #[serde(default = "certain")]
+    pub probability: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct InputCell {
+    pub offset: IVec2,
+    /// Layer to read; the active layer when `None`. Missing layers are empty.
+    #[serde(default)]
+    pub layer: Option<String>,
+    pub matches: CellMatch,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum CellMatch {
+    Empty,
+    Filled,
+    Tile(PaintedTile),
+    OneOf(Vec<PaintedTile>),
+    Tileset(String),
+    Not(Box<CellMatch>),
+}
+
+impl CellMatch {
+    pub fn matches(&self, tile: Option<&PaintedTile>) -> bool {
+        match self {
+            CellMatch::Empty => tile.is_none(),
+            CellMatch::Filled => tile.is_some(),
+            CellMatch::Tile(expected) => tile == Some(expected),
+            CellMatch::OneOf(expected) => tile.is_some_and(|tile| expected.contains(tile)),
+            CellMatch::Tileset(tileset) => tile.is_some_and(|tile| tile.tileset == *tileset),
+            CellMatch::Not(inner) => !inner.matches(tile),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OutputPattern {
+    #[serde(default = "certain")]
+    pub weight: f32,
+    pub cells: Vec<OutputCell>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OutputCell {
+    pub offset: IVec2,
+    /// Layer to write; the active layer when `None`. Missing layers are added
+    /// on top of the map.
+    #[serde(default)]
+    pub layer: Option<String>,
+    /// Tile to write, or `None` to erase the cell.
+    pub tile: Option<PaintedTile>,
+}
+
+fn certain() -> f32 {
+    1.
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum RuleFileError {
+    #[error("Could not read rule file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse rule file: {0}")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+}
+
+pub fn read_rule_file(path: &Path) -> Result<RuleSet, RuleFileError> {
+    let contents = std::fs::read(path)?;
+    Ok(ron::de::from_bytes(&contents)?)
+}
+
+/// Applies the rules in order, each seeing what the ones before it wrote.
+/// Returns the number of cells changed.
+pub fn apply_rules(
+    grid_size: &GridSize,
+    map: &mut MapData,
+    rules: &RuleSet,
+    area: Option<&[IVec2]>,
+    rng: &mut fastrand::Rng,
+) -> usize {
+    let cells = match area {
+        Some(cells) => cells.to_vec(),
+        None => {
+            search_area(grid_size, map, rules).map_or_else(Vec::new, |(min, max)| rect(min, max))
+        }
+    };
+    let mut changed = 0;
+    for rule in &rules.rules {
+        if rule.input.is_empty() || rule.output.is_empty() {
+            continue;
+        }
+        let input: Vec<_> = rule
+            .input
+            .iter()
+            .map(|cell| (cell, layer_for(map, cell.layer.as_deref())))
+            .collect();
+        // Match against the map as it was before this rule, so its output
+        // does not feed back into its own input.
+        let mut anchors = Vec::new();
+        for &anchor in &cells {
+            let matched = input.iter().all(|(cell, layer)| {
+                let tile = layer.and_then(|layer| map.get_in(layer, anchor + cell.offset));
+                cell.matches.matches(tile)
+            });
+            if matched && grid_size.contains(anchor) && rng.f32() < rule.probability {
+                anchors.push(anchor);
+            }
+        }
+        debug!("Rule {:?} matched {} times", rule.name, anchors.len());
+        for anchor in anchors {
+            let output = pick(&rule.output, rng);
+            for cell in &output.cells {
+                let target = anchor + cell.offset;
+                if !grid_size.contains(target) {
+                    continue;
+                }
+                let layer = match cell.layer.as_deref() {
+                    None => map.active_layer(),
+                    Some(name) => map.layer_index(name).unwrap_or_else(|| map.add_layer(name)),
+                };
+                if map.get_in(layer, target) != cell.tile.as_ref() {
+                    map.set_in(layer, target, cell.tile.clone());
+                    changed += 1;
+                }
+            }
+        }
+    }
+    changed
+}
+
+fn layer_for(map: &MapData, name: Option<&str>) -> Option<usize> {
+    match name {
+        None => Some(map.active_layer()),
+        Some(name) => map.layer_index(name),
+    }
+}
+
+/// The whole of a bounded map, or the painted area of an infinite one grown by
+/// the furthest input offset so rules can match around its edges.
+fn search_area(grid_size: &GridSize, map: &MapData, rules: &RuleSet) -> Option<(IVec2, IVec2)> {
+    match grid_size.mode {
+        MapMode::Bounded => (grid_size.cols > 0 && grid_size.rows > 0).then(|| {
+            (
+                IVec2::ZERO,
+                IVec2::new(grid_size.cols as i32 - 1, grid_size.rows as i32 - 1),
+            )
+        }),
+        MapMode::Infinite => {
+            let reach = rules
+                .rules
+                .iter()
+                .flat_map(|rule| &rule.input)
+                .map(|cell| cell.offset.abs().max_element())
+                .max()
+                .unwrap_or(0);
+            map.bounds().map(|(min, max)| (min - reach, max + reach))
+        }
+    }
+}
+
+fn pick<'a>(outputs: &'a [OutputPattern], rng: &mut fastrand::Rng) -> &'a OutputPattern {
+    let total: f32 = outputs.iter().map(|o| o.weight.max(0.)).sum();
+    let mut roll = rng.f32() * total;
+    for output in outputs {
+        roll -= output.weight.max(0.);
+        if roll < 0. {
+            return output;
+        }
+    }
+    &outputs[outputs.len() - 1]
+}
+
+fn automap(
+    mut reader: EventReader<AutomapEvent>,
+    grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+) {
+    for event in reader.read() {
+        let path = FileAssetReader::get_base_path()
+            .join("assets")
+            .join(RULES_PATH);
+        match read_rule_file(&path) {
+            Ok(rules) => {
+                let mut rng = fastrand::Rng::new();
+                let changed = apply_rules(
+                    &grid_size,
+                    &mut map,
+                    &rules,
+                    event.area.as_deref(),
+                    &mut rng,
+                );
+                info!("Automapping changed {changed} cells");
+            }
+            Err(e) => error!("{e}"),
+        }
+    }
+}
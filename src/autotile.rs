@@ -0,0 +1,258 @@
+//! Terrain autotiling for blob and Wang tile sets.
+//!
+//! Masks are built from the eight cells around a cell on the offset grid, with
+//! "north" being the row drawn above it: `+y` on orthogonal maps and `-y` on
+//! isometric and hexagonal ones.
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{
+    assets::{TerrainKind, TerrainSet},
+    grid::{GridSize, Orientation},
+    map::{MapData, PaintedTile, TileTransform},
+};
+
+/// Bits for the edges of a blob mask, in `N, E, S, W` order.
+const BLOB_EDGES: u8 = 0b0101_0101;
+
+/// Offsets of the neighbours in `N, NE, E, SE, S, SW, W, NW` order.
+fn directions(grid_size: &GridSize) -> [IVec2; 8] {
+    let up = match grid_size.orientation {
+        Orientation::Orthogonal => 1,
+        Orientation::Isometric | Orientation::Hexagonal => -1,
+    };
+    [
+        IVec2::new(0, up),
+        IVec2::new(1, up),
+        IVec2::new(1, 0),
+        IVec2::new(1, -up),
+        IVec2::new(0, -up),
+        IVec2::new(-1, -up),
+        IVec2::new(-1, 0),
+        IVec2::new(-1, up),
+    ]
+}
+
+/// Whether the tile belongs to the terrain set.
+pub fn is_member(tileset: &str, set: &TerrainSet, tile: Option<&PaintedTile>) -> bool {
+    tile.is_some_and(|tile| tile.tileset == tileset && set.tiles.values().any(|i| *i == tile.index))
+}
+
+fn full_mask(kind: TerrainKind) -> u8 {
+    match kind {
+        TerrainKind::Blob => u8::MAX,
+        TerrainKind::Edge | TerrainKind::Corner => 0b1111,
+    }
+}
+
+/// The neighbour mask of a cell for the given terrain.
+pub fn mask(
+    grid_size: &GridSize,
+    map: &MapData,
+    tileset: &str,
+    set: &TerrainSet,
+    cell: IVec2,
+) -> u8 {
+    mask_of(grid_size, set.kind, cell, |cell| {
+        is_member(tileset, set, map.get(cell))
+    })
+}
+
+/// The neighbour mask of a cell, where `filled` tells which cells hold the
+/// terrain, e.g. for cells that are not painted yet.
+pub fn mask_of(
+    grid_size: &GridSize,
+    kind: TerrainKind,
+    cell: IVec2,
+    filled: impl Fn(IVec2) -> bool,
+) -> u8 {
+    let around = directions(grid_size).map(|offset| filled(cell + offset));
+    let bit = |set: bool, i: usize| (set as u8) << i;
+    match kind {
+        TerrainKind::Blob => (0..8).fold(0, |mask, i| {
+            // Corners only count when both edges next to them are filled.
+            let counts = i % 2 == 0 || (around[i - 1] && around[(i + 1) % 8]);
+            mask | bit(around[i] && counts, i)
+        }),
+        TerrainKind::Edge => (0..4).fold(0, |mask, i| mask | bit(around[i * 2], i)),
+        TerrainKind::Corner => (0..4).fold(0, |mask, i| {
+            let corner = i * 2 + 1;
+            let filled = around[corner - 1] && around[corner] && around[(corner + 1) % 8];
+            mask | bit(filled, i)
+        }),
+    }
+}
+
+/// Atlas index for a mask. Sets that leave out some combinations fall back to
+/// the edges alone, then to the fully surrounded tile.
+pub fn tile_index(set: &TerrainSet, mask: u8) -> Option<usize> {
+    let edges = match set.kind {
+        TerrainKind::Blob => mask & BLOB_EDGES,
+        TerrainKind::Edge | TerrainKind::Corner => mask,
+    };
+    [mask, edges, full_mask(set.kind)]
+        .into_iter()
+        .find_map(|mask| set.tiles.get(&mask).copied())
+        .or_else(|| set.tiles.values().min().copied())
+}
+
+/// Paint or erase terrain over `cells`, then pick the right tile for every
+/// terrain cell touched by the change.
+pub fn paint_terrain(
+    grid_size: &GridSize,
+    map: &mut MapData,
+    tileset: &str,
+    set: &TerrainSet,
+    cells: &[IVec2],
+    erase: bool,
+) {
+    let Some(full) = tile_index(set, full_mask(set.kind)) else {
+        return;
+    };
+    let painted = (!erase).then(|| PaintedTile {
+        tileset: tileset.to_string(),
+        index: full,
+        transform: TileTransform::default(),
+    });
+    let mut affected = HashSet::new();
+    for cell in cells {
+        // Cells already in the terrain keep their tile until the pass below.
+        if erase || !is_member(tileset, set, map.get(*cell)) {
+            map.set(*cell, painted.clone());
+        }
+        affected.insert(*cell);
+        affected.extend(directions(grid_size).map(|offset| *cell + offset));
+    }
+    for cell in affected {
+        if !grid_size.contains(cell) || !is_member(tileset, set, map.get(cell)) {
+            continue;
+        }
+        let Some(index) = tile_index(set, mask(grid_size, map, tileset, set, cell)) else {
+            continue;
+        };
+        if map.get(cell).map(|tile| tile.index) != Some(index) {
+            map.set(
+                cell,
+                Some(PaintedTile {
+                    tileset: tileset.to_string(),
+                    index,
+                    transform: TileTransform::default(),
+                }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn filled(cells: &[(i32, i32)]) -> impl Fn(IVec2) -> bool + '_ {
+        |cell| cells.contains(&(cell.x, cell.y))
+    }
+
+    fn terrain(kind: TerrainKind, tiles: &[(u8, usize)]) -> TerrainSet {
+        TerrainSet {
+            name: "grass".into(),
+            kind,
+            tiles: tiles.iter().copied().collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn blob_corners_need_both_edges() {
+        let grid_size = GridSize::default();
+        let mask = |cells| mask_of(&grid_size, TerrainKind::Blob, IVec2::ZERO, filled(cells));
+        assert_eq!(mask(&[]), 0);
+        assert_eq!(mask(&[(1, 1)]), 0);
+        assert_eq!(mask(&[(1, 1), (0, 1)]), 1);
+        assert_eq!(mask(&[(1, 1), (0, 1), (1, 0)]), 1 | 2 | 4);
+        let all: Vec<_> = (-1..=1)
+            .flat_map(|y| (-1..=1).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(mask(&all), u8::MAX);
+    }
+
+    #[test]
+    fn wang_masks_use_edges_or_corners() {
+        let grid_size = GridSize::default();
+        let cells = [(0, 1), (-1, 0), (-1, 1), (1, -1)];
+        let edge = mask_of(&grid_size, TerrainKind::Edge, IVec2::ZERO, filled(&cells));
+        assert_eq!(edge, 1 | 8);
+        let corner = mask_of(&grid_size, TerrainKind::Corner, IVec2::ZERO, filled(&cells));
+        assert_eq!(corner, 8);
+    }
+
+    #[test]
+    fn north_is_the_row_drawn_above() {
+        let mut grid_size = GridSize::default();
+        let cells = [(0, 1)];
+        let mask = |grid_size: &GridSize| {
+            mask_of(grid_size, TerrainKind::Edge, IVec2::ZERO, filled(&cells))
+        };
+        assert_eq!(mask(&grid_size), 1);
+        grid_size.orientation = Orientation::Isometric;
+        assert_eq!(mask(&grid_size), 4);
+    }
+
+    #[test]
+    fn missing_masks_fall_back_to_edges_then_full() {
+        let set = terrain(TerrainKind::Blob, &[(1 | 4, 1), (u8::MAX, 2)]);
+        assert_eq!(tile_index(&set, 1 | 2 | 4), Some(1));
+        assert_eq!(tile_index(&set, 16), Some(2));
+    }
+
+    #[test]
+    fn painting_updates_neighbours() {
+        let grid_size = GridSize::default();
+        let set = terrain(
+            TerrainKind::Edge,
+            &[(0, 0), (2, 1), (8, 2), (2 | 8, 3), (0b1111, 4)],
+        );
+        let mut map = MapData::default();
+        let index = |map: &MapData, x| map.get(IVec2::new(x, 1)).map(|tile| tile.index);
+        paint_terrain(
+            &grid_size,
+            &mut map,
+            "grass.png",
+            &set,
+            &[IVec2::new(1, 1)],
+            false,
+        );
+        assert_eq!(index(&map, 1), Some(0));
+        paint_terrain(
+            &grid_size,
+            &mut map,
+            "grass.png",
+            &set,
+            &[IVec2::new(2, 1)],
+            false,
+        );
+        assert_eq!((index(&map, 1), index(&map, 2)), (Some(1), Some(2)));
+        paint_terrain(
+            &grid_size,
+            &mut map,
+            "grass.png",
+            &set,
+            &[IVec2::new(3, 1)],
+            false,
+        );
+        assert_eq!(index(&map, 2), Some(3));
+        paint_terrain(
+            &grid_size,
+            &mut map,
+            "grass.png",
+            &set,
+            &[IVec2::new(2, 1)],
+            true,
+        );
+        assert_eq!(
+            (index(&map, 1), index(&map, 2), index(&map, 3)),
+            (Some(0), None, Some(0))
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Commands run from the shell instead of opening the editor:
+//!
+//! ```text
+//! tile-editor replace [--dry-run] [--project FILE] FROM=TO... [MAP...]
+//! tile-editor script [--dry-run] [--project FILE] [--definitions FILE]... SCRIPT [MAP...]
+//! ```
+//!
+//! `replace` swaps tiles, written `tileset:index`, in the given map files and
+//! in every map of the project. With `--dry-run` it only counts them.
+//!
+//! `script` runs a Rhai script on each of the maps, or once on an empty map
+//! when there are none. Tilesets can be named in the script once their
+//! definition files are given, which `--project` does for the project's; ones already painted on a map can also be
+//! written by image path.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use bevy::asset::AssetPath;
+use thiserror::Error;
+
+use crate::{
+    map::MapData,
+    project::{asset_file, read_project_file, CurrentProject, ProjectFileError, ProjectRoots},
+    replace::{replace_in_file, ReplaceError, Replacements},
+    script::{self, read_tilesets, run_on_file, ScriptError, ScriptWorld, TilesetInfo},
+};
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}")]
+    Replace(#[from] ReplaceError),
+    #[error("{0}")]
+    Project(#[from] ProjectFileError),
+    #[error("{0}")]
+    Script(#[from] ScriptError),
+    #[error("Could not find definition file {0}")]
+    NoDefinitions(String),
+}
+
+/// Runs the command in `args`, or returns `None` to open the editor when
+/// there is none.
+pub fn run(args: &[String]) -> Option<ExitCode> {
+    let (command, args) = args.split_first()?;
+    let result = match command.as_str() {
+        "replace" => replace(args),
+        "script" => script(args),
+        _ => return None,
+    };
+    Some(match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    })
+}
+
+fn open_project(path: &Path) -> Result<CurrentProject, CliError> {
+    Ok(CurrentProject {
+        project: read_project_file(path)?,
+        path: Some(path.to_path_buf()),
+    })
+}
+
+fn project_maps(project: &CurrentProject) -> Vec<PathBuf> {
+    project
+        .project
+        .maps
+        .iter()
+        .map(|map| project.resolve(map))
+        .collect()
+}
+
+/// Tilesets of the project's definition files, found the way the editor
+/// loads them.
+fn project_tilesets(project: &CurrentProject) -> Result<Vec<TilesetInfo>, CliError> {
+    let roots = ProjectRoots::default();
+    roots.set(project.roots());
+    let mut tilesets = Vec::new();
+    for definitions in &project.project.definitions {
+        let file = asset_file(&roots, &AssetPath::parse(definitions))
+            .filter(|file| file.exists())
+            .ok_or_else(|| CliError::NoDefinitions(definitions.clone()))?;
+        tilesets.extend(read_tilesets(&file)?);
+    }
+    Ok(tilesets)
+}
+
+fn replace(args: &[String]) -> Result<(), CliError> {
+    let usage = || {
+        CliError::Usage(
+            "Usage: tile-editor replace [--dry-run] [--project FILE] FROM=TO... [MAP...]"
+                .to_string(),
+        )
+    };
+    let mut dry_run = false;
+    let mut replacements = Replacements::default();
+    let mut maps = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--project" => maps.extend(project_maps(&open_project(Path::new(
+                args.next().ok_or_else(usage)?,
+            ))?)),
+            _ => match arg.split_once('=') {
+                Some((from, to)) => replacements.0.push((from.parse()?, to.parse()?)),
+                None => maps.push(PathBuf::from(arg)),
+            },
+        }
+    }
+    if replacements.0.is_empty() || maps.is_empty() {
+        return Err(usage());
+    }
+    let mut total = 0;
+    for path in &maps {
+        let (changed, _) = replace_in_file(path, &replacements, !dry_run)?;
+        println!("{}: {changed} cells", path.display());
+        total += changed;
+    }
+    let verb = if dry_run { "Would replace" } else { "Replaced" };
+    println!("{verb} {total} cells in {} maps", maps.len());
+    Ok(())
+}
+
+fn script(args: &[String]) -> Result<(), CliError> {
+    let usage = || {
+        CliError::Usage(
+            "Usage: tile-editor script [--dry-run] [--project FILE] [--definitions FILE]... SCRIPT [MAP...]"
+                .to_string(),
+        )
+    };
+    let mut dry_run = false;
+    let mut tilesets = Vec::new();
+    let mut script = None;
+    let mut maps = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--project" => {
+                let project = open_project(Path::new(args.next().ok_or_else(usage)?))?;
+                maps.extend(project_maps(&project));
+                tilesets.extend(project_tilesets(&project)?);
+            }
+            "--definitions" => {
+                tilesets.extend(read_tilesets(Path::new(args.next().ok_or_else(usage)?))?)
+            }
+            _ if script.is_none() => script = Some(PathBuf::from(arg)),
+            _ => maps.push(PathBuf::from(arg)),
+        }
+    }
+    let script = script.ok_or_else(usage)?;
+    let source = std::fs::read_to_string(&script).map_err(ScriptError::from)?;
+    if maps.is_empty() {
+        let world = ScriptWorld {
+            map: MapData::default(),
+            bounds: None,
+            selection: Vec::new(),
+            tilesets,
+            output: Vec::new(),
+        };
+        let (world, result) = script::run(&source, world);
+        world.output.iter().for_each(|line| println!("{line}"));
+        return Ok(result?);
+    }
+    let mut total = 0;
+    for path in &maps {
+        let (changed, output) = run_on_file(&source, path, &tilesets, !dry_run)?;
+        output.iter().for_each(|line| println!("{line}"));
+        println!("{}: {changed} cells", path.display());
+        total += changed;
+    }
+    let verb = if dry_run { "Would change" } else { "Changed" };
+    println!("{verb} {total} cells in {} maps", maps.len());
+    Ok(())
+}
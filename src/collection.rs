@@ -0,0 +1,168 @@
+//! Image-collection tilesets: one image file per tile, packed into a runtime
+//! atlas so they paint and show in the palette like spritesheets do.
+
+use std::collections::HashMap;
+
+use bevy::{asset::LoadState, prelude::*};
+
+use crate::{
+    assets::{AtlasDefinition, Tile},
+    map::MapData,
+    AtlasesRebuilt, MapSettings,
+};
+
+/// Largest atlas a collection is packed into.
+const MAX_ATLAS_SIZE: f32 = 8192.;
+
+pub struct CollectionPlugin;
+
+impl Plugin for CollectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ImageCollections>().add_systems(
+            Update,
+            (
+                repack_modified.run_if(on_event::<AssetEvent<Image>>()),
+                pack_collections,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// The collection tilesets from the tile definitions, keyed by tile path.
+#[derive(Resource, Default)]
+pub struct ImageCollections(HashMap<String, Collection>);
+
+struct Collection {
+    images: Vec<Handle<Image>>,
+    definition: AtlasDefinition,
+    packed: bool,
+}
+
+impl ImageCollections {
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Starts loading the images of a collection tile; it is packed once they
+    /// have all loaded.
+    pub fn add(&mut self, tile: &Tile, asset_server: &AssetServer) {
+        let Some(collection) = &tile.images else {
+            return;
+        };
+        let images = collection
+            .paths(&tile.path)
+            .map(|path| asset_server.load(path))
+            .collect();
+        let definition = AtlasDefinition {
+            tile_size: Vec2::ZERO,
+            columns: collection.files.len(),
+            rows: 1,
+            padding: None,
+            offsest: None,
+            render_size: collection.render_size,
+            anchor: collection.anchor,
+            animations: collection.animations.clone(),
+        };
+        self.0.insert(
+            tile.path.clone(),
+            Collection {
+                images,
+                definition,
+                packed: false,
+            },
+        );
+    }
+}
+
+/// Packs the images of a collection into one atlas, with frames in the same
+/// order as the files.
+fn pack(
+    collection: &Collection,
+    images: &mut Assets<Image>,
+) -> Result<(TextureAtlas, Vec2), String> {
+    let mut builder = TextureAtlasBuilder::default().max_size(Vec2::splat(MAX_ATLAS_SIZE));
+    let mut largest = Vec2::ZERO;
+    for handle in &collection.images {
+        let image = images
+            .get(handle)
+            .ok_or_else(|| "an image is not loaded".to_string())?;
+        largest = largest.max(image.size_f32());
+        builder.add_texture(handle.id(), image);
+    }
+    let packed = builder.finish(images).map_err(|e| e.to_string())?;
+    let mut atlas = TextureAtlas::new_empty(packed.texture.clone(), packed.size);
+    for handle in &collection.images {
+        let index = packed.get_texture_index(handle).unwrap();
+        atlas.add_texture(packed.textures[index]);
+    }
+    Ok((atlas, largest))
+}
+
+fn pack_collections(
+    mut collections: ResMut<ImageCollections>,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut state: ResMut<MapSettings>,
+    mut map: ResMut<MapData>,
+    mut rebuilt: EventWriter<AtlasesRebuilt>,
+) {
+    let mut packed_any = false;
+    for (path, collection) in &mut collections.0 {
+        if collection.packed {
+            continue;
+        }
+        let failed = collection
+            .images
+            .iter()
+            .any(|handle| asset_server.get_load_state(handle) == Some(LoadState::Failed));
+        if failed {
+            error!("Could not load the images of collection {path}");
+            collection.packed = true;
+            continue;
+        }
+        if !collection
+            .images
+            .iter()
+            .all(|handle| images.contains(handle))
+        {
+            continue;
+        }
+        collection.packed = true;
+        match pack(collection, &mut images) {
+            Ok((atlas, largest)) => {
+                let mut definition = collection.definition.clone();
+                // Frames keep their own size, this only matters for export.
+                definition.tile_size = largest;
+                state
+                    .atlases
+                    .insert(path.clone(), texture_atlases.add(atlas));
+                state.atlas_definitions.insert(path.clone(), definition);
+                packed_any = true;
+            }
+            Err(e) => error!("Could not pack collection {path}: {e}"),
+        }
+    }
+    if packed_any {
+        map.redraw();
+        rebuilt.send(AtlasesRebuilt);
+    }
+}
+
+/// Packs collections again when one of their images changes on disk.
+fn repack_modified(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut collections: ResMut<ImageCollections>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        for collection in collections.0.values_mut() {
+            if collection.images.iter().any(|handle| handle.id() == *id) {
+                collection.packed = false;
+            }
+        }
+    }
+}
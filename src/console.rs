@@ -0,0 +1,591 @@
+//! A drop-down console, toggled with the backquote key, for editing from the
+//! keyboard. Every command that changes the map is one undo step:
+//!
+//! ```text
+//! fill 0 0 10 5 grass:1    fill a rectangle of cells
+//! set 3 4 grass:2          paint one cell
+//! erase 0 0 10 5           empty a rectangle, or one cell
+//! resize 64 64 center      resize a bounded map, keeping the tiles at an anchor
+//! layer add decor          add a layer on top and make it active
+//! layer select decor
+//! undo, redo
+//! run level.macro          run the commands of a file, one per line
+//! save level.macro         write the commands entered so far to a file
+//! ```
+//!
+//! Tiles are written `tileset:index`, where the tileset is its name or its
+//! image path. Names holding spaces go in double quotes. In macro files,
+//! lines starting with `#` are comments.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till, take_till1},
+    character::complete::{char, i32 as int, space0, space1, u32 as uint},
+    combinator::{all_consuming, map, opt, value},
+    sequence::{delimited, preceded, tuple},
+    IResult, Parser,
+};
+use thiserror::Error;
+
+use crate::{
+    assets::TileDefinition,
+    grid::{rect, rect_len, resize_map, GridSize, MapMode, MAX_RECT_CELLS},
+    history::History,
+    map::MapData,
+    script::{parse_tile, TilesetInfo},
+    TilesData,
+};
+
+/// Macros may run other macros this deep, which stops a macro running itself
+/// forever.
+const MAX_DEPTH: usize = 8;
+
+const HELP: &str = "fill X0 Y0 X1 Y1 TILE | set X Y TILE | erase X0 Y0 [X1 Y1]
+resize COLUMNS ROWS [top-left|top|top-right|left|center|right|bottom-left|bottom|bottom-right]
+layer add NAME | layer select NAME | undo | redo | run FILE | save FILE";
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CommandConsole>()
+            .add_systems(Update, console_ui);
+    }
+}
+
+/// The point of the map that stays in place when it is resized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Share of the added columns placed left of the tiles, and of the added
+    /// rows placed above them on screen.
+    fn before(self) -> Vec2 {
+        let x = match self {
+            Self::TopLeft | Self::Left | Self::BottomLeft => 0.,
+            Self::Top | Self::Center | Self::Bottom => 0.5,
+            Self::TopRight | Self::Right | Self::BottomRight => 1.,
+        };
+        let y = match self {
+            Self::TopLeft | Self::Top | Self::TopRight => 0.,
+            Self::Left | Self::Center | Self::Right => 0.5,
+            Self::BottomLeft | Self::Bottom | Self::BottomRight => 1.,
+        };
+        Vec2::new(x, y)
+    }
+
+    /// How far the tiles move when the map is resized to `size`.
+    pub fn offset(self, grid_size: &GridSize, size: UVec2) -> IVec2 {
+        let grow = size.as_ivec2() - IVec2::new(grid_size.cols as i32, grid_size.rows as i32);
+        let before = (grow.as_vec2() * self.before()).floor().as_ivec2();
+        // Rows count up the screen on orthogonal maps, and down it otherwise.
+        let y = if grid_size.down() == IVec2::Y {
+            before.y
+        } else {
+            grow.y - before.y
+        };
+        IVec2::new(before.x, y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Fills an inclusive range of cells, or erases it when there is no tile.
+    Fill {
+        from: IVec2,
+        to: IVec2,
+        tile: Option<String>,
+    },
+    Resize {
+        size: UVec2,
+        anchor: Option<Anchor>,
+    },
+    AddLayer(String),
+    SelectLayer(String),
+    Undo,
+    Redo,
+    Run(PathBuf),
+    Save(PathBuf),
+    Help,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ConsoleError {
+    #[error("Could not read command {0:?}, try help")]
+    Parse(String),
+    #[error("{0}")]
+    Tile(String),
+    #[error("There is no layer {0}")]
+    NoLayer(String),
+    #[error("There is already a layer {0}")]
+    LayerExists(String),
+    #[error("Infinite maps have no size")]
+    Infinite,
+    #[error("{0} cells is more than the {MAX_RECT_CELLS} a command may change")]
+    TooManyCells(u64),
+    #[error("Macros are nested too deep")]
+    TooDeep,
+    #[error("Could not access file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{path}:{line}: {error}")]
+    Macro {
+        path: PathBuf,
+        line: usize,
+        error: Box<ConsoleError>,
+    },
+}
+
+/// A name, in double quotes if it holds spaces.
+fn word(input: &str) -> IResult<&str, String> {
+    map(
+        alt((
+            delimited(char('"'), take_till(|c| c == '"'), char('"')),
+            take_till1(char::is_whitespace),
+        )),
+        str::to_string,
+    )(input)
+}
+
+fn cell(input: &str) -> IResult<&str, IVec2> {
+    map(tuple((int, preceded(space1, int))), |(x, y)| {
+        IVec2::new(x, y)
+    })(input)
+}
+
+/// An argument, after the spaces separating it from the one before.
+fn arg<'a, O>(
+    parser: impl Parser<&'a str, O, nom::error::Error<&'a str>>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    preceded(space1, parser)
+}
+
+fn anchor(input: &str) -> IResult<&str, Anchor> {
+    alt((
+        value(Anchor::TopLeft, tag("top-left")),
+        value(Anchor::TopRight, tag("top-right")),
+        value(Anchor::Top, tag("top")),
+        value(Anchor::BottomLeft, tag("bottom-left")),
+        value(Anchor::BottomRight, tag("bottom-right")),
+        value(Anchor::Bottom, tag("bottom")),
+        value(Anchor::Left, tag("left")),
+        value(Anchor::Right, tag("right")),
+        value(Anchor::Center, tag("center")),
+    ))(input)
+}
+
+fn fill(input: &str) -> IResult<&str, Command> {
+    map(
+        preceded(tag("fill"), tuple((arg(cell), arg(cell), arg(word)))),
+        |(from, to, tile)| Command::Fill {
+            from,
+            to,
+            tile: Some(tile),
+        },
+    )(input)
+}
+
+fn set(input: &str) -> IResult<&str, Command> {
+    map(
+        preceded(tag("set"), tuple((arg(cell), arg(word)))),
+        |(cell, tile)| Command::Fill {
+            from: cell,
+            to: cell,
+            tile: Some(tile),
+        },
+    )(input)
+}
+
+fn erase(input: &str) -> IResult<&str, Command> {
+    map(
+        preceded(tag("erase"), tuple((arg(cell), opt(arg(cell))))),
+        |(from, to)| Command::Fill {
+            from,
+            to: to.unwrap_or(from),
+            tile: None,
+        },
+    )(input)
+}
+
+fn resize(input: &str) -> IResult<&str, Command> {
+    map(
+        preceded(
+            tag("resize"),
+            tuple((arg(uint), arg(uint), opt(arg(anchor)))),
+        ),
+        |(columns, rows, anchor)| Command::Resize {
+            size: UVec2::new(columns, rows),
+            anchor,
+        },
+    )(input)
+}
+
+fn layer(input: &str) -> IResult<&str, Command> {
+    preceded(
+        tag("layer"),
+        arg(alt((
+            map(preceded(tag("add"), arg(word)), Command::AddLayer),
+            map(preceded(tag("select"), arg(word)), Command::SelectLayer),
+        ))),
+    )(input)
+}
+
+fn file(input: &str) -> IResult<&str, Command> {
+    alt((
+        map(preceded(tag("run"), arg(word)), |path| {
+            Command::Run(path.into())
+        }),
+        map(preceded(tag("save"), arg(word)), |path| {
+            Command::Save(path.into())
+        }),
+    ))(input)
+}
+
+fn command(input: &str) -> IResult<&str, Command> {
+    alt((
+        fill,
+        set,
+        erase,
+        resize,
+        layer,
+        file,
+        value(Command::Undo, tag("undo")),
+        value(Command::Redo, tag("redo")),
+        value(Command::Help, tag("help")),
+    ))(input)
+}
+
+pub fn parse_command(line: &str) -> Result<Command, ConsoleError> {
+    all_consuming(delimited(space0, command, space0))(line)
+        .map(|(_, command)| command)
+        .map_err(|_| ConsoleError::Parse(line.trim().to_string()))
+}
+
+/// What commands work on.
+#[derive(SystemParam)]
+struct Editor<'w> {
+    map: ResMut<'w, MapData>,
+    history: ResMut<'w, History>,
+    grid_size: ResMut<'w, GridSize>,
+    tile_handle: Res<'w, TilesData>,
+    tile_assets: Res<'w, Assets<TileDefinition>>,
+}
+
+impl Editor<'_> {
+    /// Runs a command, returning what it did. `save` and `help` are left to
+    /// the console.
+    fn execute(&mut self, command: &Command, depth: usize) -> Result<String, ConsoleError> {
+        let map = &mut *self.map;
+        match command {
+            Command::Fill { from, to, tile } => {
+                let tile = match tile {
+                    Some(tile) => {
+                        let tilesets: Vec<_> = self
+                            .tile_handle
+                            .tiles(&self.tile_assets)
+                            .map(TilesetInfo::from_definition)
+                            .collect();
+                        Some(parse_tile(tile, &tilesets).map_err(ConsoleError::Tile)?)
+                    }
+                    None => None,
+                };
+                let Some((from, to)) = self.grid_size.clip(*from, *to) else {
+                    return Ok("Changed 0 cells".to_string());
+                };
+                let cells = rect_len(from, to);
+                if cells > MAX_RECT_CELLS {
+                    return Err(ConsoleError::TooManyCells(cells));
+                }
+                self.history.commit(map);
+                let mut changed = 0;
+                for cell in rect(from, to) {
+                    if map.get(cell) != tile.as_ref() {
+                        map.set(cell, tile.clone());
+                        changed += 1;
+                    }
+                }
+                self.history.commit(map);
+                Ok(format!("Changed {changed} cells"))
+            }
+            Command::Resize { size, anchor } => {
+                if self.grid_size.mode != MapMode::Bounded {
+                    return Err(ConsoleError::Infinite);
+                }
+                let offset =
+                    anchor.map_or(IVec2::ZERO, |anchor| anchor.offset(&self.grid_size, *size));
+                resize_map(&mut self.grid_size, map, &mut self.history, *size, offset);
+                Ok(format!("Resized to {} x {}", size.x, size.y))
+            }
+            Command::AddLayer(name) => {
+                if map.layer_index(name).is_some() {
+                    return Err(ConsoleError::LayerExists(name.clone()));
+                }
+                self.history.commit(map);
+                let layer = map.add_layer(name.clone());
+                map.set_active_layer(layer);
+                self.history.commit(map);
+                Ok(format!("Added layer {name}"))
+            }
+            Command::SelectLayer(name) => {
+                let layer = map
+                    .layer_index(name)
+                    .ok_or_else(|| ConsoleError::NoLayer(name.clone()))?;
+                map.set_active_layer(layer);
+                Ok(format!("Painting on layer {name}"))
+            }
+            Command::Undo => Ok(match self.history.undo(map, &mut self.grid_size) {
+                true => "Undone".to_string(),
+                false => "Nothing to undo".to_string(),
+            }),
+            Command::Redo => Ok(match self.history.redo(map, &mut self.grid_size) {
+                true => "Redone".to_string(),
+                false => "Nothing to redo".to_string(),
+            }),
+            Command::Run(path) => self.run_macro(path, depth + 1),
+            Command::Save(_) | Command::Help => Ok(String::new()),
+        }
+    }
+
+    /// Runs the commands of a macro file, stopping at the first that fails.
+    fn run_macro(&mut self, path: &Path, depth: usize) -> Result<String, ConsoleError> {
+        if depth > MAX_DEPTH {
+            return Err(ConsoleError::TooDeep);
+        }
+        let source = std::fs::read_to_string(path)?;
+        let mut ran = 0;
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            parse_command(line)
+                .and_then(|command| self.execute(&command, depth))
+                .map_err(|error| ConsoleError::Macro {
+                    path: path.to_path_buf(),
+                    line: i + 1,
+                    error: Box::new(error),
+                })?;
+            ran += 1;
+        }
+        Ok(format!("Ran {ran} commands from {}", path.display()))
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct CommandConsole {
+    pub open: bool,
+    input: String,
+    /// Lines shown above the input, and whether each is an error.
+    output: Vec<(String, bool)>,
+    /// Commands that ran, for `save` and for recalling with the arrow keys.
+    entered: Vec<String>,
+    /// Position in `entered` while recalling.
+    recall: Option<usize>,
+}
+
+impl CommandConsole {
+    fn submit(&mut self, editor: &mut Editor) {
+        let line = std::mem::take(&mut self.input);
+        let line = line.trim();
+        self.recall = None;
+        if line.is_empty() {
+            return;
+        }
+        self.output.push((format!("> {line}"), false));
+        let result = parse_command(line).and_then(|command| match &command {
+            Command::Help => Ok(HELP.to_string()),
+            Command::Save(path) => {
+                let mut contents = self.entered.join("\n");
+                contents.push('\n');
+                std::fs::write(path, contents)?;
+                Ok(format!(
+                    "Saved {} commands to {}",
+                    self.entered.len(),
+                    path.display()
+                ))
+            }
+            _ => {
+                let message = editor.execute(&command, 0)?;
+                self.entered.push(line.to_string());
+                Ok(message)
+            }
+        });
+        match result {
+            Ok(message) => self.output.push((message, false)),
+            Err(e) => self.output.push((e.to_string(), true)),
+        }
+    }
+
+    /// Steps through the entered commands, older with `back` set.
+    fn recall(&mut self, back: bool) {
+        let last = self.entered.len().checked_sub(1);
+        self.recall = match (self.recall, back) {
+            (None, true) => last,
+            (None, false) => None,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) => (i + 1 < self.entered.len()).then_some(i + 1),
+        };
+        self.input = self
+            .recall
+            .map_or_else(String::new, |i| self.entered[i].clone());
+    }
+}
+
+fn console_ui(
+    mut contexts: EguiContexts,
+    keys: Res<Input<KeyCode>>,
+    mut console: ResMut<CommandConsole>,
+    mut editor: Editor,
+) {
+    let ctx = contexts.ctx_mut();
+    if keys.just_pressed(KeyCode::Grave) && !ctx.wants_keyboard_input() {
+        console.open = !console.open;
+    }
+    if !console.open {
+        return;
+    }
+    let console = &mut *console;
+    egui::TopBottomPanel::top("console").show(ctx, |ui| {
+        egui::ScrollArea::vertical()
+            .max_height(200.)
+            .stick_to_bottom(true)
+            .show(ui, |sui| {
+                for (line, error) in &console.output {
+                    let text = egui::RichText::new(line).monospace();
+                    match error {
+                        true => sui.label(text.color(egui::Color32::LIGHT_RED)),
+                        false => sui.label(text),
+                    };
+                }
+            });
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut console.input)
+                .font(egui::TextStyle::Monospace)
+                .desired_width(f32::INFINITY)
+                .hint_text("help"),
+        );
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            console.submit(&mut editor);
+            response.request_focus();
+        } else if response.has_focus() {
+            if ui.input(|i| i.key_pressed(egui::Key::ArrowUp)) {
+                console.recall(true);
+            } else if ui.input(|i| i.key_pressed(egui::Key::ArrowDown)) {
+                console.recall(false);
+            } else if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                console.open = false;
+            }
+        }
+        // Typing the toggle key closes the console rather than adding a `.
+        if response.changed() && console.input.ends_with('`') {
+            console.input.pop();
+            console.open = false;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::Orientation;
+
+    use super::*;
+
+    #[test]
+    fn parses_edits() {
+        assert_eq!(
+            parse_command("  fill -2 0 10 5 grass:1 ").unwrap(),
+            Command::Fill {
+                from: IVec2::new(-2, 0),
+                to: IVec2::new(10, 5),
+                tile: Some("grass:1".into()),
+            }
+        );
+        assert_eq!(
+            parse_command("set 3 4 \"Tall grass:2\"").unwrap(),
+            Command::Fill {
+                from: IVec2::new(3, 4),
+                to: IVec2::new(3, 4),
+                tile: Some("Tall grass:2".into()),
+            }
+        );
+        assert_eq!(
+            parse_command("erase 1 2").unwrap(),
+            Command::Fill {
+                from: IVec2::new(1, 2),
+                to: IVec2::new(1, 2),
+                tile: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_the_rest() {
+        assert_eq!(
+            parse_command("resize 64 32 top-right").unwrap(),
+            Command::Resize {
+                size: UVec2::new(64, 32),
+                anchor: Some(Anchor::TopRight),
+            }
+        );
+        assert_eq!(
+            parse_command("resize 8 8").unwrap(),
+            Command::Resize {
+                size: UVec2::splat(8),
+                anchor: None,
+            }
+        );
+        assert_eq!(
+            parse_command("layer add \"Top decor\"").unwrap(),
+            Command::AddLayer("Top decor".into())
+        );
+        assert_eq!(
+            parse_command("run level.macro").unwrap(),
+            Command::Run("level.macro".into())
+        );
+        assert_eq!(parse_command("undo").unwrap(), Command::Undo);
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        for line in [
+            "",
+            "fill 0 0 1 grass:1",
+            "undo now",
+            "resize -1 4",
+            "paint 1 1 a:1",
+        ] {
+            assert!(
+                matches!(parse_command(line), Err(ConsoleError::Parse(_))),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn anchors_keep_their_side_on_screen() {
+        let mut grid_size = GridSize::default();
+        let size = UVec2::new(grid_size.cols as u32 + 2, grid_size.rows as u32 + 2);
+        assert_eq!(Anchor::TopLeft.offset(&grid_size, size), IVec2::new(0, 2));
+        assert_eq!(Anchor::Center.offset(&grid_size, size), IVec2::ONE);
+        assert_eq!(
+            Anchor::BottomRight.offset(&grid_size, size),
+            IVec2::new(2, 0)
+        );
+        grid_size.orientation = Orientation::Isometric;
+        assert_eq!(Anchor::TopLeft.offset(&grid_size, size), IVec2::ZERO);
+    }
+}
@@ -0,0 +1,282 @@
+//! Painting dungeon layouts from [`tile_editor::layout`]: a frame each for
+//! floor and walls, or a terrain set autotiled over the floor or the walls.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use tile_editor::layout::{bsp, caves, BspSettings, CaveSettings, DungeonGrid};
+
+use crate::{
+    assets::{TerrainSet, TileDefinition},
+    autotile::{mask_of, tile_index},
+    generator::{region, size_ui, Generated, GeneratedPreview, DEFAULT_SIZE},
+    grid::GridSize,
+    map::{PaintedTile, TileTransform},
+    MapSettings, TilesData,
+};
+
+pub struct DungeonPlugin;
+
+impl Plugin for DungeonPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DungeonGenerator>()
+            .add_systems(Update, dungeon_ui);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TerrainOn {
+    Floor,
+    #[default]
+    Walls,
+}
+
+/// How floor and walls are painted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DungeonTiles {
+    pub tileset: String,
+    /// Frames for floor and walls; `None` leaves the cells empty.
+    pub floor: Option<usize>,
+    pub wall: Option<usize>,
+    /// Terrain autotiled over the floor or the walls instead of their frame.
+    pub terrain: Option<(TerrainSet, TerrainOn)>,
+}
+
+impl DungeonTiles {
+    /// The tiles of a layout placed at `min`.
+    pub fn paint(&self, grid: &DungeonGrid, min: IVec2, grid_size: &GridSize) -> Generated {
+        let mut generated = Generated::new("Dungeon");
+        let tile = |index| PaintedTile {
+            tileset: self.tileset.clone(),
+            index,
+            transform: TileTransform::default(),
+        };
+        for cell in grid.cells() {
+            let floor = grid.is_floor(cell);
+            let index = match &self.terrain {
+                Some((set, on)) if floor == (*on == TerrainOn::Floor) => {
+                    let mask = mask_of(grid_size, set.kind, cell, |other| {
+                        grid.is_floor(other) == floor
+                    });
+                    tile_index(set, mask)
+                }
+                _ if floor => self.floor,
+                _ => self.wall,
+            };
+            generated.cells.push((min + cell, index.map(tile)));
+        }
+        generated
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DungeonKind {
+    #[default]
+    Rooms,
+    Caves,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DungeonSettings {
+    pub kind: DungeonKind,
+    pub rooms: BspSettings,
+    pub caves: CaveSettings,
+    pub tiles: DungeonTiles,
+    pub seed: u32,
+    /// Size generated on infinite maps.
+    pub size: UVec2,
+}
+
+impl Default for DungeonSettings {
+    fn default() -> Self {
+        Self {
+            kind: DungeonKind::default(),
+            rooms: BspSettings::default(),
+            caves: CaveSettings::default(),
+            tiles: DungeonTiles {
+                floor: Some(0),
+                wall: Some(1),
+                ..default()
+            },
+            seed: 0,
+            size: DEFAULT_SIZE,
+        }
+    }
+}
+
+impl DungeonSettings {
+    pub fn layout(&self, size: UVec2) -> DungeonGrid {
+        match self.kind {
+            DungeonKind::Rooms => bsp(size, &self.rooms, self.seed),
+            DungeonKind::Caves => caves(size, &self.caves, self.seed),
+        }
+    }
+
+    pub fn generate(&self, grid_size: &GridSize) -> Generated {
+        let (min, size) = region(grid_size, self.size);
+        let mut generated = self.tiles.paint(&self.layout(size), min, grid_size);
+        let kind = match self.kind {
+            DungeonKind::Rooms => "Rooms",
+            DungeonKind::Caves => "Caves",
+        };
+        generated.name = format!("{kind}, seed {}", self.seed);
+        generated
+    }
+}
+
+/// The open dungeon generator window, if any.
+#[derive(Resource, Default)]
+pub struct DungeonGenerator(pub Option<DungeonSettings>);
+
+fn frame_ui(ui: &mut egui::Ui, label: &str, frame: &mut Option<usize>, frames: usize) {
+    ui.horizontal(|hui| {
+        let mut paint = frame.is_some();
+        hui.checkbox(&mut paint, label);
+        let mut index = frame.unwrap_or(0);
+        hui.add_enabled(
+            paint,
+            egui::DragValue::new(&mut index).clamp_range(0..=frames - 1),
+        );
+        *frame = paint.then_some(index);
+    });
+}
+
+fn dungeon_ui(
+    mut contexts: EguiContexts,
+    mut generator: ResMut<DungeonGenerator>,
+    mut preview: ResMut<GeneratedPreview>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    grid_size: Res<GridSize>,
+) {
+    let Some(dungeon) = &mut generator.0 else {
+        return;
+    };
+    let tilesets: Vec<_> = tile_handle
+        .tiles(&tile_assets)
+        .filter(|tile| settings.atlases.contains_key(&tile.path))
+        .collect();
+    let tiles = &mut dungeon.tiles;
+    if !tilesets.iter().any(|tile| tile.path == tiles.tileset) {
+        if let Some(tile) = tilesets.first() {
+            tiles.tileset = tile.path.clone();
+            tiles.terrain = None;
+        }
+    }
+    let terrains = tilesets
+        .iter()
+        .find(|tile| tile.path == tiles.tileset)
+        .map_or(&[][..], |tile| tile.terrains.as_slice());
+    let frames = settings
+        .atlases
+        .get(&tiles.tileset)
+        .and_then(|handle| texture_atlases.get(handle))
+        .map_or(1, |atlas| atlas.len().max(1));
+    let mut open = true;
+    let mut generate = false;
+    egui::Window::new("Dungeon")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|hui| {
+                hui.radio_value(&mut dungeon.kind, DungeonKind::Rooms, "Rooms");
+                hui.radio_value(&mut dungeon.kind, DungeonKind::Caves, "Caves");
+            });
+            match dungeon.kind {
+                DungeonKind::Rooms => {
+                    let rooms = &mut dungeon.rooms;
+                    ui.horizontal(|hui| {
+                        hui.label("Room size");
+                        hui.add(egui::DragValue::new(&mut rooms.min_room).clamp_range(1..=64));
+                        hui.label("to");
+                        hui.add(
+                            egui::DragValue::new(&mut rooms.max_room)
+                                .clamp_range(rooms.min_room..=128),
+                        );
+                    });
+                    ui.horizontal(|hui| {
+                        hui.label("Corridor width");
+                        hui.add(egui::DragValue::new(&mut rooms.corridor_width).clamp_range(1..=8));
+                    });
+                }
+                DungeonKind::Caves => {
+                    let caves = &mut dungeon.caves;
+                    ui.add(egui::Slider::new(&mut caves.fill, 0.0..=1.0).text("Starting walls"));
+                    ui.add(egui::Slider::new(&mut caves.steps, 0..=20).text("Steps"));
+                    ui.add(egui::Slider::new(&mut caves.birth, 0..=8).text("Walls to fill"));
+                    ui.add(egui::Slider::new(&mut caves.survival, 0..=8).text("Walls to stand"));
+                    ui.checkbox(&mut caves.keep_largest, "Only the largest cave");
+                }
+            }
+            ui.horizontal(|hui| {
+                hui.label("Seed");
+                hui.add(egui::DragValue::new(&mut dungeon.seed));
+                if hui.button("New Seed").clicked() {
+                    dungeon.seed = fastrand::u32(..);
+                    generate = true;
+                }
+            });
+            size_ui(ui, &grid_size, &mut dungeon.size);
+
+            ui.separator();
+            let tiles = &mut dungeon.tiles;
+            let selected = tilesets
+                .iter()
+                .find(|tile| tile.path == tiles.tileset)
+                .map_or("", |tile| tile.name.as_str());
+            egui::ComboBox::from_label("Tileset")
+                .selected_text(selected)
+                .show_ui(ui, |cui| {
+                    for tile in &tilesets {
+                        if cui
+                            .selectable_value(&mut tiles.tileset, tile.path.clone(), &tile.name)
+                            .changed()
+                        {
+                            tiles.terrain = None;
+                        }
+                    }
+                });
+            frame_ui(ui, "Floor", &mut tiles.floor, frames);
+            frame_ui(ui, "Wall", &mut tiles.wall, frames);
+            let selected = tiles
+                .terrain
+                .as_ref()
+                .map_or("None", |(set, _)| set.name.as_str());
+            egui::ComboBox::from_label("Terrain")
+                .selected_text(selected)
+                .show_ui(ui, |cui| {
+                    if cui
+                        .selectable_label(tiles.terrain.is_none(), "None")
+                        .clicked()
+                    {
+                        tiles.terrain = None;
+                    }
+                    for set in terrains {
+                        let current = tiles.terrain.as_ref().is_some_and(|(s, _)| s == set);
+                        if cui.selectable_label(current, &set.name).clicked() {
+                            let on = tiles.terrain.as_ref().map_or_else(default, |(_, on)| *on);
+                            tiles.terrain = Some((set.clone(), on));
+                        }
+                    }
+                });
+            if let Some((_, on)) = &mut tiles.terrain {
+                ui.horizontal(|hui| {
+                    hui.label("Autotile");
+                    hui.radio_value(on, TerrainOn::Walls, "Walls");
+                    hui.radio_value(on, TerrainOn::Floor, "Floor");
+                });
+            }
+
+            ui.separator();
+            let valid = !dungeon.tiles.tileset.is_empty();
+            generate |= ui
+                .add_enabled(valid, egui::Button::new("Preview"))
+                .clicked();
+        });
+    if generate && !dungeon.tiles.tileset.is_empty() {
+        preview.0 = Some(dungeon.generate(&grid_size));
+    }
+    if !open {
+        generator.0 = None;
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, fmt::Write, path::PathBuf};
+
+use bevy::{asset::AssetPath, prelude::*};
+
+use crate::{
+    assets::{AtlasDefinition, Tile, TileAnchor, TileAnimation, TileDefinition, TileRenderSize},
+    grid::{GridSize, MapMode, Orientation},
+    hex::{StaggerAxis, StaggerIndex},
+    map::{MapData, CHUNK_SIZE},
+    project::{asset_file, ProjectRoots},
+    MapSettings, TilesData,
+};
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportMapEvent>()
+            .add_systems(Update, export_map);
+    }
+}
+
+/// Write the current map as a Tiled `.tmx` file.
+#[derive(Event)]
+pub struct ExportMapEvent(pub PathBuf);
+
+fn export_map(
+    mut reader: EventReader<ExportMapEvent>,
+    grid_size: Res<GridSize>,
+    map: Res<MapData>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    settings: Res<MapSettings>,
+    roots: Res<ProjectRoots>,
+) {
+    for event in reader.read() {
+        let tiles: Vec<_> = tile_handle.tiles(&tile_assets).cloned().collect();
+        let tmx = to_tmx(
+            &grid_size,
+            &map,
+            &tiles,
+            &settings.atlas_definitions,
+            &roots,
+        );
+        match std::fs::write(&event.0, tmx) {
+            Ok(()) => info!("Exported map to {}", event.0.display()),
+            Err(e) => error!("Could not export map: {e}"),
+        }
+    }
+}
+
+/// Tiled counts rows downwards, while orthogonal maps in the editor count them
+/// upwards. Isometric maps already share Tiled's axes. The mapping is its own
+/// inverse.
+fn from_tiled(grid_size: &GridSize, tiled: IVec2) -> IVec2 {
+    match (grid_size.orientation, grid_size.mode) {
+        (Orientation::Orthogonal, MapMode::Bounded) => {
+            IVec2::new(tiled.x, grid_size.rows as i32 - 1 - tiled.y)
+        }
+        (Orientation::Orthogonal, MapMode::Infinite) => IVec2::new(tiled.x, -1 - tiled.y),
+        (Orientation::Isometric | Orientation::Hexagonal, _) => tiled,
+    }
+}
+
+fn object_alignment(anchor: TileAnchor) -> &'static str {
+    match anchor {
+        TileAnchor::Unspecified => "unspecified",
+        TileAnchor::TopLeft => "topleft",
+        TileAnchor::Top => "top",
+        TileAnchor::TopRight => "topright",
+        TileAnchor::Left => "left",
+        TileAnchor::Center => "center",
+        TileAnchor::Right => "right",
+        TileAnchor::BottomLeft => "bottomleft",
+        TileAnchor::Bottom => "bottom",
+        TileAnchor::BottomRight => "bottomright",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Global tile ids for each tileset, keyed by the tileset's image path.
+struct Gids<'a>(Vec<(&'a Tile, &'a AtlasDefinition, usize)>);
+
+impl<'a> Gids<'a> {
+    /// Only tilesets with a built atlas are exported.
+    fn new(tiles: &'a [Tile], definitions: &'a HashMap<String, AtlasDefinition>) -> Self {
+        let mut next = 1;
+        let mut gids = Vec::new();
+        for tile in tiles {
+            if let Some(atlas) = definitions.get(&tile.path) {
+                gids.push((tile, atlas, next));
+                next += atlas.columns * atlas.rows;
+            }
+        }
+        Self(gids)
+    }
+
+    fn get(&self, tileset: &str, index: usize) -> usize {
+        self.0
+            .iter()
+            .find(|(tile, _, _)| tile.path == tileset)
+            .map_or(0, |(_, _, first)| first + index)
+    }
+}
+
+/// Writes the map with a tileset for every entry of `definitions`, which are
+/// the atlases as built from `tiles`.
+pub fn to_tmx(
+    grid_size: &GridSize,
+    map: &MapData,
+    tiles: &[Tile],
+    definitions: &HashMap<String, AtlasDefinition>,
+    roots: &ProjectRoots,
+) -> String {
+    let gids = Gids::new(tiles, definitions);
+    let extent = grid_size.cell_size;
+    let orientation = match grid_size.orientation {
+        Orientation::Orthogonal => "orthogonal",
+        Orientation::Isometric => "isometric",
+        Orientation::Hexagonal => "hexagonal",
+    };
+    let hex = match grid_size.orientation {
+        Orientation::Hexagonal => format!(
+            r#" hexsidelength="{}" staggeraxis="{}" staggerindex="{}""#,
+            grid_size.hex.side_length.round(),
+            match grid_size.hex.stagger_axis {
+                StaggerAxis::X => "x",
+                StaggerAxis::Y => "y",
+            },
+            match grid_size.hex.stagger_index {
+                StaggerIndex::Odd => "odd",
+                StaggerIndex::Even => "even",
+            },
+        ),
+        _ => String::new(),
+    };
+    let infinite = grid_size.mode == MapMode::Infinite;
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        out,
+        r#"<map version="1.10" orientation="{orientation}" renderorder="right-down" width="{}" height="{}" tilewidth="{}" tileheight="{}"{hex} infinite="{}">"#,
+        grid_size.cols,
+        grid_size.rows,
+        extent.x.round(),
+        extent.y.round(),
+        infinite as u8,
+    )
+    .unwrap();
+
+    let source = |path: &str| {
+        let file = asset_file(roots, &AssetPath::parse(path)).unwrap_or_else(|| path.into());
+        escape(&file.to_string_lossy())
+    };
+    for (tile, atlas, first_gid) in &gids.0 {
+        let alignment = object_alignment(atlas.anchor);
+        let render_size = match atlas.render_size {
+            TileRenderSize::Grid => "grid",
+            TileRenderSize::Tile => "tile",
+        };
+        if let Some(collection) = &tile.images {
+            // Tiled reads the size of each image itself.
+            writeln!(
+                out,
+                r#" <tileset firstgid="{first_gid}" name="{}" tilewidth="{}" tileheight="{}" tilecount="{}" columns="0" objectalignment="{alignment}" tilerendersize="{render_size}" fillmode="preserve-aspect-fit">"#,
+                escape(&tile.name),
+                atlas.tile_size.x,
+                atlas.tile_size.y,
+                collection.files.len(),
+            )
+            .unwrap();
+            writeln!(
+                out,
+                r#"  <grid orientation="orthogonal" width="1" height="1"/>"#
+            )
+            .unwrap();
+            for (id, path) in collection.paths(&tile.path).enumerate() {
+                writeln!(out, r#"  <tile id="{id}">"#).unwrap();
+                writeln!(out, r#"   <image source="{}"/>"#, source(&path)).unwrap();
+                if let Some(animation) = atlas.animation(id) {
+                    write_animation(&mut out, animation);
+                }
+                writeln!(out, "  </tile>").unwrap();
+            }
+            writeln!(out, " </tileset>").unwrap();
+            continue;
+        }
+        let spacing = atlas.padding.unwrap_or_default();
+        let margin = atlas.offsest.unwrap_or_default();
+        let image = margin
+            + atlas.tile_size * Vec2::new(atlas.columns as f32, atlas.rows as f32)
+            + spacing * Vec2::new(atlas.columns as f32 - 1., atlas.rows as f32 - 1.);
+        writeln!(
+            out,
+            r#" <tileset firstgid="{first_gid}" name="{}" tilewidth="{}" tileheight="{}" spacing="{}" margin="{}" tilecount="{}" columns="{}" objectalignment="{alignment}" tilerendersize="{render_size}" fillmode="preserve-aspect-fit">"#,
+            escape(&tile.name),
+            atlas.tile_size.x,
+            atlas.tile_size.y,
+            spacing.x,
+            margin.x,
+            atlas.columns * atlas.rows,
+            atlas.columns,
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#"  <image source="{}" width="{}" height="{}"/>"#,
+            source(&tile.path),
+            image.x,
+            image.y,
+        )
+        .unwrap();
+        for animation in &atlas.animations {
+            writeln!(out, r#"  <tile id="{}">"#, animation.index).unwrap();
+            write_animation(&mut out, animation);
+            writeln!(out, "  </tile>").unwrap();
+        }
+        writeln!(out, " </tileset>").unwrap();
+    }
+
+    for (id, layer) in map.layers().iter().enumerate() {
+        let gid_at = |tiled: IVec2| {
+            layer.get(from_tiled(grid_size, tiled)).map_or(0, |tile| {
+                match gids.get(&tile.tileset, tile.index) {
+                    0 => 0,
+                    gid => gid as u32 | tile.transform.tiled_flags(),
+                }
+            })
+        };
+        writeln!(
+            out,
+            r#" <layer id="{}" name="{}" width="{}" height="{}" visible="{}">"#,
+            id + 1,
+            escape(&layer.name),
+            grid_size.cols,
+            grid_size.rows,
+            layer.visible as u8,
+        )
+        .unwrap();
+        writeln!(out, r#"  <data encoding="csv">"#).unwrap();
+        if infinite {
+            let mut coords: Vec<_> = layer.chunks().map(|(coord, _)| *coord).collect();
+            coords.sort_by_key(|coord| (coord.y, coord.x));
+            for coord in coords {
+                // Every editor chunk lines up with exactly one Tiled chunk.
+                let first = coord * CHUNK_SIZE;
+                let start =
+                    from_tiled(grid_size, first).min(from_tiled(grid_size, first + CHUNK_SIZE - 1));
+                writeln!(
+                    out,
+                    r#"   <chunk x="{}" y="{}" width="{CHUNK_SIZE}" height="{CHUNK_SIZE}">"#,
+                    start.x, start.y
+                )
+                .unwrap();
+                write_csv(&mut out, start, CHUNK_SIZE, CHUNK_SIZE, &gid_at);
+                writeln!(out, "   </chunk>").unwrap();
+            }
+        } else {
+            write_csv(
+                &mut out,
+                IVec2::ZERO,
+                grid_size.cols as i32,
+                grid_size.rows as i32,
+                &gid_at,
+            );
+        }
+        writeln!(out, "  </data>").unwrap();
+        writeln!(out, " </layer>").unwrap();
+    }
+    writeln!(out, "</map>").unwrap();
+    out
+}
+
+fn write_animation(out: &mut String, animation: &TileAnimation) {
+    writeln!(out, "   <animation>").unwrap();
+    for frame in &animation.frames {
+        writeln!(
+            out,
+            r#"    <frame tileid="{}" duration="{}"/>"#,
+            frame.index, frame.duration
+        )
+        .unwrap();
+    }
+    writeln!(out, "   </animation>").unwrap();
+}
+
+fn write_csv(
+    out: &mut String,
+    start: IVec2,
+    width: i32,
+    height: i32,
+    gid_at: &impl Fn(IVec2) -> u32,
+) {
+    let rows: Vec<String> = (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| gid_at(start + IVec2::new(x, y)).to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect();
+    writeln!(out, "{}", rows.join(",\n")).unwrap();
+}
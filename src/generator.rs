@@ -0,0 +1,181 @@
+//! Shared by the map generators: generated cells are drawn over the map as a
+//! preview, and only written to the active layer once applied, as a single
+//! undo step.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    grid::{tile_sprite, GridSize, MapMode},
+    history::History,
+    map::{MapData, PaintedTile},
+    AppState, MapSettings,
+};
+
+/// Size generated on infinite maps, which have no bounds of their own.
+pub const DEFAULT_SIZE: UVec2 = UVec2::splat(64);
+
+/// Previews are drawn above every layer.
+const PREVIEW_Z: f32 = 300.;
+
+pub struct GeneratorPlugin;
+
+impl Plugin for GeneratorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GeneratedPreview>().add_systems(
+            Update,
+            (preview_ui, draw_preview, draw_marked)
+                .chain()
+                .run_if(in_state(AppState::Painting)),
+        );
+    }
+}
+
+/// Generated cells, and cells the generator wants to point out, e.g. where
+/// it failed.
+pub struct Generated {
+    /// The generator that made it, shown in the preview window.
+    pub name: String,
+    /// Cells to write; `None` erases the cell.
+    pub cells: Vec<(IVec2, Option<PaintedTile>)>,
+    /// Cells outlined in red over the preview.
+    pub marked: Vec<IVec2>,
+    /// Shown below the name, e.g. why cells are marked.
+    pub message: Option<String>,
+}
+
+impl Generated {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            cells: Vec::new(),
+            marked: Vec::new(),
+            message: None,
+        }
+    }
+}
+
+/// The generated cells waiting to be applied, if any.
+#[derive(Resource, Default)]
+pub struct GeneratedPreview(pub Option<Generated>);
+
+/// The block of cells to generate: the whole of a bounded map, or `size`
+/// cells from the origin of an infinite one.
+pub fn region(grid_size: &GridSize, size: UVec2) -> (IVec2, UVec2) {
+    match grid_size.mode {
+        MapMode::Bounded => (
+            IVec2::ZERO,
+            UVec2::new(grid_size.cols as u32, grid_size.rows as u32),
+        ),
+        MapMode::Infinite => (IVec2::ZERO, size),
+    }
+}
+
+/// Edits the generated size on infinite maps.
+pub fn size_ui(ui: &mut egui::Ui, grid_size: &GridSize, size: &mut UVec2) {
+    if grid_size.mode != MapMode::Infinite {
+        return;
+    }
+    ui.horizontal(|hui| {
+        hui.label("Size");
+        hui.add(egui::DragValue::new(&mut size.x).clamp_range(1..=1024));
+        hui.label("x");
+        hui.add(egui::DragValue::new(&mut size.y).clamp_range(1..=1024));
+    });
+}
+
+/// Writes generated cells to the active layer.
+pub fn apply(generated: &Generated, grid_size: &GridSize, map: &mut MapData) {
+    for (cell, tile) in &generated.cells {
+        if grid_size.contains(*cell) && map.get(*cell) != tile.as_ref() {
+            map.set(*cell, tile.clone());
+        }
+    }
+}
+
+fn preview_ui(
+    mut contexts: EguiContexts,
+    mut preview: ResMut<GeneratedPreview>,
+    grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+    mut history: ResMut<History>,
+) {
+    let Some(generated) = &preview.0 else {
+        return;
+    };
+    let mut apply_preview = false;
+    let mut discard = false;
+    egui::Window::new("Generated Map").show(contexts.ctx_mut(), |ui| {
+        ui.label(format!(
+            "{}: {} cells",
+            generated.name,
+            generated.cells.len()
+        ));
+        if let Some(message) = &generated.message {
+            let color = if generated.marked.is_empty() {
+                ui.visuals().text_color()
+            } else {
+                egui::Color32::LIGHT_RED
+            };
+            ui.colored_label(color, message);
+        }
+        ui.horizontal(|hui| {
+            apply_preview = hui.button("Apply").clicked();
+            discard = hui.button("Discard").clicked();
+        });
+    });
+    if apply_preview {
+        history.commit(&mut map);
+        apply(generated, &grid_size, &mut map);
+        history.commit(&mut map);
+    }
+    if apply_preview || discard {
+        preview.0 = None;
+    }
+}
+
+#[derive(Component)]
+struct PreviewTile;
+
+fn draw_preview(
+    mut commands: Commands,
+    sprites: Query<Entity, With<PreviewTile>>,
+    preview: Res<GeneratedPreview>,
+    grid_size: Res<GridSize>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+) {
+    if !preview.is_changed() && !settings.is_changed() && !grid_size.is_changed() {
+        return;
+    }
+    for sprite in &sprites {
+        commands.entity(sprite).despawn();
+    }
+    let Some(generated) = &preview.0 else {
+        return;
+    };
+    for (cell, tile) in &generated.cells {
+        let Some(tile) = tile else {
+            continue;
+        };
+        let cell = *cell;
+        if !grid_size.contains(cell) {
+            continue;
+        }
+        let z = PREVIEW_Z + grid_size.cell_depth(cell);
+        if let Some(bundle) = tile_sprite(&grid_size, &settings, &texture_atlases, cell, tile, z) {
+            commands.spawn((PreviewTile, bundle));
+        }
+    }
+}
+
+fn draw_marked(mut gizmos: Gizmos, preview: Res<GeneratedPreview>, grid_size: Res<GridSize>) {
+    let Some(generated) = &preview.0 else {
+        return;
+    };
+    for cell in &generated.marked {
+        for outline in grid_size.outline(*cell, *cell) {
+            gizmos.linestrip_2d(outline, Color::RED);
+        }
+    }
+}
@@ -1,9 +1,27 @@
+use std::collections::HashSet;
+
 use bevy::{
-    input::common_conditions::input_just_pressed, prelude::*, sprite::MaterialMesh2dBundle,
+    input::mouse::{MouseMotion, MouseWheel},
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::{Anchor, MaterialMesh2dBundle},
     window::PrimaryWindow,
 };
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+pub use tile_editor::layout::rect;
 
-use crate::{assets::TileDefinition, AppState, MainCamera, TilesData};
+use crate::{
+    assets::{AtlasDefinition, TerrainSet, TileAnchor, TileAnimation, TileRenderSize},
+    autotile,
+    hex::HexLayout,
+    history::History,
+    map::{MapData, PaintedTile, TileTransform, CHUNK_SIZE},
+    menus::ResizeEvent,
+    scatter::{ScatterBrush, ScatterRng},
+    stamp::StampBrush,
+    AppState, MainCamera, MapSettings,
+};
 
 pub struct GridPlugin;
 
@@ -14,18 +32,37 @@ impl Plugin for GridPlugin {
             .add_event::<ClickEvent>()
             .add_event::<CellInteractionEvent>()
             .init_resource::<GridSize>()
+            .init_resource::<CellAssets>()
+            .init_resource::<TerrainBrush>()
+            .init_resource::<BrushTransform>()
             .add_systems(OnEnter(AppState::Painting), spawn_grid)
             .add_systems(OnExit(AppState::Painting), despawn_grid)
             .add_systems(
                 Update,
-                (translate_coords, interact_cell).run_if(in_state(AppState::Painting)),
+                (
+                    resize_grid,
+                    pan_camera,
+                    translate_coords,
+                    interact_cell,
+                    render_chunks,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Painting)),
             )
-            .add_systems(Update, paint.run_if(in_state(ActionState::Paint)));
+            .add_systems(Update, animate_tiles.run_if(in_state(AppState::Painting)))
+            .add_systems(Update, transform_brush)
+            .add_systems(
+                Update,
+                paint
+                    .after(interact_cell)
+                    .before(render_chunks)
+                    .run_if(in_state(ActionState::Paint)),
+            );
     }
 }
 
 #[derive(States, Default, Debug, Hash, PartialEq, Eq, Clone)]
-enum ActionState {
+pub enum ActionState {
     #[default]
     Select,
     Paint,
@@ -36,29 +73,415 @@ pub enum BrushState {
     #[default]
     Single,
     Fill,
+    Line,
+    Rect,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MapMode {
+    /// A fixed `cols x rows` map centered on the origin.
+    #[default]
+    Bounded,
+    /// An unbounded map where only painted chunks are stored.
+    Infinite,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    Orthogonal,
+    /// Diamond cells, twice as wide as they are tall, with the x axis running
+    /// down-right and the y axis down-left as in Tiled.
+    Isometric,
+    /// Staggered hexagons laid out by `GridSize.hex`, rows counting downwards.
+    Hexagonal,
 }
 
 #[derive(Resource)]
-struct GridSize {
-    cols: usize,
-    rows: usize,
-    tile_size: f32,
+pub struct GridSize {
+    pub mode: MapMode,
+    pub orientation: Orientation,
+    pub hex: HexLayout,
+    pub cols: usize,
+    pub rows: usize,
+    /// Size of a map cell in pixels; independent of the tilesets' frame sizes.
+    pub cell_size: Vec2,
 }
 
 impl Default for GridSize {
     fn default() -> Self {
         Self {
+            mode: MapMode::default(),
+            orientation: Orientation::default(),
+            hex: HexLayout::default(),
             cols: 6,
             rows: 4,
-            tile_size: 64.,
+            cell_size: Vec2::splat(64.),
+        }
+    }
+}
+
+impl GridSize {
+    /// Cell coordinate placed at the world origin.
+    fn center_cell(&self) -> Vec2 {
+        match self.mode {
+            MapMode::Bounded => Vec2::new(self.cols as f32, self.rows as f32) / 2.,
+            MapMode::Infinite => Vec2::ZERO,
+        }
+    }
+
+    /// World position of the center of hex cell `(0, 0)`.
+    fn hex_origin(&self) -> Vec2 {
+        match self.mode {
+            MapMode::Bounded => {
+                let last = IVec2::new(self.cols as i32 - 1, self.rows as i32 - 1);
+                -self.hex.center(last, self.cell_size) / 2.
+            }
+            MapMode::Infinite => Vec2::ZERO,
+        }
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        match self.mode {
+            MapMode::Bounded => {
+                cell.x >= 0
+                    && cell.y >= 0
+                    && (cell.x as usize) < self.cols
+                    && (cell.y as usize) < self.rows
+            }
+            MapMode::Infinite => true,
+        }
+    }
+
+    /// The corners of the part of a rectangle inside a bounded map, or `None`
+    /// when it misses the map.
+    pub fn clip(&self, from: IVec2, to: IVec2) -> Option<(IVec2, IVec2)> {
+        let (mut min, mut max) = (from.min(to), from.max(to));
+        if self.mode == MapMode::Bounded {
+            min = min.max(IVec2::ZERO);
+            max = max.min(IVec2::new(self.cols as i32, self.rows as i32) - 1);
+        }
+        min.cmple(max).all().then_some((min, max))
+    }
+
+    pub fn cell_center(&self, cell: IVec2) -> Vec2 {
+        let c = cell.as_vec2() + 0.5 - self.center_cell();
+        let half = self.cell_size / 2.;
+        match self.orientation {
+            Orientation::Orthogonal => c * self.cell_size,
+            Orientation::Isometric => Vec2::new((c.x - c.y) * half.x, -(c.x + c.y) * half.y),
+            Orientation::Hexagonal => self.hex_origin() + self.hex.center(cell, self.cell_size),
+        }
+    }
+
+    /// Continuous cell coordinates of a world position; the containing cell is
+    /// its floor, except for hexagons where it is only approximate.
+    fn cell_position(&self, pos: Vec2) -> Vec2 {
+        let half = self.cell_size / 2.;
+        match self.orientation {
+            Orientation::Orthogonal => pos / self.cell_size + self.center_cell(),
+            Orientation::Isometric => {
+                let a = pos.x / half.x;
+                let b = -pos.y / half.y;
+                Vec2::new(a + b, b - a) / 2. + self.center_cell()
+            }
+            Orientation::Hexagonal => {
+                self.hex
+                    .approximate_cell(pos - self.hex_origin(), self.cell_size)
+                    + 0.5
+            }
+        }
+    }
+
+    /// World position of continuous cell coordinates on orthogonal and
+    /// isometric maps, the inverse of `cell_position`.
+    fn cell_point(&self, cell: Vec2) -> Vec2 {
+        let c = cell - self.center_cell();
+        let half = self.cell_size / 2.;
+        match self.orientation {
+            Orientation::Isometric => Vec2::new((c.x - c.y) * half.x, -(c.x + c.y) * half.y),
+            _ => c * self.cell_size,
+        }
+    }
+
+    /// Closed outlines around an inclusive range of cells: one around the
+    /// whole range, or one per cell on hexagonal maps.
+    pub fn outline(&self, min: IVec2, max: IVec2) -> Vec<Vec<Vec2>> {
+        match self.orientation {
+            Orientation::Hexagonal => (min.y..=max.y)
+                .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+                .map(|cell| {
+                    let center = self.cell_center(cell);
+                    let corners = self.hex.corners(self.cell_size);
+                    corners
+                        .iter()
+                        .chain(corners.first())
+                        .map(|corner| center + *corner)
+                        .collect()
+                })
+                .collect(),
+            _ => {
+                let (a, b) = (min.as_vec2(), (max + 1).as_vec2());
+                let corners = [a, Vec2::new(b.x, a.y), b, Vec2::new(a.x, b.y), a];
+                vec![corners.map(|p| self.cell_point(p)).to_vec()]
+            }
+        }
+    }
+
+    pub fn world_to_cell(&self, pos: Vec2) -> Option<IVec2> {
+        let cell = match self.orientation {
+            Orientation::Hexagonal => self.hex.pick(pos - self.hex_origin(), self.cell_size),
+            _ => self.cell_position(pos).floor().as_ivec2(),
+        };
+        self.contains(cell).then_some(cell)
+    }
+
+    /// Offset to the row drawn below a cell: `-y` on orthogonal maps and `+y`
+    /// on isometric and hexagonal ones.
+    pub fn down(&self) -> IVec2 {
+        match self.orientation {
+            Orientation::Orthogonal => IVec2::NEG_Y,
+            Orientation::Isometric | Orientation::Hexagonal => IVec2::Y,
+        }
+    }
+
+    /// Offset added to a painted tile's z so that cells further down the
+    /// screen draw over the ones behind them.
+    pub fn cell_depth(&self, cell: IVec2) -> f32 {
+        match self.orientation {
+            Orientation::Orthogonal => -cell.y as f32 * 1e-5,
+            Orientation::Isometric => (cell.x + cell.y) as f32 * 1e-5,
+            Orientation::Hexagonal => -self.hex.center(cell, Vec2::ONE).y * 1e-5,
+        }
+    }
+
+    /// Where and how large to draw a tile from the given atlas in `cell`. The
+    /// frame's anchor point is pinned to the same point of the cell's bounding
+    /// box, so frames larger than a cell overhang it. `frame` is the size of
+    /// the frame in the atlas, which varies within image collections.
+    pub fn place_tile(
+        &self,
+        cell: IVec2,
+        definition: Option<&AtlasDefinition>,
+        frame: Option<Vec2>,
+    ) -> TilePlacement {
+        let frame = frame.unwrap_or_else(|| definition.map_or(self.cell_size, |d| d.tile_size));
+        let size = match definition.map_or(TileRenderSize::default(), |d| d.render_size) {
+            TileRenderSize::Grid => frame * (self.cell_size / frame).min_element(),
+            TileRenderSize::Tile => frame,
+        };
+        let anchor = definition
+            .map_or(TileAnchor::default(), |d| d.anchor)
+            .offset(self.orientation == Orientation::Isometric);
+        TilePlacement {
+            position: self.cell_center(cell) + anchor * self.cell_size,
+            size,
+            anchor,
+        }
+    }
+
+    /// Cells sharing an edge with `cell`.
+    pub fn neighbors(&self, cell: IVec2) -> Vec<IVec2> {
+        match self.orientation {
+            Orientation::Orthogonal | Orientation::Isometric => {
+                vec![
+                    cell + IVec2::X,
+                    cell - IVec2::X,
+                    cell + IVec2::Y,
+                    cell - IVec2::Y,
+                ]
+            }
+            Orientation::Hexagonal => self.hex.neighbors(cell).to_vec(),
+        }
+    }
+
+    /// Cells on the straight line between two cells, inclusive.
+    pub fn line(&self, from: IVec2, to: IVec2) -> Vec<IVec2> {
+        match self.orientation {
+            Orientation::Orthogonal | Orientation::Isometric => bresenham(from, to),
+            Orientation::Hexagonal => self.hex.line(from, to),
+        }
+    }
+
+    /// Inclusive range of chunks overlapping the given world-space area,
+    /// clipped to the map bounds.
+    fn chunks_in(&self, area: Rect) -> Option<(IVec2, IVec2)> {
+        let corners = [
+            area.min,
+            area.max,
+            Vec2::new(area.min.x, area.max.y),
+            Vec2::new(area.max.x, area.min.y),
+        ]
+        .map(|corner| self.cell_position(corner));
+        // Pad by a cell to cover hexes, whose positions are approximate.
+        let cell_min = corners.into_iter().reduce(Vec2::min).unwrap() - 1.;
+        let cell_max = corners.into_iter().reduce(Vec2::max).unwrap() + 1.;
+        let chunk_size = IVec2::splat(CHUNK_SIZE);
+        let mut min = cell_min.floor().as_ivec2().div_euclid(chunk_size);
+        let mut max = cell_max.floor().as_ivec2().div_euclid(chunk_size);
+        if self.mode == MapMode::Bounded {
+            if self.cols == 0 || self.rows == 0 {
+                return None;
+            }
+            let last =
+                IVec2::new(self.cols as i32 - 1, self.rows as i32 - 1).div_euclid(chunk_size);
+            min = min.max(IVec2::ZERO);
+            max = max.min(last);
+        }
+        min.cmple(max).all().then_some((min, max))
+    }
+}
+
+pub struct TilePlacement {
+    pub position: Vec2,
+    pub size: Vec2,
+    /// Sprite anchor, from `(-0.5, -0.5)` at the bottom left to `(0.5, 0.5)`.
+    pub anchor: Vec2,
+}
+
+/// Most cells a rectangle typed into the console or a script may cover, as
+/// [`rect`] lists every one of them.
+pub const MAX_RECT_CELLS: u64 = 1 << 22;
+
+/// How many cells [`rect`] would list, without listing them.
+pub fn rect_len(from: IVec2, to: IVec2) -> u64 {
+    let size = (to.as_i64vec2() - from.as_i64vec2()).abs() + 1;
+    size.x as u64 * size.y as u64
+}
+
+fn bresenham(from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let d = (to - from).abs();
+    let step = (to - from).signum();
+    let mut error = d.x - d.y;
+    let mut cell = from;
+    let mut cells = vec![cell];
+    while cell != to {
+        let e2 = error * 2;
+        if e2 > -d.y {
+            error -= d.y;
+            cell.x += step.x;
+        }
+        if e2 < d.x {
+            error += d.x;
+            cell.y += step.y;
+        }
+        cells.push(cell);
+    }
+    cells
+}
+
+/// The contiguous region of cells painted like `start`, within the map. In
+/// infinite maps the region is limited to the area already holding chunks.
+fn flood_fill(grid_size: &GridSize, map: &MapData, start: IVec2) -> Vec<IVec2> {
+    let target = map.get(start).cloned();
+    contiguous(grid_size, map, start, |tile| tile == target.as_ref())
+}
+
+/// The contiguous region of cells around `start` whose tiles `same` accepts,
+/// limited like `flood_fill`.
+pub fn contiguous(
+    grid_size: &GridSize,
+    map: &MapData,
+    start: IVec2,
+    same: impl Fn(Option<&PaintedTile>) -> bool,
+) -> Vec<IVec2> {
+    let (min, max) = match map.bounds() {
+        Some((min, max)) => (
+            min.min(MapData::chunk_coord(start) * CHUNK_SIZE),
+            max.max((MapData::chunk_coord(start) + 1) * CHUNK_SIZE - 1),
+        ),
+        None => (
+            MapData::chunk_coord(start) * CHUNK_SIZE,
+            (MapData::chunk_coord(start) + 1) * CHUNK_SIZE - 1,
+        ),
+    };
+    let within = |cell: IVec2| {
+        grid_size.contains(cell)
+            && (grid_size.mode == MapMode::Bounded || (cell.cmpge(min) & cell.cmple(max)).all())
+    };
+    let mut seen = HashSet::from([start]);
+    let mut stack = vec![start];
+    let mut cells = Vec::new();
+    while let Some(cell) = stack.pop() {
+        cells.push(cell);
+        for next in grid_size.neighbors(cell) {
+            if within(next) && same(map.get(next)) && seen.insert(next) {
+                stack.push(next);
+            }
         }
     }
+    cells
 }
 
-#[derive(Bundle)]
-struct CellBundle {
-    cell: Cell,
-    transformation: TransformBundle,
+#[derive(Resource)]
+struct CellAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<ColorMaterial>,
+    shape: (Orientation, Vec2, HexLayout),
+}
+
+impl CellAssets {
+    fn shape(grid_size: &GridSize) -> (Orientation, Vec2, HexLayout) {
+        (grid_size.orientation, grid_size.cell_size, grid_size.hex)
+    }
+
+    fn placeholder_mesh(grid_size: &GridSize) -> Mesh {
+        // Leave a pixel between cells so the grid lines show.
+        let size = grid_size.cell_size - 1.0;
+        let half = size / 2.;
+        match grid_size.orientation {
+            Orientation::Orthogonal => polygon_mesh(&[
+                Vec2::new(half.x, half.y),
+                Vec2::new(-half.x, half.y),
+                Vec2::new(-half.x, -half.y),
+                Vec2::new(half.x, -half.y),
+            ]),
+            Orientation::Isometric => polygon_mesh(&[
+                Vec2::new(0., half.y),
+                Vec2::new(-half.x, 0.),
+                Vec2::new(0., -half.y),
+                Vec2::new(half.x, 0.),
+            ]),
+            Orientation::Hexagonal => polygon_mesh(&grid_size.hex.corners(size)),
+        }
+    }
+}
+
+/// Triangle fan over a convex polygon centered on the origin.
+fn polygon_mesh(corners: &[Vec2]) -> Mesh {
+    let positions: Vec<[f32; 3]> = std::iter::once(Vec2::ZERO)
+        .chain(corners.iter().copied())
+        .map(|p| [p.x, p.y, 0.])
+        .collect();
+    let normals = vec![[0., 0., 1.]; positions.len()];
+    let uvs: Vec<[f32; 2]> = positions.iter().map(|p| [p[0], -p[1]]).collect();
+    let count = corners.len() as u32;
+    let indices = (0..count)
+        .flat_map(|i| [0, i + 1, (i + 1) % count + 1])
+        .collect();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+impl FromWorld for CellAssets {
+    fn from_world(world: &mut World) -> Self {
+        let grid_size = world.resource::<GridSize>();
+        let shape = Self::shape(grid_size);
+        let mesh = Self::placeholder_mesh(grid_size);
+        let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+        let material = world
+            .resource_mut::<Assets<ColorMaterial>>()
+            .add(ColorMaterial::from(Color::PURPLE));
+        Self {
+            mesh,
+            material,
+            shape,
+        }
+    }
 }
 
 #[derive(Component)]
@@ -67,66 +490,326 @@ struct Cell;
 #[derive(Component)]
 struct Grid;
 
+/// A spawned, visible chunk of cells.
+#[derive(Component)]
+struct ChunkView(IVec2);
+
 #[derive(Component)]
-struct Brush {
-    index: usize,
-    atlas: Handle<TextureAtlas>,
+pub struct Brush {
+    pub tileset: String,
+    pub index: usize,
 }
 
 #[derive(Component)]
-struct SelectedBrush;
+pub struct SelectedBrush;
+
+/// A sprite or palette image cycling through the frames of an animated tile.
+#[derive(Component)]
+pub struct AnimatedTile(pub TileAnimation);
+
+/// Terrain painted instead of the selected brush, with the tileset it uses.
+#[derive(Resource, Default)]
+pub struct TerrainBrush(pub Option<(String, TerrainSet)>);
+
+/// Flips and rotation given to tiles painted with the selected brush.
+#[derive(Resource, Default)]
+pub struct BrushTransform(pub TileTransform);
+
+fn spawn_grid(mut commands: Commands) {
+    commands.spawn((Grid, SpatialBundle::default()));
+}
+
+fn despawn_grid(mut commands: Commands, mut query: Query<Entity, With<Grid>>) {
+    let e = query.single_mut();
+    commands.get_entity(e).unwrap().despawn_recursive();
+}
+
+/// Sets the columns and rows of the map as one undo step, moving every tile
+/// by `offset` so the map can grow or shrink around any point.
+pub fn resize_map(
+    grid_size: &mut GridSize,
+    map: &mut MapData,
+    history: &mut History,
+    size: UVec2,
+    offset: IVec2,
+) {
+    history.commit(map);
+    map.shift(offset);
+    let before = UVec2::new(grid_size.cols as u32, grid_size.rows as u32);
+    if before != size {
+        history.record_resize(before, size);
+        grid_size.cols = size.x as usize;
+        grid_size.rows = size.y as usize;
+    }
+    history.commit(map);
+}
+
+fn resize_grid(
+    mut reader: EventReader<ResizeEvent>,
+    mut grid_size: ResMut<GridSize>,
+    mut map: ResMut<MapData>,
+    mut history: ResMut<History>,
+) {
+    for event in reader.read() {
+        grid_size.mode = event.mode;
+        grid_size.orientation = event.orientation;
+        grid_size.hex = event.hex;
+        grid_size.cell_size = event.cell_size;
+        let size = UVec2::new(event.grid_width, event.grid_height);
+        if event.clear {
+            grid_size.cols = size.x as usize;
+            grid_size.rows = size.y as usize;
+            map.clear();
+        } else {
+            resize_map(&mut grid_size, &mut map, &mut history, size, IVec2::ZERO);
+        }
+    }
+}
+
+fn pan_camera(
+    mut contexts: EguiContexts,
+    buttons: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    let (mut transform, mut projection) = camera.single_mut();
+    let over_ui = contexts.ctx_mut().is_pointer_over_area();
+    for event in motion.read() {
+        if buttons.pressed(MouseButton::Middle) {
+            transform.translation.x -= event.delta.x * projection.scale;
+            transform.translation.y += event.delta.y * projection.scale;
+        }
+    }
+    for event in wheel.read() {
+        if !over_ui {
+            projection.scale = (projection.scale * (1. - event.y * 0.1)).clamp(0.1, 10.);
+        }
+    }
+}
 
-fn spawn_grid(
+/// Keeps a `ChunkView` spawned for every chunk in the camera's view, rebuilding
+/// chunks whose cells changed and despawning the ones that scrolled away.
+fn render_chunks(
     mut commands: Commands,
+    grid: Query<Entity, With<Grid>>,
+    views: Query<(Entity, &ChunkView)>,
+    camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
     grid_size: Res<GridSize>,
-
+    mut map: ResMut<MapData>,
+    mut cell_assets: ResMut<CellAssets>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
 ) {
+    let Ok(root) = grid.get_single() else {
+        return;
+    };
+    let (cam, cam_transform) = camera.single();
+    let Some(area) = cam.logical_viewport_size().and_then(|size| {
+        let a = cam.viewport_to_world_2d(cam_transform, Vec2::ZERO)?;
+        let b = cam.viewport_to_world_2d(cam_transform, size)?;
+        Some(Rect::from_corners(a, b))
+    }) else {
+        return;
+    };
+
+    let shape = CellAssets::shape(&grid_size);
+    if cell_assets.shape != shape {
+        cell_assets.shape = shape;
+        cell_assets.mesh = meshes.add(CellAssets::placeholder_mesh(&grid_size));
+    }
+
+    let visible = grid_size.chunks_in(area);
+    let in_view = |coord: IVec2| {
+        visible.is_some_and(|(min, max)| (coord.cmpge(min) & coord.cmple(max)).all())
+    };
+    let dirty = map.take_dirty();
+    let mut shown = HashSet::new();
+    for (entity, view) in &views {
+        if grid_size.is_changed() || dirty.contains(&view.0) || !in_view(view.0) {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            shown.insert(view.0);
+        }
+    }
+
+    let Some((min, max)) = visible else {
+        return;
+    };
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let coord = IVec2::new(x, y);
+            if !shown.contains(&coord) {
+                let view = spawn_chunk(
+                    &mut commands,
+                    coord,
+                    &grid_size,
+                    &map,
+                    &cell_assets,
+                    &settings,
+                    &texture_atlases,
+                );
+                commands.entity(root).add_child(view);
+            }
+        }
+    }
+}
+
+fn spawn_chunk(
+    commands: &mut Commands,
+    coord: IVec2,
+    grid_size: &GridSize,
+    map: &MapData,
+    cell_assets: &CellAssets,
+    settings: &MapSettings,
+    texture_atlases: &Assets<TextureAtlas>,
+) -> Entity {
     commands
-        .spawn((Grid, SpatialBundle::default()))
+        .spawn((ChunkView(coord), SpatialBundle::default()))
         .with_children(|parent| {
-            let x_start = (grid_size.tile_size * grid_size.cols as f32) / 2.;
-            let y_start = (grid_size.tile_size * grid_size.rows as f32) / 2.;
-            for y in 0..grid_size.rows {
-                for x in 0..grid_size.cols {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let local = IVec2::new(x, y);
+                    let cell = coord * CHUNK_SIZE + local;
+                    if !grid_size.contains(cell) {
+                        continue;
+                    }
+                    let center = grid_size.cell_center(cell);
                     parent.spawn((
                         Cell,
                         MaterialMesh2dBundle {
-                            mesh: meshes
-                                .add(Mesh::from(shape::Quad::new(Vec2::splat(
-                                    grid_size.tile_size - 1.0,
-                                ))))
-                                .into(),
-                            transform: Transform::from_translation(Vec3::new(
-                                x_start + (x as f32 * grid_size.tile_size),
-                                y_start + (y as f32 * grid_size.tile_size),
-                                1.0,
-                            )),
-                            material: materials.add(ColorMaterial::from(Color::PURPLE)),
+                            mesh: cell_assets.mesh.clone().into(),
+                            transform: Transform::from_translation(center.extend(1.0)),
+                            material: cell_assets.material.clone(),
                             ..default()
                         },
                     ));
+                    for (depth, layer) in map.layers().iter().enumerate() {
+                        if !layer.visible {
+                            continue;
+                        }
+                        let painted = layer.chunk(coord).and_then(|c| c.get(local));
+                        let Some(tile) = painted else {
+                            continue;
+                        };
+                        // Each layer sits a whole unit above the one below it.
+                        let z = 10.0 + depth as f32 + grid_size.cell_depth(cell);
+                        let Some(bundle) =
+                            tile_sprite(grid_size, settings, texture_atlases, cell, tile, z)
+                        else {
+                            continue;
+                        };
+                        let mut sprite = parent.spawn(bundle);
+                        let definition = settings.atlas_definitions.get(&tile.tileset);
+                        if let Some(animation) = definition.and_then(|d| d.animation(tile.index)) {
+                            sprite.insert(AnimatedTile(animation.clone()));
+                        }
+                    }
                 }
             }
-        });
+        })
+        .id()
 }
 
-fn despawn_grid(mut commands: Commands, mut query: Query<Entity, With<Grid>>) {
-    let e = query.single_mut();
-    commands.get_entity(e).unwrap().despawn_recursive();
+/// A sprite drawing `tile` in `cell`, or `None` if its tileset is not loaded.
+pub fn tile_sprite(
+    grid_size: &GridSize,
+    settings: &MapSettings,
+    texture_atlases: &Assets<TextureAtlas>,
+    cell: IVec2,
+    tile: &PaintedTile,
+    z: f32,
+) -> Option<SpriteSheetBundle> {
+    let atlas = settings.atlases.get(&tile.tileset)?;
+    let definition = settings.atlas_definitions.get(&tile.tileset);
+    let frame = texture_atlases
+        .get(atlas)
+        .and_then(|a| a.textures.get(tile.index))
+        .map(Rect::size)
+        .or(definition.map(|d| d.tile_size));
+    let turned = tile.transform.rotated;
+    // A turned frame is placed by the size it takes up on screen.
+    let frame = frame.map(|f| if turned { f.yx() } else { f });
+    let placement = grid_size.place_tile(cell, definition, frame);
+    let (size, anchor, position) = if turned {
+        // Turn about the middle so the frame stays in place.
+        let middle = placement.position - placement.anchor * placement.size;
+        (placement.size.yx(), Anchor::Center, middle)
+    } else {
+        (
+            placement.size,
+            Anchor::Custom(placement.anchor),
+            placement.position,
+        )
+    };
+    Some(SpriteSheetBundle {
+        sprite: TextureAtlasSprite {
+            index: tile.index,
+            flip_x: tile.transform.flip_x,
+            flip_y: tile.transform.flip_y,
+            custom_size: Some(size),
+            anchor,
+            ..default()
+        },
+        texture_atlas: atlas.clone_weak(),
+        transform: Transform::from_translation(position.extend(z))
+            .with_rotation(tile.transform.rotation()),
+        ..default()
+    })
+}
+
+/// X and Y mirror the brush and Z turns it a quarter clockwise.
+fn transform_brush(
+    mut contexts: EguiContexts,
+    keys: Res<Input<KeyCode>>,
+    mut brush_transform: ResMut<BrushTransform>,
+) {
+    // Leave shortcuts such as Ctrl+X to the other tools.
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if ctrl || contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let transform = brush_transform.0;
+    if keys.just_pressed(KeyCode::X) {
+        brush_transform.0 = transform.flip_x();
+    }
+    if keys.just_pressed(KeyCode::Y) {
+        brush_transform.0 = transform.flip_y();
+    }
+    if keys.just_pressed(KeyCode::Z) {
+        brush_transform.0 = transform.rotate();
+    }
+}
+
+fn animate_tiles(time: Res<Time>, mut sprites: Query<(&AnimatedTile, &mut TextureAtlasSprite)>) {
+    for (animated, mut sprite) in &mut sprites {
+        let index = animated.0.frame_at(time.elapsed());
+        if sprite.index != index {
+            sprite.index = index;
+        }
+    }
 }
 
 #[derive(Event)]
 enum ClickEvent {
     LeftClick(Vec2),
     RightClick(Vec2),
+    /// The cursor is over a point with the button still held after a click.
+    LeftDrag(Vec2),
+    RightDrag(Vec2),
 }
 
 #[derive(Event)]
-struct CellInteractionEvent(Entity);
+enum CellInteractionEvent {
+    Paint(IVec2),
+    Erase(IVec2),
+    /// A held button moved onto another cell.
+    PaintDrag(IVec2),
+    EraseDrag(IVec2),
+}
 
-fn get_coords(window: &Window, camera: &Camera, transform: &GlobalTransform) -> Option<Vec2> {
+pub fn get_coords(window: &Window, camera: &Camera, transform: &GlobalTransform) -> Option<Vec2> {
     window
         .cursor_position()
         .and_then(|cursor| camera.viewport_to_world(transform, cursor))
@@ -134,87 +817,150 @@ fn get_coords(window: &Window, camera: &Camera, transform: &GlobalTransform) ->
 }
 
 fn translate_coords(
+    mut contexts: EguiContexts,
     window: Query<&Window, With<PrimaryWindow>>,
     camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
     buttons: Res<Input<MouseButton>>,
     mut writer: EventWriter<ClickEvent>,
 ) {
+    if contexts.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+    if buttons.get_pressed().next().is_none() {
+        return;
+    }
+    let (cam, coords) = camera.single();
+    let Some(loc) = get_coords(window.single(), cam, coords) else {
+        return;
+    };
     if buttons.just_pressed(MouseButton::Left) {
-        let (cam, coords) = camera.single();
-        let win = window.single();
-
-        if let Some(loc) = get_coords(win, cam, coords) {
-            writer.send(ClickEvent::LeftClick(loc));
-        }
+        writer.send(ClickEvent::LeftClick(loc));
+    } else if buttons.pressed(MouseButton::Left) {
+        writer.send(ClickEvent::LeftDrag(loc));
     }
     if buttons.just_pressed(MouseButton::Right) {
-        let (cam, coords) = camera.single();
-        let win = window.single();
-
-        if let Some(loc) = get_coords(win, cam, coords) {
-            writer.send(ClickEvent::RightClick(loc));
-        }
+        writer.send(ClickEvent::RightClick(loc));
+    } else if buttons.pressed(MouseButton::Right) {
+        writer.send(ClickEvent::RightDrag(loc));
     }
 }
 
 fn interact_cell(
     mut reader: EventReader<ClickEvent>,
     mut writer: EventWriter<CellInteractionEvent>,
-    grid: Query<&Children, With<Grid>>,
-    cells: Query<&GlobalTransform, With<Cell>>,
     grid_size: Res<GridSize>,
+    mut last: Local<Option<IVec2>>,
 ) {
     for event in reader.read() {
+        let (ClickEvent::LeftClick(pos)
+        | ClickEvent::RightClick(pos)
+        | ClickEvent::LeftDrag(pos)
+        | ClickEvent::RightDrag(pos)) = event;
+        let Some(cell) = grid_size.world_to_cell(*pos) else {
+            continue;
+        };
+        // Drags only report the cells they move onto.
+        let moved = *last != Some(cell);
+        *last = Some(cell);
         match event {
-            ClickEvent::LeftClick(click) => {
-                for g in &grid {
-                    for c in g {
-                        if let Ok(transform) = cells.get(*c) {
-                            if Rect::from_center_size(
-                                transform.translation().xy(),
-                                Vec2::splat(grid_size.tile_size),
-                            )
-                            .contains(*click)
-                            {
-                                writer.send(CellInteractionEvent(c.clone()));
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-            ClickEvent::RightClick(click) => todo!(),
+            ClickEvent::LeftClick(_) => writer.send(CellInteractionEvent::Paint(cell)),
+            ClickEvent::RightClick(_) => writer.send(CellInteractionEvent::Erase(cell)),
+            ClickEvent::LeftDrag(_) if moved => writer.send(CellInteractionEvent::PaintDrag(cell)),
+            ClickEvent::RightDrag(_) if moved => writer.send(CellInteractionEvent::EraseDrag(cell)),
+            _ => {}
         }
     }
 }
 
 fn paint(
-    mut commands: Commands,
     mut reader: EventReader<CellInteractionEvent>,
-    cell: Query<(Entity, &Parent, &Transform), With<Cell>>,
     brush: Query<&Brush, With<SelectedBrush>>,
+    terrain_brush: Res<TerrainBrush>,
+    stamp: Res<StampBrush>,
+    brush_transform: Res<BrushTransform>,
+    scatter_brush: Res<ScatterBrush>,
+    mut scatter_rng: ResMut<ScatterRng>,
+    brush_state: Res<State<BrushState>>,
     grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+    mut anchor: Local<Option<IVec2>>,
 ) {
+    if brush_state.is_changed() {
+        *anchor = None;
+    }
     for event in reader.read() {
-        if let Ok((current, grid, transform)) = cell.get(event.0) {
-            let current_brush = brush.single();
-            // TODO: not sure if this will be inefficient to despawn and recreate if it hasn't changed
-            commands.entity(current).despawn_recursive();
-            commands.entity(grid.get()).with_children(|parent| {
-                parent.spawn((
-                    SpriteSheetBundle {
-                        sprite: TextureAtlasSprite {
-                            index: current_brush.index,
-                            custom_size: Some(Vec2::splat(grid_size.tile_size)),
-                            ..default()
-                        },
-                        texture_atlas: current_brush.atlas.clone_weak(),
-                        transform: transform.clone(),
-                        ..default()
-                    },
-                    Cell,
-                ));
-            });
+        let (cell, erase, drag) = match event {
+            CellInteractionEvent::Paint(cell) => (*cell, false, false),
+            CellInteractionEvent::Erase(cell) => (*cell, true, false),
+            CellInteractionEvent::PaintDrag(cell) => (*cell, false, true),
+            CellInteractionEvent::EraseDrag(cell) => (*cell, true, true),
+        };
+        // Dragging paints strokes with the single brush only.
+        if drag && *brush_state.get() != BrushState::Single {
+            continue;
+        }
+        // Stamps are placed once per click, whatever the brush state.
+        if let (Some(stamp), None) = (&stamp.0, &terrain_brush.0) {
+            if drag {
+                continue;
+            }
+            for (target, tile) in stamp.placed(&grid_size, cell, brush_transform.0) {
+                if grid_size.contains(target) {
+                    map.set(target, (!erase).then_some(tile));
+                }
+            }
+            continue;
+        }
+        let scatter = scatter_brush.get();
+        let single = brush.get_single().ok().map(|current_brush| PaintedTile {
+            tileset: current_brush.tileset.clone(),
+            index: current_brush.index,
+            transform: brush_transform.0,
+        });
+        if !erase && terrain_brush.0.is_none() && scatter.is_none() && single.is_none() {
+            continue;
+        }
+        let cells = match brush_state.get() {
+            BrushState::Single => vec![cell],
+            BrushState::Fill => flood_fill(&grid_size, &map, cell),
+            // The first click anchors the line or rectangle, the second
+            // draws it.
+            BrushState::Line | BrushState::Rect => match anchor.take() {
+                Some(start) if *brush_state.get() == BrushState::Line => {
+                    grid_size.line(start, cell)
+                }
+                Some(start) => rect(start, cell)
+                    .into_iter()
+                    .filter(|cell| grid_size.contains(*cell))
+                    .collect(),
+                None => {
+                    *anchor = Some(cell);
+                    vec![cell]
+                }
+            },
+        };
+        if let Some((tileset, set)) = &terrain_brush.0 {
+            autotile::paint_terrain(&grid_size, &mut map, tileset, set, &cells, erase);
+            continue;
+        }
+        for cell in cells {
+            let tile = match scatter {
+                _ if erase => None,
+                Some(scatter) => {
+                    let Some(index) = scatter.pick(&mut scatter_rng.rng) else {
+                        continue;
+                    };
+                    Some(PaintedTile {
+                        tileset: scatter.tileset.clone(),
+                        index,
+                        transform: brush_transform.0,
+                    })
+                }
+                None => single.clone(),
+            };
+            if map.get(cell) != tile.as_ref() {
+                map.set(cell, tile);
+            }
         }
     }
 }
@@ -0,0 +1,322 @@
+//! Offset-coordinate helpers for staggered hexagonal grids.
+//!
+//! Cells use Tiled's staggered layout: rows (or columns) count downwards and
+//! every other one is shifted by half a cell along the stagger axis.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StaggerAxis {
+    /// Columns are staggered, giving flat-topped hexes.
+    X,
+    /// Rows are staggered, giving pointy-topped hexes.
+    #[default]
+    Y,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StaggerIndex {
+    #[default]
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HexLayout {
+    pub stagger_axis: StaggerAxis,
+    pub stagger_index: StaggerIndex,
+    /// Length of the sides running along the stagger axis, in pixels.
+    pub side_length: f32,
+}
+
+impl Default for HexLayout {
+    fn default() -> Self {
+        Self {
+            stagger_axis: StaggerAxis::default(),
+            stagger_index: StaggerIndex::default(),
+            side_length: 32.,
+        }
+    }
+}
+
+impl HexLayout {
+    /// Whether the row/column `i` along the stagger axis is shifted.
+    pub fn is_staggered(&self, i: i32) -> bool {
+        let odd = i.rem_euclid(2) == 1;
+        match self.stagger_index {
+            StaggerIndex::Odd => odd,
+            StaggerIndex::Even => !odd,
+        }
+    }
+
+    /// Center of a cell relative to the center of cell `(0, 0)`, y up, for
+    /// cells with the given bounding box.
+    pub fn center(&self, cell: IVec2, extent: Vec2) -> Vec2 {
+        let shift = |i: i32| {
+            let base = self.is_staggered(0) as i32 as f32;
+            (self.is_staggered(i) as i32 as f32 - base) / 2.
+        };
+        match self.stagger_axis {
+            StaggerAxis::Y => {
+                let row_height = (extent.y + self.side_length) / 2.;
+                Vec2::new(
+                    (cell.x as f32 + shift(cell.y)) * extent.x,
+                    -cell.y as f32 * row_height,
+                )
+            }
+            StaggerAxis::X => {
+                let column_width = (extent.x + self.side_length) / 2.;
+                Vec2::new(
+                    cell.x as f32 * column_width,
+                    -(cell.y as f32 + shift(cell.x)) * extent.y,
+                )
+            }
+        }
+    }
+
+    /// Approximate continuous cell coordinates of a position relative to the
+    /// center of cell `(0, 0)`. Only good to within a cell; use `pick` for
+    /// exact hit testing.
+    pub fn approximate_cell(&self, pos: Vec2, extent: Vec2) -> Vec2 {
+        match self.stagger_axis {
+            StaggerAxis::Y => Vec2::new(
+                pos.x / extent.x,
+                -pos.y / ((extent.y + self.side_length) / 2.),
+            ),
+            StaggerAxis::X => Vec2::new(
+                pos.x / ((extent.x + self.side_length) / 2.),
+                -pos.y / extent.y,
+            ),
+        }
+    }
+
+    /// Corners of a hex centered on the origin, counter-clockwise.
+    pub fn corners(&self, extent: Vec2) -> [Vec2; 6] {
+        let half = extent / 2.;
+        let side = self.side_length / 2.;
+        match self.stagger_axis {
+            StaggerAxis::Y => [
+                Vec2::new(0., half.y),
+                Vec2::new(-half.x, side),
+                Vec2::new(-half.x, -side),
+                Vec2::new(0., -half.y),
+                Vec2::new(half.x, -side),
+                Vec2::new(half.x, side),
+            ],
+            StaggerAxis::X => [
+                Vec2::new(half.x, 0.),
+                Vec2::new(side, half.y),
+                Vec2::new(-side, half.y),
+                Vec2::new(-half.x, 0.),
+                Vec2::new(-side, -half.y),
+                Vec2::new(side, -half.y),
+            ],
+        }
+    }
+
+    /// The cell whose hexagon contains `pos`, a position relative to the center
+    /// of cell `(0, 0)`.
+    pub fn pick(&self, pos: Vec2, extent: Vec2) -> IVec2 {
+        let rough = self.approximate_cell(pos, extent).round().as_ivec2();
+        let corners = self.corners(extent);
+        let mut nearest = rough;
+        let mut nearest_distance = f32::INFINITY;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let cell = rough + IVec2::new(dx, dy);
+                let local = pos - self.center(cell, extent);
+                if contains(&corners, local) {
+                    return cell;
+                }
+                let distance = local.length_squared();
+                if distance < nearest_distance {
+                    nearest = cell;
+                    nearest_distance = distance;
+                }
+            }
+        }
+        nearest
+    }
+
+    pub fn neighbors(&self, cell: IVec2) -> [IVec2; 6] {
+        match self.stagger_axis {
+            StaggerAxis::Y => {
+                let (left, right) = if self.is_staggered(cell.y) {
+                    (0, 1)
+                } else {
+                    (-1, 0)
+                };
+                [
+                    cell + IVec2::new(-1, 0),
+                    cell + IVec2::new(1, 0),
+                    cell + IVec2::new(left, -1),
+                    cell + IVec2::new(right, -1),
+                    cell + IVec2::new(left, 1),
+                    cell + IVec2::new(right, 1),
+                ]
+            }
+            StaggerAxis::X => {
+                let (up, down) = if self.is_staggered(cell.x) {
+                    (0, 1)
+                } else {
+                    (-1, 0)
+                };
+                [
+                    cell + IVec2::new(0, -1),
+                    cell + IVec2::new(0, 1),
+                    cell + IVec2::new(-1, up),
+                    cell + IVec2::new(-1, down),
+                    cell + IVec2::new(1, up),
+                    cell + IVec2::new(1, down),
+                ]
+            }
+        }
+    }
+
+    /// Offset to cube coordinates `(q, r)`; the third axis is `-q - r`.
+    fn cube(&self, cell: IVec2) -> IVec2 {
+        // Parity of the staggered rows/columns relative to an odd layout.
+        let flip = matches!(self.stagger_index, StaggerIndex::Even) as i32;
+        match self.stagger_axis {
+            StaggerAxis::Y => {
+                let parity = (cell.y + flip) & 1;
+                IVec2::new(cell.x - (cell.y + flip - parity) / 2, cell.y)
+            }
+            StaggerAxis::X => {
+                let parity = (cell.x + flip) & 1;
+                IVec2::new(cell.x, cell.y - (cell.x + flip - parity) / 2)
+            }
+        }
+    }
+
+    fn offset(&self, cube: IVec2) -> IVec2 {
+        let flip = matches!(self.stagger_index, StaggerIndex::Even) as i32;
+        match self.stagger_axis {
+            StaggerAxis::Y => {
+                let parity = (cube.y + flip) & 1;
+                IVec2::new(cube.x + (cube.y + flip - parity) / 2, cube.y)
+            }
+            StaggerAxis::X => {
+                let parity = (cube.x + flip) & 1;
+                IVec2::new(cube.x, cube.y + (cube.x + flip - parity) / 2)
+            }
+        }
+    }
+
+    /// Cells on the straight line between two cells, inclusive.
+    pub fn line(&self, from: IVec2, to: IVec2) -> Vec<IVec2> {
+        let a = self.cube(from).as_vec2();
+        let b = self.cube(to).as_vec2();
+        let d = b - a;
+        let steps = d.x.abs().max(d.y.abs()).max((d.x + d.y).abs()) as i32;
+        // Nudge off cell edges so ties round consistently.
+        let a = a + Vec2::new(1e-6, 2e-6);
+        (0..=steps)
+            .map(|i| {
+                let t = if steps == 0 {
+                    0.
+                } else {
+                    i as f32 / steps as f32
+                };
+                self.offset(cube_round(a + d * t))
+            })
+            .collect()
+    }
+}
+
+fn cube_round(cube: Vec2) -> IVec2 {
+    let s = -cube.x - cube.y;
+    let (mut q, mut r, rs) = (cube.x.round(), cube.y.round(), s.round());
+    let (dq, dr, ds) = ((q - cube.x).abs(), (r - cube.y).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        q = -r - rs;
+    } else if dr > ds {
+        r = -q - rs;
+    }
+    IVec2::new(q as i32, r as i32)
+}
+
+/// Point-in-convex-polygon test for counter-clockwise corners.
+fn contains(corners: &[Vec2], point: Vec2) -> bool {
+    (0..corners.len()).all(|i| {
+        let a = corners[i];
+        let b = corners[(i + 1) % corners.len()];
+        (b - a).perp_dot(point - a) >= 0.
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: Vec2 = Vec2::new(32., 28.);
+
+    fn layouts() -> Vec<HexLayout> {
+        let mut layouts = Vec::new();
+        for stagger_axis in [StaggerAxis::X, StaggerAxis::Y] {
+            for stagger_index in [StaggerIndex::Odd, StaggerIndex::Even] {
+                layouts.push(HexLayout {
+                    stagger_axis,
+                    stagger_index,
+                    side_length: 14.,
+                });
+            }
+        }
+        layouts
+    }
+
+    fn cells() -> impl Iterator<Item = IVec2> {
+        (-4..4).flat_map(|y| (-4..4).map(move |x| IVec2::new(x, y)))
+    }
+
+    fn distance(layout: &HexLayout, a: IVec2, b: IVec2) -> i32 {
+        let d = layout.cube(b) - layout.cube(a);
+        d.x.abs().max(d.y.abs()).max((d.x + d.y).abs())
+    }
+
+    #[test]
+    fn cube_coordinates_round_trip() {
+        for layout in layouts() {
+            for cell in cells() {
+                assert_eq!(layout.offset(layout.cube(cell)), cell, "{layout:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn centers_pick_their_cell() {
+        for layout in layouts() {
+            for cell in cells() {
+                let center = layout.center(cell, EXTENT);
+                assert_eq!(layout.pick(center, EXTENT), cell, "{layout:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_are_adjacent_both_ways() {
+        for layout in layouts() {
+            for cell in cells() {
+                for neighbor in layout.neighbors(cell) {
+                    assert_eq!(distance(&layout, cell, neighbor), 1, "{layout:?}");
+                    assert!(layout.neighbors(neighbor).contains(&cell), "{layout:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lines_step_between_neighbors() {
+        for layout in layouts() {
+            let (from, to) = (IVec2::new(-3, 2), IVec2::new(4, -3));
+            let line = layout.line(from, to);
+            assert_eq!(line.first(), Some(&from));
+            assert_eq!(line.last(), Some(&to));
+            assert_eq!(line.len() as i32, distance(&layout, from, to) + 1);
+            for pair in line.windows(2) {
+                assert!(layout.neighbors(pair[0]).contains(&pair[1]), "{layout:?}");
+            }
+        }
+    }
+}
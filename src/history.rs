@@ -0,0 +1,193 @@
+//! Undo and redo with Ctrl+Z and Ctrl+Shift+Z (or Ctrl+Y).
+//!
+//! Every change to the open map's cells and layers is recorded, as are
+//! resizes. Changes made while a mouse button is held are grouped into one
+//! step, so a brush stroke undoes at once. Tools that rewrite map files on
+//! disk attach the files' old and new contents to the step they are part of.
+//! Loading or clearing the map forgets the history.
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::{
+    grid::GridSize,
+    map::{MapData, MapEdit},
+};
+
+/// Oldest steps are dropped beyond this.
+const MAX_STEPS: usize = 100;
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<History>()
+            .add_systems(Update, undo_keys)
+            .add_systems(Last, record_edits);
+    }
+}
+
+/// A map file rewritten on disk, with its contents before and after.
+pub struct FileEdit {
+    pub path: PathBuf,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Step {
+    edits: Vec<MapEdit>,
+    /// Columns and rows before and after a resize.
+    resize: Option<(UVec2, UVec2)>,
+    files: Vec<FileEdit>,
+}
+
+impl Step {
+    fn is_empty(&self) -> bool {
+        self.edits.is_empty() && self.resize.is_none() && self.files.is_empty()
+    }
+
+    /// Undoes the step, or applies it again if `forward` is set.
+    fn apply(&self, map: &mut MapData, grid_size: &mut GridSize, forward: bool) {
+        let edits: Box<dyn Iterator<Item = &MapEdit>> = match forward {
+            true => Box::new(self.edits.iter()),
+            false => Box::new(self.edits.iter().rev()),
+        };
+        for edit in edits {
+            match edit {
+                MapEdit::Cell(edit) if edit.layer < map.layers().len() => {
+                    let tile = if forward { &edit.after } else { &edit.before };
+                    map.set_in(edit.layer, edit.cell, tile.clone());
+                }
+                MapEdit::Cell(_) => {}
+                MapEdit::AddLayer(name) if forward => {
+                    map.add_layer(name.clone());
+                }
+                MapEdit::AddLayer(_) => map.remove_top_layer(),
+            }
+        }
+        if let Some((before, after)) = self.resize {
+            let size = if forward { after } else { before };
+            grid_size.cols = size.x as usize;
+            grid_size.rows = size.y as usize;
+        }
+        for file in &self.files {
+            let contents = if forward { &file.after } else { &file.before };
+            if let Err(e) = std::fs::write(&file.path, contents) {
+                error!("Could not restore {}: {e}", file.path.display());
+            }
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    /// Changes not yet closed into a step.
+    pending: Step,
+}
+
+impl History {
+    /// Adds a rewritten file to the step being recorded.
+    pub fn record_file(&mut self, file: FileEdit) {
+        self.pending.files.push(file);
+    }
+
+    /// Adds a resize of the map to the step being recorded.
+    pub fn record_resize(&mut self, before: UVec2, after: UVec2) {
+        let before = self.pending.resize.map_or(before, |(first, _)| first);
+        self.pending.resize = Some((before, after));
+    }
+
+    /// Closes the changes recorded so far into a step of their own.
+    pub fn commit(&mut self, map: &mut MapData) {
+        self.pending.edits.extend(map.take_edits());
+        if self.pending.is_empty() {
+            return;
+        }
+        self.undo.push(std::mem::take(&mut self.pending));
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Reverts the last step, returning whether there was one.
+    pub fn undo(&mut self, map: &mut MapData, grid_size: &mut GridSize) -> bool {
+        self.commit(map);
+        let Some(step) = self.undo.pop() else {
+            return false;
+        };
+        step.apply(map, grid_size, false);
+        map.take_edits();
+        self.redo.push(step);
+        true
+    }
+
+    /// Applies the last undone step again, returning whether there was one.
+    pub fn redo(&mut self, map: &mut MapData, grid_size: &mut GridSize) -> bool {
+        self.commit(map);
+        let Some(step) = self.redo.pop() else {
+            return false;
+        };
+        step.apply(map, grid_size, true);
+        map.take_edits();
+        self.undo.push(step);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.pending = Step::default();
+    }
+}
+
+fn record_edits(
+    mut history: ResMut<History>,
+    mut map: ResMut<MapData>,
+    buttons: Res<Input<MouseButton>>,
+) {
+    if map.take_cleared() {
+        map.take_edits();
+        history.clear();
+        return;
+    }
+    let edits = map.take_edits();
+    if !edits.is_empty() {
+        history.pending.edits.extend(edits);
+    }
+    if buttons.get_pressed().next().is_none() && !history.pending.is_empty() {
+        history.commit(&mut map);
+    }
+}
+
+fn undo_keys(
+    mut contexts: EguiContexts,
+    keys: Res<Input<KeyCode>>,
+    mut history: ResMut<History>,
+    mut map: ResMut<MapData>,
+    mut grid_size: ResMut<GridSize>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let ctrl = keys.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::SuperLeft,
+        KeyCode::SuperRight,
+    ]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !ctrl {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z)) {
+        history.redo(&mut map, &mut grid_size);
+    } else if keys.just_pressed(KeyCode::Z) {
+        history.undo(&mut map, &mut grid_size);
+    }
+}
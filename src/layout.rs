@@ -0,0 +1,268 @@
+//! Dungeon layouts for roguelikes: rooms from a binary space partition joined
+//! by corridors, or caves grown with a cellular automaton. Both make a grid of
+//! floor and wall cells, and only depend on their settings and seed.
+
+use bevy::math::{IVec2, UVec2};
+
+/// Cells of the rectangle between two corner cells, inclusive, row by row.
+pub fn rect(from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let (min, max) = (from.min(to), from.max(to));
+    (min.y..=max.y)
+        .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+        .collect()
+}
+
+/// Floor and wall cells, row by row from the origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DungeonGrid {
+    pub size: UVec2,
+    pub floor: Vec<bool>,
+}
+
+impl DungeonGrid {
+    /// A grid of walls.
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            floor: vec![false; (size.x * size.y) as usize],
+        }
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        let inside = cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.size.as_ivec2()).all();
+        inside.then(|| (cell.y as u32 * self.size.x + cell.x as u32) as usize)
+    }
+
+    /// Whether the cell is floor. Cells outside the grid are walls.
+    pub fn is_floor(&self, cell: IVec2) -> bool {
+        self.index(cell).is_some_and(|i| self.floor[i])
+    }
+
+    /// Makes a cell floor, leaving the outer ring of walls alone.
+    pub fn carve(&mut self, cell: IVec2) {
+        let inner = cell.cmpge(IVec2::ONE).all() && cell.cmplt(self.size.as_ivec2() - 1).all();
+        if let (true, Some(i)) = (inner, self.index(cell)) {
+            self.floor[i] = true;
+        }
+    }
+
+    pub fn cells(&self) -> Vec<IVec2> {
+        if self.size.x == 0 || self.size.y == 0 {
+            return Vec::new();
+        }
+        rect(IVec2::ZERO, self.size.as_ivec2() - 1)
+    }
+
+    fn walls_around(&self, cell: IVec2) -> u8 {
+        rect(cell - 1, cell + 1)
+            .into_iter()
+            .filter(|other| *other != cell && !self.is_floor(*other))
+            .count() as u8
+    }
+
+    /// Turns every floor region but the largest into walls.
+    fn keep_largest_region(&mut self) {
+        let mut region = vec![usize::MAX; self.floor.len()];
+        let mut sizes = Vec::new();
+        for start in self.cells() {
+            let i = self.index(start).unwrap();
+            if !self.floor[i] || region[i] != usize::MAX {
+                continue;
+            }
+            let id = sizes.len();
+            let mut size = 0;
+            let mut open = vec![start];
+            region[i] = id;
+            while let Some(cell) = open.pop() {
+                size += 1;
+                for side in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                    let next = cell + side;
+                    if let Some(j) = self.index(next) {
+                        if self.floor[j] && region[j] == usize::MAX {
+                            region[j] = id;
+                            open.push(next);
+                        }
+                    }
+                }
+            }
+            sizes.push(size);
+        }
+        let Some(largest) = (0..sizes.len()).max_by_key(|id| (sizes[*id], usize::MAX - id)) else {
+            return;
+        };
+        for (floor, id) in self.floor.iter_mut().zip(region) {
+            *floor &= id == largest;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspSettings {
+    /// Smallest and largest sides of a room, walls not included.
+    pub min_room: u32,
+    pub max_room: u32,
+    pub corridor_width: u32,
+}
+
+impl Default for BspSettings {
+    fn default() -> Self {
+        Self {
+            min_room: 4,
+            max_room: 10,
+            corridor_width: 1,
+        }
+    }
+}
+
+/// Rooms placed in the leaves of a binary space partition, with a corridor
+/// between the two halves of every split.
+pub fn bsp(size: UVec2, settings: &BspSettings, seed: u32) -> DungeonGrid {
+    let mut grid = DungeonGrid::new(size);
+    let mut rng = fastrand::Rng::with_seed(seed.into());
+    let min_room = settings.min_room.max(1) as i32;
+    let max_room = (settings.max_room as i32).max(min_room);
+    split(
+        &mut grid,
+        &mut rng,
+        settings,
+        (min_room, max_room),
+        IVec2::ZERO,
+        size.as_ivec2(),
+    );
+    grid
+}
+
+/// Splits a leaf or places a room in it, returning the centres of the rooms
+/// placed inside it.
+fn split(
+    grid: &mut DungeonGrid,
+    rng: &mut fastrand::Rng,
+    settings: &BspSettings,
+    (min_room, max_room): (i32, i32),
+    min: IVec2,
+    size: IVec2,
+) -> Vec<IVec2> {
+    // Leaves keep a wall on every side of their room.
+    let leaf = min_room + 2;
+    let can_split = size.cmpge(IVec2::splat(leaf * 2));
+    let too_big = size.cmpgt(IVec2::splat(max_room + 2));
+    if (can_split.x || can_split.y) && (too_big.x || too_big.y) {
+        let vertical = match (can_split.x, can_split.y) {
+            (true, false) => true,
+            (false, true) => false,
+            _ if size.x * 4 > size.y * 5 => true,
+            _ if size.y * 4 > size.x * 5 => false,
+            _ => rng.bool(),
+        };
+        let axis = if vertical { IVec2::X } else { IVec2::Y };
+        let length = size.dot(axis);
+        let at = rng.i32(leaf..=length - leaf);
+        let first = split(
+            grid,
+            rng,
+            settings,
+            (min_room, max_room),
+            min,
+            size - axis * (length - at),
+        );
+        let second = split(
+            grid,
+            rng,
+            settings,
+            (min_room, max_room),
+            min + axis * at,
+            size - axis * at,
+        );
+        if let (Some(from), Some(to)) = (pick(&first, rng), pick(&second, rng)) {
+            corridor(grid, rng, from, to, settings.corridor_width.max(1) as i32);
+        }
+        return first.into_iter().chain(second).collect();
+    }
+    if size.cmplt(IVec2::splat(leaf)).any() {
+        return Vec::new();
+    }
+    let room_max = (size - 2).min(IVec2::splat(max_room));
+    let room = IVec2::new(
+        rng.i32(min_room..=room_max.x),
+        rng.i32(min_room..=room_max.y),
+    );
+    let slack = size - 2 - room;
+    let origin = min + 1 + IVec2::new(rng.i32(0..=slack.x), rng.i32(0..=slack.y));
+    for cell in rect(origin, origin + room - 1) {
+        grid.carve(cell);
+    }
+    vec![origin + room / 2]
+}
+
+fn pick(rooms: &[IVec2], rng: &mut fastrand::Rng) -> Option<IVec2> {
+    (!rooms.is_empty()).then(|| rooms[rng.usize(..rooms.len())])
+}
+
+/// An L-shaped corridor, turning at a random corner.
+fn corridor(grid: &mut DungeonGrid, rng: &mut fastrand::Rng, from: IVec2, to: IVec2, width: i32) {
+    let corner = if rng.bool() {
+        IVec2::new(to.x, from.y)
+    } else {
+        IVec2::new(from.x, to.y)
+    };
+    for (a, b) in [(from, corner), (corner, to)] {
+        for cell in rect(a.min(b), a.max(b) + width - 1) {
+            grid.carve(cell);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaveSettings {
+    /// Chance of a cell starting as a wall.
+    pub fill: f32,
+    pub steps: u32,
+    /// Walls among the eight neighbours that turn a floor cell into a wall.
+    pub birth: u8,
+    /// Walls among the eight neighbours that keep a wall standing.
+    pub survival: u8,
+    /// Fill in caves not connected to the largest one.
+    pub keep_largest: bool,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            fill: 0.45,
+            steps: 5,
+            birth: 5,
+            survival: 4,
+            keep_largest: true,
+        }
+    }
+}
+
+/// Caves smoothed out of random walls by a cellular automaton.
+pub fn caves(size: UVec2, settings: &CaveSettings, seed: u32) -> DungeonGrid {
+    let mut grid = DungeonGrid::new(size);
+    let mut rng = fastrand::Rng::with_seed(seed.into());
+    for cell in grid.cells() {
+        if rng.f32() >= settings.fill {
+            grid.carve(cell);
+        }
+    }
+    for _ in 0..settings.steps {
+        let mut next = DungeonGrid::new(size);
+        for cell in grid.cells() {
+            let walls = grid.walls_around(cell);
+            let wall = if grid.is_floor(cell) {
+                walls >= settings.birth
+            } else {
+                walls >= settings.survival
+            };
+            if !wall {
+                next.carve(cell);
+            }
+        }
+        grid = next;
+    }
+    if settings.keep_largest {
+        grid.keep_largest_region();
+    }
+    grid
+}
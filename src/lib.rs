@@ -0,0 +1,4 @@
+//! Parts of the editor usable without it, such as from a game generating
+//! its levels at runtime.
+
+pub mod layout;
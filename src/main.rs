@@ -1,16 +1,57 @@
-use std::collections::HashMap;
+// Bevy systems routinely take many parameters and nested query filters.
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
-use assets::{AssetPlugin, Tile, TileDefinition};
+use std::{collections::HashMap, process::ExitCode};
+
+use assets::{AssetPlugin, AtlasDefinition, Tile, TileDefinition};
+use automap::AutomapPlugin;
 use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
+use collection::{CollectionPlugin, ImageCollections};
+use console::ConsolePlugin;
+use dungeon::DungeonPlugin;
+use export::ExportPlugin;
+use generator::GeneratorPlugin;
 use grid::GridPlugin;
+use history::HistoryPlugin;
+use map::{MapData, MapPlugin};
 use menus::MenuPlugin;
+use noisegen::NoiseGenPlugin;
+use packer::PackerPlugin;
+use project::{ProjectPlugin, DEFAULT_DEFINITIONS};
+use replace::ReplacePlugin;
+use scatter::ScatterPlugin;
+use script::ScriptPlugin;
+use selection::SelectionPlugin;
+use stamp::StampPlugin;
+use wfc::WfcPlugin;
+use wizard::WizardPlugin;
 // use paint::PaintPlugin;
 
 mod assets;
+mod automap;
+mod autotile;
+mod cli;
+mod collection;
+mod console;
+mod dungeon;
+mod export;
+mod generator;
 mod grid;
-mod loader;
+mod hex;
+mod history;
+mod map;
 mod menus;
+mod noisegen;
+mod packer;
+mod project;
+mod replace;
+mod scatter;
+mod script;
+mod selection;
+mod stamp;
+mod wfc;
+mod wizard;
 // mod paint;
 
 #[derive(States, Default, Debug, Clone, Hash, PartialEq, Eq)]
@@ -25,18 +66,39 @@ pub enum AppSystemSets {
     LoadingStuff,
 }
 
-fn main() {
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        return code;
+    }
     App::new()
+        .add_plugins(ProjectPlugin)
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
         .add_plugins(EguiPlugin)
         .add_plugins(AssetPlugin)
         // .add_plugins(PaintPlugin)
         .add_plugins(MenuPlugin)
         .add_plugins(GridPlugin)
+        .add_plugins(MapPlugin)
+        .add_plugins(ExportPlugin)
+        .add_plugins(AutomapPlugin)
+        .add_plugins(WizardPlugin)
+        .add_plugins(CollectionPlugin)
+        .add_plugins(PackerPlugin)
+        .add_plugins(StampPlugin)
+        .add_plugins(ScatterPlugin)
+        .add_plugins(SelectionPlugin)
+        .add_plugins(HistoryPlugin)
+        .add_plugins(ReplacePlugin)
+        .add_plugins(GeneratorPlugin)
+        .add_plugins(NoiseGenPlugin)
+        .add_plugins(WfcPlugin)
+        .add_plugins(DungeonPlugin)
+        .add_plugins(ScriptPlugin)
+        .add_plugins(ConsolePlugin)
         .add_state::<AppState>()
+        .add_event::<AtlasesRebuilt>()
         .init_resource::<MapSettings>()
-        .init_resource::<TileAtlases>()
-        // .register_asset_source("", )
         .add_systems(Startup, setup.in_set(AppSystemSets::LoadingStuff))
         // .add_systems(Update, draw_grid)
         // .add_systems(OnEnter(AppState::ConfiguringMap), configure_grid)
@@ -44,9 +106,19 @@ fn main() {
         //     Update,
         //     (selected_tile).run_if(resource_exists::<TilesData>()),
         // )
-        .add_systems(Update, load_tiles.run_if(resource_changed::<TilesData>()))
+        .add_systems(
+            Update,
+            load_tiles.run_if(
+                on_event::<AssetEvent<TileDefinition>>().or_else(resource_changed::<TilesData>()),
+            ),
+        )
+        .add_systems(
+            Update,
+            reload_tiles_for_images.run_if(on_event::<AssetEvent<Image>>()),
+        )
         // .add_systems(Startup, load_tiles.run_if(resource_exists::<TilesData>()))
         .run();
+    ExitCode::SUCCESS
 }
 
 // #[derive(Debug, Clone, PartialEq, Eq)]
@@ -54,43 +126,40 @@ fn main() {
 //     path: String,
 // }
 
-#[derive(Resource, Clone)]
+#[derive(Resource, Clone, Default)]
 struct MapSettings {
-    tile_size: f32,
     paint_tile: Option<Tile>,
     atlases: HashMap<String, Handle<TextureAtlas>>,
+    atlas_definitions: HashMap<String, AtlasDefinition>,
     // tile_folder: Option<String>,
     // tiles_list: Vec<Tile>,
 }
 
-impl Default for MapSettings {
-    fn default() -> Self {
-        Self {
-            tile_size: 64.0,
-            paint_tile: None,
-            atlases: HashMap::new(),
-            // tile_folder: None,
-            // tiles_list: Vec::new(),
-        }
-    }
-}
-
+/// Sent whenever the atlases in `MapSettings` are rebuilt from the tile
+/// definitions.
 #[derive(Event)]
-struct FolderEvent(String);
-
-#[derive(Resource, Default)]
-struct TilesData(Handle<TileDefinition>);
+pub struct AtlasesRebuilt;
 
+/// The tile definition files of the open project.
 #[derive(Resource, Default)]
-struct TileAtlases(Vec<(String, Handle<TextureAtlas>)>);
+struct TilesData(Vec<Handle<TileDefinition>>);
+
+impl TilesData {
+    /// Tiles from every loaded definition file, in project order.
+    fn tiles<'a>(&'a self, assets: &'a Assets<TileDefinition>) -> impl Iterator<Item = &'a Tile> {
+        self.0
+            .iter()
+            .filter_map(|handle| assets.get(handle))
+            .flat_map(|definition| &definition.tiles)
+    }
+}
 
 #[derive(Component)]
 pub struct MainCamera;
 
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.spawn((Camera2dBundle::default(), MainCamera));
-    let tiles_data = asset_server.load("data/tiles.ron");
-    commands.insert_resource(TilesData(tiles_data));
+    commands.insert_resource(TilesData(vec![asset_server.load(DEFAULT_DEFINITIONS)]));
 }
 
 fn load_tiles(
@@ -99,11 +168,26 @@ fn load_tiles(
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut state: ResMut<MapSettings>,
-    // mut atlases: ResMut<TileAtlases>,
+    mut map: ResMut<MapData>,
+    mut collections: ResMut<ImageCollections>,
+    app_state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut rebuilt: EventWriter<AtlasesRebuilt>,
 ) {
-    if let Some(tiles) = tile_assets.get(&tile_handle.0) {
-        for tile in &tiles.tiles {
-            if let Some(atlas) = &tile.atlas_definition {
+    if tile_handle
+        .0
+        .iter()
+        .any(|handle| tile_assets.contains(handle))
+    {
+        // Start over so tilesets removed from the definitions go away too.
+        state.atlases.clear();
+        state.atlas_definitions.clear();
+        collections.clear();
+        for tile in tile_handle.tiles(&tile_assets) {
+            if tile.images.is_some() {
+                // Added to the atlases once its images have loaded.
+                collections.add(tile, &asset_server);
+            } else if let Some(atlas) = &tile.atlas_definition {
                 let texture_handle = asset_server.load(&tile.path);
                 let tx_atlas = TextureAtlas::from_grid(
                     texture_handle,
@@ -115,12 +199,53 @@ fn load_tiles(
                 );
 
                 let atlas_handle = texture_atlases.add(tx_atlas);
-                // texture_atlases.add(tx_atlas);
                 state.atlases.insert(tile.path.clone(), atlas_handle);
+                state
+                    .atlas_definitions
+                    .insert(tile.path.clone(), atlas.clone());
             }
         }
+        // Keep the palette on the selected tileset as it was reloaded.
+        state.paint_tile = state.paint_tile.take().and_then(|selected| {
+            tile_handle
+                .tiles(&tile_assets)
+                .find(|t| t.name == selected.name)
+                .cloned()
+        });
+        map.redraw();
+        rebuilt.send(AtlasesRebuilt);
+        if *app_state.get() == AppState::Loading {
+            next_state.set(AppState::Painting);
+        }
     }
 }
 
-#[derive(Component)]
-struct ConfigMenu;
+/// Reload the tile definitions when one of their images changes on disk, so
+/// the atlases are checked and rebuilt against the new image.
+fn reload_tiles_for_images(
+    mut events: EventReader<AssetEvent<Image>>,
+    tile_handle: Res<TilesData>,
+    state: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    asset_server: Res<AssetServer>,
+) {
+    let in_use = |id: &AssetId<Image>| {
+        state
+            .atlases
+            .values()
+            .filter_map(|handle| texture_atlases.get(handle))
+            .any(|atlas| atlas.texture.id() == *id)
+    };
+    let modified = events
+        .read()
+        .any(|event| matches!(event, AssetEvent::Modified { id } if in_use(id)));
+    if modified {
+        for path in tile_handle
+            .0
+            .iter()
+            .filter_map(|h| asset_server.get_path(h))
+        {
+            asset_server.reload(path);
+        }
+    }
+}
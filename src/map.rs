@@ -0,0 +1,843 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    assets::TileAnimation,
+    grid::{GridSize, MapMode, Orientation},
+    hex::HexLayout,
+    MapSettings,
+};
+
+/// Cells per side of a storage chunk.
+pub const CHUNK_SIZE: i32 = 16;
+
+pub struct MapPlugin;
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveMapEvent>()
+            .add_event::<LoadMapEvent>()
+            .init_resource::<MapData>()
+            .add_systems(Update, (save_map, load_map));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PaintedTile {
+    /// Key of the atlas in `MapSettings.atlases`, i.e. the tile's image path.
+    pub tileset: String,
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "TileTransform::is_identity")]
+    pub transform: TileTransform,
+}
+
+/// How a painted frame is mirrored and turned. The flips are applied to the
+/// frame first, then it is turned a quarter clockwise if `rotated` is set,
+/// which covers every combination of flips and quarter turns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TileTransform {
+    #[serde(default)]
+    pub flip_x: bool,
+    #[serde(default)]
+    pub flip_y: bool,
+    #[serde(default)]
+    pub rotated: bool,
+}
+
+impl TileTransform {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Mirrors the result left to right, as seen on screen.
+    pub fn flip_x(self) -> Self {
+        match self.rotated {
+            false => Self {
+                flip_x: !self.flip_x,
+                ..self
+            },
+            true => Self {
+                flip_y: !self.flip_y,
+                ..self
+            },
+        }
+    }
+
+    /// Mirrors the result top to bottom, as seen on screen.
+    pub fn flip_y(self) -> Self {
+        self.flip_x().rotate().rotate()
+    }
+
+    /// Turns the result a quarter clockwise.
+    pub fn rotate(self) -> Self {
+        match self.rotated {
+            false => Self {
+                rotated: true,
+                ..self
+            },
+            // Two quarter turns are the same as flipping both ways.
+            true => Self {
+                flip_x: !self.flip_x,
+                flip_y: !self.flip_y,
+                rotated: false,
+            },
+        }
+    }
+
+    /// This transform applied on top of `other`.
+    pub fn compose(self, other: Self) -> Self {
+        let mut result = other;
+        if self.flip_y {
+            result = result.flip_y();
+        }
+        if self.flip_x {
+            result = result.flip_x();
+        }
+        if self.rotated {
+            result = result.rotate();
+        }
+        result
+    }
+
+    /// Moves a screen offset, `y` pointing up, the way the frame is moved.
+    pub fn apply(&self, offset: IVec2) -> IVec2 {
+        let mut offset = offset;
+        if self.flip_y {
+            offset.y = -offset.y;
+        }
+        if self.flip_x {
+            offset.x = -offset.x;
+        }
+        if self.rotated {
+            offset = IVec2::new(offset.y, -offset.x);
+        }
+        offset
+    }
+
+    pub fn rotation(&self) -> Quat {
+        match self.rotated {
+            false => Quat::IDENTITY,
+            true => Quat::from_rotation_z(-std::f32::consts::FRAC_PI_2),
+        }
+    }
+
+    /// Tiled's flip bits for the high end of a GID: horizontal, vertical and
+    /// diagonal, the diagonal flip being applied first.
+    pub fn tiled_flags(&self) -> u32 {
+        const HORIZONTAL: u32 = 0x8000_0000;
+        const VERTICAL: u32 = 0x4000_0000;
+        const DIAGONAL: u32 = 0x2000_0000;
+        // A quarter turn clockwise is a diagonal then a horizontal flip.
+        let (horizontal, vertical) = match self.rotated {
+            false => (self.flip_x, self.flip_y),
+            true => (!self.flip_y, self.flip_x),
+        };
+        (horizontal as u32 * HORIZONTAL)
+            | (vertical as u32 * VERTICAL)
+            | (self.rotated as u32 * DIAGONAL)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "ChunkCells")]
+pub struct Chunk {
+    cells: Vec<Option<PaintedTile>>,
+}
+
+/// A chunk as read from a file, which must hold exactly one chunk of cells.
+#[derive(Deserialize)]
+#[serde(rename = "Chunk")]
+struct ChunkCells {
+    cells: Vec<Option<PaintedTile>>,
+}
+
+impl TryFrom<ChunkCells> for Chunk {
+    type Error = String;
+
+    fn try_from(chunk: ChunkCells) -> Result<Self, Self::Error> {
+        let expected = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        if chunk.cells.len() != expected {
+            return Err(format!(
+                "chunk has {} cells instead of {expected}",
+                chunk.cells.len()
+            ));
+        }
+        Ok(Self { cells: chunk.cells })
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self {
+            cells: vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+        }
+    }
+}
+
+impl Chunk {
+    fn index(local: IVec2) -> usize {
+        (local.y * CHUNK_SIZE + local.x) as usize
+    }
+
+    pub fn get(&self, local: IVec2) -> Option<&PaintedTile> {
+        self.cells[Self::index(local)].as_ref()
+    }
+
+    fn set(&mut self, local: IVec2, tile: Option<PaintedTile>) -> Option<PaintedTile> {
+        std::mem::replace(&mut self.cells[Self::index(local)], tile)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(Option::is_none)
+    }
+
+    /// Replaces every tile `f` returns a new tile for, returning how many
+    /// changed.
+    pub fn remap(&mut self, f: &impl Fn(&PaintedTile) -> Option<PaintedTile>) -> usize {
+        let mut changed = 0;
+        for cell in self.cells.iter_mut().flatten() {
+            if let Some(tile) = f(cell) {
+                *cell = tile;
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// A named layer of painted cells, stored sparsely in chunks that are created
+/// on first paint.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    chunks: HashMap<IVec2, Chunk>,
+}
+
+impl Layer {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            visible: true,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, cell: IVec2) -> Option<&PaintedTile> {
+        self.chunks
+            .get(&MapData::chunk_coord(cell))
+            .and_then(|chunk| chunk.get(MapData::local_coord(cell)))
+    }
+
+    pub fn chunk(&self, coord: IVec2) -> Option<&Chunk> {
+        self.chunks.get(&coord)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&IVec2, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    fn set(&mut self, cell: IVec2, tile: Option<PaintedTile>) -> Option<PaintedTile> {
+        let coord = MapData::chunk_coord(cell);
+        let local = MapData::local_coord(cell);
+        match (self.chunks.get_mut(&coord), tile) {
+            (Some(chunk), tile) => {
+                let previous = chunk.set(local, tile);
+                if chunk.is_empty() {
+                    self.chunks.remove(&coord);
+                }
+                previous
+            }
+            (None, Some(tile)) => self.chunks.entry(coord).or_default().set(local, Some(tile)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A change to one cell, kept so it can be undone.
+#[derive(Clone, Debug)]
+pub struct CellEdit {
+    pub layer: usize,
+    pub cell: IVec2,
+    pub before: Option<PaintedTile>,
+    pub after: Option<PaintedTile>,
+}
+
+/// A change to the map, kept so it can be undone.
+#[derive(Clone, Debug)]
+pub enum MapEdit {
+    Cell(CellEdit),
+    /// A layer added on top of the others.
+    AddLayer(String),
+}
+
+/// The map's layers, bottom first. Painting tools work on the active layer.
+#[derive(Resource)]
+pub struct MapData {
+    layers: Vec<Layer>,
+    active: usize,
+    dirty: HashSet<IVec2>,
+    /// Changes since the last `take_edits`.
+    edits: Vec<MapEdit>,
+    /// Whether the map was cleared since the last `take_cleared`.
+    cleared: bool,
+}
+
+impl Default for MapData {
+    fn default() -> Self {
+        Self {
+            layers: vec![Layer::new("Tiles")],
+            active: 0,
+            dirty: HashSet::new(),
+            edits: Vec::new(),
+            cleared: false,
+        }
+    }
+}
+
+impl MapData {
+    pub fn chunk_coord(cell: IVec2) -> IVec2 {
+        cell.div_euclid(IVec2::splat(CHUNK_SIZE))
+    }
+
+    fn local_coord(cell: IVec2) -> IVec2 {
+        cell.rem_euclid(IVec2::splat(CHUNK_SIZE))
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    /// Adds a layer on top of the others, returning its index.
+    pub fn add_layer(&mut self, name: impl Into<String>) -> usize {
+        let name = name.into();
+        self.edits.push(MapEdit::AddLayer(name.clone()));
+        self.layers.push(Layer::new(name));
+        self.layers.len() - 1
+    }
+
+    /// Removes the top layer, undoing `add_layer`. The first layer stays.
+    pub fn remove_top_layer(&mut self) {
+        if self.layers.len() < 2 {
+            return;
+        }
+        if let Some(layer) = self.layers.pop() {
+            self.dirty.extend(layer.chunks.keys());
+        }
+        self.active = self.active.min(self.layers.len() - 1);
+    }
+
+    pub fn active_layer(&self) -> usize {
+        self.active
+    }
+
+    pub fn set_active_layer(&mut self, layer: usize) {
+        self.active = layer.min(self.layers.len() - 1);
+    }
+
+    pub fn set_visible(&mut self, layer: usize, visible: bool) {
+        self.layers[layer].visible = visible;
+        self.dirty.extend(self.layers[layer].chunks.keys());
+    }
+
+    /// The tile at `cell` on the active layer.
+    pub fn get(&self, cell: IVec2) -> Option<&PaintedTile> {
+        self.layers[self.active].get(cell)
+    }
+
+    pub fn get_in(&self, layer: usize, cell: IVec2) -> Option<&PaintedTile> {
+        self.layers[layer].get(cell)
+    }
+
+    /// Every painted cell of the active layer, in no particular order.
+    pub fn tiles(&self) -> impl Iterator<Item = (IVec2, &PaintedTile)> {
+        self.tiles_in(self.active)
+    }
+
+    /// Every painted cell of the given layer, in no particular order.
+    pub fn tiles_in(&self, layer: usize) -> impl Iterator<Item = (IVec2, &PaintedTile)> {
+        self.layers[layer].chunks.iter().flat_map(|(coord, chunk)| {
+            chunk.cells.iter().enumerate().filter_map(move |(i, tile)| {
+                let local = IVec2::new(i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE);
+                tile.as_ref()
+                    .map(|tile| (*coord * CHUNK_SIZE + local, tile))
+            })
+        })
+    }
+
+    /// Inclusive cell range covered by the stored chunks of every layer.
+    pub fn bounds(&self) -> Option<(IVec2, IVec2)> {
+        let coords = || {
+            self.layers
+                .iter()
+                .flat_map(|layer| layer.chunks.keys().copied())
+        };
+        let min = coords().reduce(IVec2::min)?;
+        let max = coords().reduce(IVec2::max)?;
+        Some((min * CHUNK_SIZE, (max + 1) * CHUNK_SIZE - 1))
+    }
+
+    /// Sets a cell on the active layer, returning its previous contents.
+    pub fn set(&mut self, cell: IVec2, tile: Option<PaintedTile>) -> Option<PaintedTile> {
+        self.set_in(self.active, cell, tile)
+    }
+
+    /// Sets a cell on the given layer, returning its previous contents. Chunks
+    /// left empty are dropped.
+    pub fn set_in(
+        &mut self,
+        layer: usize,
+        cell: IVec2,
+        tile: Option<PaintedTile>,
+    ) -> Option<PaintedTile> {
+        self.dirty.insert(Self::chunk_coord(cell));
+        let previous = self.layers[layer].set(cell, tile.clone());
+        if previous != tile {
+            self.edits.push(MapEdit::Cell(CellEdit {
+                layer,
+                cell,
+                before: previous.clone(),
+                after: tile,
+            }));
+        }
+        previous
+    }
+
+    /// Removes every tile and every layer but an empty first one.
+    pub fn clear(&mut self) {
+        for layer in &self.layers {
+            self.dirty.extend(layer.chunks.keys());
+        }
+        self.layers.truncate(1);
+        self.layers[0].chunks.clear();
+        self.active = 0;
+        self.edits.clear();
+        self.cleared = true;
+    }
+
+    /// Replaces tiles on every layer, see `Chunk::remap`.
+    pub fn remap(&mut self, f: impl Fn(&PaintedTile) -> Option<PaintedTile>) -> usize {
+        let mut changed = 0;
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            for (coord, chunk) in &mut layer.chunks {
+                for (i, cell) in chunk.cells.iter_mut().enumerate() {
+                    let Some(tile) = cell.as_ref().and_then(&f) else {
+                        continue;
+                    };
+                    let local = IVec2::new(i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE);
+                    self.edits.push(MapEdit::Cell(CellEdit {
+                        layer: index,
+                        cell: *coord * CHUNK_SIZE + local,
+                        before: cell.replace(tile.clone()),
+                        after: Some(tile),
+                    }));
+                    self.dirty.insert(*coord);
+                    changed += 1;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Moves the tiles of every layer by `offset`.
+    pub fn shift(&mut self, offset: IVec2) {
+        if offset == IVec2::ZERO {
+            return;
+        }
+        for layer in 0..self.layers.len() {
+            let tiles: Vec<_> = self
+                .tiles_in(layer)
+                .map(|(cell, tile)| (cell, tile.clone()))
+                .collect();
+            for (cell, _) in &tiles {
+                self.set_in(layer, *cell, None);
+            }
+            for (cell, tile) in tiles {
+                self.set_in(layer, cell + offset, Some(tile));
+            }
+        }
+    }
+
+    /// Counts the tiles on every layer that `f` accepts.
+    pub fn count(&self, f: impl Fn(&PaintedTile) -> bool) -> usize {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.chunks.values())
+            .flat_map(|chunk| chunk.cells.iter().flatten())
+            .filter(|tile| f(tile))
+            .count()
+    }
+
+    /// Marks every painted chunk for redrawing, e.g. after the tilesets change.
+    pub fn redraw(&mut self) {
+        for layer in &self.layers {
+            self.dirty.extend(layer.chunks.keys());
+        }
+    }
+
+    /// Chunks touched since the last call, for the renderer to rebuild.
+    pub fn take_dirty(&mut self) -> HashSet<IVec2> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Changes since the last call, for the undo history.
+    pub fn take_edits(&mut self) -> Vec<MapEdit> {
+        std::mem::take(&mut self.edits)
+    }
+
+    /// Whether the map was cleared, e.g. by loading another, since the last
+    /// call.
+    pub fn take_cleared(&mut self) -> bool {
+        std::mem::take(&mut self.cleared)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MapFile {
+    pub mode: MapMode,
+    #[serde(default)]
+    pub orientation: Orientation,
+    #[serde(default)]
+    pub hex: HexLayout,
+    pub cols: usize,
+    pub rows: usize,
+    /// Maps saved before cells could be rectangular have a square `tile_size`.
+    #[serde(alias = "tile_size", deserialize_with = "cell_size")]
+    pub cell_size: Vec2,
+    /// Chunks of maps saved before layers existed, loaded as the first layer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ChunkFile>,
+    #[serde(default)]
+    pub layers: Vec<LayerFile>,
+    /// Animations of the tilesets in use, so the map can be played back
+    /// without the editor's tile definitions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub animations: Vec<TilesetAnimations>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TilesetAnimations {
+    pub tileset: String,
+    pub animations: Vec<TileAnimation>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LayerFile {
+    pub name: String,
+    #[serde(default = "visible_default")]
+    pub visible: bool,
+    pub chunks: Vec<ChunkFile>,
+}
+
+fn cell_size<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum CellSize {
+        Size(Vec2),
+        Square(f32),
+    }
+    Ok(match CellSize::deserialize(deserializer)? {
+        CellSize::Size(size) => size,
+        CellSize::Square(size) => Vec2::splat(size),
+    })
+}
+
+fn visible_default() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChunkFile {
+    pub coord: IVec2,
+    pub chunk: Chunk,
+}
+
+impl MapFile {
+    pub fn new(grid_size: &GridSize, map: &MapData, settings: &MapSettings) -> Self {
+        let layers = map
+            .layers
+            .iter()
+            .map(|layer| {
+                let mut chunks: Vec<_> = layer
+                    .chunks
+                    .iter()
+                    .filter(|(_, chunk)| !chunk.is_empty())
+                    .map(|(coord, chunk)| ChunkFile {
+                        coord: *coord,
+                        chunk: chunk.clone(),
+                    })
+                    .collect();
+                chunks.sort_by_key(|c| (c.coord.y, c.coord.x));
+                LayerFile {
+                    name: layer.name.clone(),
+                    visible: layer.visible,
+                    chunks,
+                }
+            })
+            .collect();
+        let used: HashSet<&str> = map
+            .layers
+            .iter()
+            .flat_map(|layer| layer.chunks.values())
+            .flat_map(|chunk| chunk.cells.iter().flatten())
+            .map(|tile| tile.tileset.as_str())
+            .collect();
+        let mut animations: Vec<_> = settings
+            .atlas_definitions
+            .iter()
+            .filter(|(tileset, definition)| {
+                used.contains(tileset.as_str()) && !definition.animations.is_empty()
+            })
+            .map(|(tileset, definition)| TilesetAnimations {
+                tileset: tileset.clone(),
+                animations: definition.animations.clone(),
+            })
+            .collect();
+        animations.sort_by(|a, b| a.tileset.cmp(&b.tileset));
+        Self {
+            mode: grid_size.mode,
+            orientation: grid_size.orientation,
+            hex: grid_size.hex,
+            cols: grid_size.cols,
+            rows: grid_size.rows,
+            cell_size: grid_size.cell_size,
+            chunks: Vec::new(),
+            layers,
+            animations,
+        }
+    }
+
+    /// Replaces tiles in a saved map without loading it, see `Chunk::remap`.
+    pub fn remap(&mut self, f: impl Fn(&PaintedTile) -> Option<PaintedTile>) -> usize {
+        self.chunks
+            .iter_mut()
+            .chain(self.layers.iter_mut().flat_map(|layer| &mut layer.chunks))
+            .map(|file| file.chunk.remap(&f))
+            .sum()
+    }
+
+    /// Loads the map. Saved animations only fill in for tilesets whose
+    /// definitions declare none.
+    pub fn apply(self, grid_size: &mut GridSize, map: &mut MapData, settings: &mut MapSettings) {
+        grid_size.mode = self.mode;
+        grid_size.orientation = self.orientation;
+        grid_size.hex = self.hex;
+        grid_size.cols = self.cols;
+        grid_size.rows = self.rows;
+        grid_size.cell_size = self.cell_size;
+        map.clear();
+        let mut layers = self.layers;
+        if layers.is_empty() {
+            layers.push(LayerFile {
+                name: map.layers[0].name.clone(),
+                visible: true,
+                chunks: self.chunks,
+            });
+        }
+        map.layers.clear();
+        for file in layers {
+            let mut layer = Layer::new(file.name);
+            layer.visible = file.visible;
+            for ChunkFile { coord, chunk } in file.chunks {
+                if !chunk.is_empty() {
+                    map.dirty.insert(coord);
+                    layer.chunks.insert(coord, chunk);
+                }
+            }
+            map.layers.push(layer);
+        }
+        for saved in self.animations {
+            if let Some(definition) = settings.atlas_definitions.get_mut(&saved.tileset) {
+                if definition.animations.is_empty() {
+                    definition.animations = saved.animations;
+                }
+            }
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum MapFileError {
+    #[error("Could not access map file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse map file: {0}")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("Could not write map file: {0}")]
+    Ron(#[from] ron::Error),
+}
+
+pub fn map_file_contents(file: &MapFile) -> Result<String, MapFileError> {
+    Ok(ron::ser::to_string_pretty(
+        file,
+        ron::ser::PrettyConfig::default(),
+    )?)
+}
+
+pub fn write_map_file(path: &std::path::Path, file: &MapFile) -> Result<(), MapFileError> {
+    std::fs::write(path, map_file_contents(file)?)?;
+    Ok(())
+}
+
+pub fn read_map_file(path: &std::path::Path) -> Result<MapFile, MapFileError> {
+    let contents = std::fs::read(path)?;
+    Ok(ron::de::from_bytes(&contents)?)
+}
+
+#[derive(Event)]
+pub struct SaveMapEvent(pub PathBuf);
+
+#[derive(Event)]
+pub struct LoadMapEvent(pub PathBuf);
+
+fn save_map(
+    mut reader: EventReader<SaveMapEvent>,
+    grid_size: Res<GridSize>,
+    map: Res<MapData>,
+    settings: Res<MapSettings>,
+) {
+    for event in reader.read() {
+        match write_map_file(&event.0, &MapFile::new(&grid_size, &map, &settings)) {
+            Ok(()) => info!("Saved map to {}", event.0.display()),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+fn load_map(
+    mut reader: EventReader<LoadMapEvent>,
+    mut grid_size: ResMut<GridSize>,
+    mut map: ResMut<MapData>,
+    mut settings: ResMut<MapSettings>,
+) {
+    for event in reader.read() {
+        match read_map_file(&event.0) {
+            Ok(file) => file.apply(&mut grid_size, &mut map, &mut settings),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transforms() -> Vec<TileTransform> {
+        (0..8)
+            .map(|bits| TileTransform {
+                flip_x: bits & 1 != 0,
+                flip_y: bits & 2 != 0,
+                rotated: bits & 4 != 0,
+            })
+            .collect()
+    }
+
+    /// Where Tiled draws a frame's corner, `y` up, for the flip bits of a GID.
+    fn tiled_corner(flags: u32, corner: IVec2) -> IVec2 {
+        // Tiled works with `y` down and applies the diagonal flip first.
+        let mut point = IVec2::new(corner.x, -corner.y);
+        if flags & 0x2000_0000 != 0 {
+            point = IVec2::new(point.y, point.x);
+        }
+        if flags & 0x8000_0000 != 0 {
+            point.x = -point.x;
+        }
+        if flags & 0x4000_0000 != 0 {
+            point.y = -point.y;
+        }
+        IVec2::new(point.x, -point.y)
+    }
+
+    #[test]
+    fn tiled_flags_draw_the_frame_the_same_way() {
+        let corners = [IVec2::new(-1, 1), IVec2::new(1, 1), IVec2::new(1, -1)];
+        for transform in transforms() {
+            let flags = transform.tiled_flags();
+            for corner in corners {
+                assert_eq!(
+                    tiled_corner(flags, corner),
+                    transform.apply(corner),
+                    "{transform:?}"
+                );
+            }
+        }
+        let flags: HashSet<_> = transforms().iter().map(|t| t.tiled_flags()).collect();
+        assert_eq!(flags.len(), 8);
+        assert_eq!(TileTransform::default().tiled_flags(), 0);
+    }
+
+    #[test]
+    fn transforms_compose_like_turns_and_mirrors() {
+        for transform in transforms() {
+            let turned = (0..4).fold(transform, |t, _| t.rotate());
+            assert_eq!(turned, transform);
+            assert_eq!(transform.flip_x().flip_x(), transform);
+            assert_eq!(transform.flip_y().flip_y(), transform);
+            assert_eq!(transform.rotate().rotate(), transform.flip_x().flip_y());
+            let offset = IVec2::new(2, 1);
+            assert_eq!(
+                transform.rotate().apply(offset),
+                TileTransform::default()
+                    .rotate()
+                    .apply(transform.apply(offset))
+            );
+        }
+    }
+
+    fn chunk_source(cells: usize) -> String {
+        format!("(cells: [{}])", vec!["None"; cells].join(", "))
+    }
+
+    #[test]
+    fn chunk_round_trips() {
+        let mut chunk = Chunk::default();
+        chunk.set(
+            IVec2::new(3, 4),
+            Some(PaintedTile {
+                tileset: "grass.png".into(),
+                index: 2,
+                transform: default(),
+            }),
+        );
+        let source = ron::to_string(&chunk).unwrap();
+        let read: Chunk = ron::from_str(&source).unwrap();
+        assert_eq!(read.get(IVec2::new(3, 4)), chunk.get(IVec2::new(3, 4)));
+    }
+
+    #[test]
+    fn old_tile_size_is_read_as_square_cells() {
+        let old: MapFile =
+            ron::from_str("(mode: Bounded, cols: 4, rows: 3, tile_size: 24.0)").unwrap();
+        assert_eq!(old.cell_size, Vec2::splat(24.));
+        let grid_size = GridSize {
+            cell_size: Vec2::new(32., 16.),
+            ..default()
+        };
+        let source = ron::to_string(&MapFile::new(
+            &grid_size,
+            &MapData::default(),
+            &MapSettings::default(),
+        ))
+        .unwrap();
+        let new: MapFile = ron::from_str(&source).unwrap();
+        assert_eq!(new.cell_size, Vec2::new(32., 16.));
+    }
+
+    #[test]
+    fn chunk_with_wrong_cell_count_is_rejected() {
+        let full = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        assert!(ron::from_str::<Chunk>(&chunk_source(full)).is_ok());
+        assert!(ron::from_str::<Chunk>(&chunk_source(full - 1)).is_err());
+        assert!(ron::from_str::<Chunk>(&chunk_source(full + 1)).is_err());
+    }
+}
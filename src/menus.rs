@@ -1,10 +1,28 @@
-use bevy::prelude::*;
-use bevy_egui::{egui, render_systems::EguiTextureId, EguiContexts};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+use egui_file::FileDialog;
 
 use crate::{
-    assets::{AtlasDefinition, TileDefinition},
-    grid::BrushState,
-    AppSystemSets, MapSettings, TilesData,
+    assets::{AtlasDefinition, DefinitionDiagnostics, TileDefinition},
+    automap::AutomapEvent,
+    dungeon::{DungeonGenerator, DungeonSettings},
+    export::ExportMapEvent,
+    grid::{
+        ActionState, AnimatedTile, Brush, BrushState, BrushTransform, MapMode, Orientation,
+        SelectedBrush, TerrainBrush,
+    },
+    hex::{HexLayout, StaggerAxis, StaggerIndex},
+    map::{LoadMapEvent, MapData, SaveMapEvent},
+    noisegen::{NoiseGenerator, NoiseSettings},
+    packer::{PackerState, TilesetPacker},
+    project::{AddAssetRootEvent, CurrentProject, OpenProjectEvent, SaveProjectEvent},
+    replace::{ReplaceState, TileReplacer},
+    script::{ConsoleState, ScriptConsole},
+    selection::{SelectTool, Selection},
+    stamp::{Stamp, StampBrush},
+    wfc::{WfcGenerator, WfcSettings},
+    wizard::{TilesetWizard, WizardState},
+    AtlasesRebuilt, MapSettings, TilesData,
 };
 
 pub struct MenuPlugin;
@@ -13,8 +31,21 @@ impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ResizeEvent>()
             .init_resource::<MenuAtlasRegistry>()
-            .add_systems(Startup, setup.after(AppSystemSets::LoadingStuff))
-            .add_systems(Update, (example_ui, handle_paint_tile_click))
+            .add_systems(
+                Update,
+                rebuild_atlas_registry.run_if(on_event::<AtlasesRebuilt>()),
+            )
+            .add_systems(
+                Update,
+                (
+                    example_ui,
+                    layers_ui,
+                    diagnostics_ui,
+                    handle_paint_tile_click,
+                    drag_palette_stamp.after(handle_paint_tile_click),
+                    animate_palette,
+                ),
+            )
             .add_systems(
                 Update,
                 (selected_tile_set).run_if(resource_exists_and_changed::<MapSettings>()),
@@ -24,7 +55,10 @@ impl Plugin for MenuPlugin {
 
 #[derive(Debug, Clone)]
 struct GridSettings {
-    tile_size: f32,
+    mode: MapMode,
+    orientation: Orientation,
+    hex: HexLayout,
+    cell_size: Vec2,
     grid_width: u32,
     grid_height: u32,
 }
@@ -32,7 +66,10 @@ struct GridSettings {
 impl Default for GridSettings {
     fn default() -> Self {
         Self {
-            tile_size: 64.,
+            mode: MapMode::default(),
+            orientation: Orientation::default(),
+            hex: HexLayout::default(),
+            cell_size: Vec2::splat(64.),
             grid_width: 6,
             grid_height: 4,
         }
@@ -40,43 +77,97 @@ impl Default for GridSettings {
 }
 #[derive(Event)]
 pub struct ResizeEvent {
-    pub tile_size: f32,
+    pub mode: MapMode,
+    pub orientation: Orientation,
+    pub hex: HexLayout,
+    pub cell_size: Vec2,
     pub grid_width: u32,
     pub grid_height: u32,
+    /// Erase every painted cell as part of the resize.
+    pub clear: bool,
 }
 
 impl From<GridSettings> for ResizeEvent {
     fn from(value: GridSettings) -> Self {
         Self {
-            tile_size: value.tile_size,
+            mode: value.mode,
+            orientation: value.orientation,
+            hex: value.hex,
+            cell_size: value.cell_size,
             grid_width: value.grid_width,
             grid_height: value.grid_height,
+            clear: false,
         }
     }
 }
 
+enum MapFileAction {
+    Open,
+    Save,
+    Export,
+    OpenProject,
+    SaveProject,
+    AddFolder,
+}
+
+#[derive(SystemParam)]
+struct FileEvents<'w> {
+    save: EventWriter<'w, SaveMapEvent>,
+    load: EventWriter<'w, LoadMapEvent>,
+    export: EventWriter<'w, ExportMapEvent>,
+    open_project: EventWriter<'w, OpenProjectEvent>,
+    save_project: EventWriter<'w, SaveProjectEvent>,
+    add_root: EventWriter<'w, AddAssetRootEvent>,
+}
+
+/// The active tool: a painting brush, or the selection marquee.
+#[derive(SystemParam)]
+struct Tools<'w> {
+    brush: Res<'w, State<BrushState>>,
+    next_brush: ResMut<'w, NextState<BrushState>>,
+    action: Res<'w, State<ActionState>>,
+    next_action: ResMut<'w, NextState<ActionState>>,
+    select_tool: ResMut<'w, SelectTool>,
+}
+
+/// Tool windows opened from the left panel.
+#[derive(SystemParam)]
+struct ToolWindows<'w> {
+    wizard: ResMut<'w, TilesetWizard>,
+    packer: ResMut<'w, TilesetPacker>,
+    replacer: ResMut<'w, TileReplacer>,
+    noise: ResMut<'w, NoiseGenerator>,
+    wfc: ResMut<'w, WfcGenerator>,
+    dungeon: ResMut<'w, DungeonGenerator>,
+    script: ResMut<'w, ScriptConsole>,
+}
+
+/// The open/save/export map dialog, if one is showing.
+#[derive(Default)]
+struct MapFileDialog(Option<(MapFileAction, FileDialog)>);
+
 #[derive(Resource, Default)]
 struct MenuAtlasRegistry(Vec<MenuImageAtlasItem>);
 
+// Not drawn yet; kept so egui panels can show atlas frames.
+#[allow(dead_code)]
 struct MenuImageAtlasItem {
     id: egui::TextureId,
     loc: Rect,
 }
 
-fn setup(
+fn rebuild_atlas_registry(
     mut contexts: EguiContexts,
     state: Res<MapSettings>,
     atlases: Res<Assets<TextureAtlas>>,
     mut registry: ResMut<MenuAtlasRegistry>,
 ) {
-    for (_path, handle) in &state.atlases {
+    registry.0.clear();
+    for handle in state.atlases.values() {
         if let Some(h) = atlases.get(handle) {
             let id = contexts.add_image(h.texture.clone_weak());
             for texture in &h.textures {
-                let item = MenuImageAtlasItem {
-                    id,
-                    loc: texture.clone(),
-                };
+                let item = MenuImageAtlasItem { id, loc: *texture };
                 registry.0.push(item);
             }
         }
@@ -94,102 +185,383 @@ fn example_ui(
     tile_assets: Res<Assets<TileDefinition>>,
     mut settings: Local<GridSettings>,
     mut resize_events: EventWriter<ResizeEvent>,
-    asset_server: Res<AssetServer>,
-    atlases: Res<Assets<TextureAtlas>>,
-    brush_state: Res<State<BrushState>>,
-    mut next_brush_state: ResMut<NextState<BrushState>>,
+    mut map_dialog: Local<MapFileDialog>,
+    mut file_events: FileEvents,
+    mut tools: Tools,
+    mut terrain_brush: ResMut<TerrainBrush>,
+    mut windows: ToolWindows,
+    mut brush_transform: ResMut<BrushTransform>,
+    project: Res<CurrentProject>,
 ) {
     let grid_settings = egui::SidePanel::left("grid-settings");
     //let ui_window = egui::Window::new("main");
     grid_settings.show(contexts.ctx_mut(), |ui| {
-        ui.add(egui::Slider::new(&mut settings.tile_size, 0.0..=100.0).text("Tile Size"));
-        ui.add(egui::Slider::new(&mut settings.grid_width, 0..=50).text("Grid Width"));
-        ui.add(egui::Slider::new(&mut settings.grid_height, 0..=50).text("Grid Height"));
+        ui.horizontal(|hui| {
+            hui.selectable_value(&mut settings.mode, MapMode::Bounded, "Bounded");
+            hui.selectable_value(&mut settings.mode, MapMode::Infinite, "Infinite");
+        });
+        ui.horizontal(|hui| {
+            hui.selectable_value(
+                &mut settings.orientation,
+                Orientation::Orthogonal,
+                "Orthogonal",
+            );
+            hui.selectable_value(
+                &mut settings.orientation,
+                Orientation::Isometric,
+                "Isometric",
+            );
+            hui.selectable_value(
+                &mut settings.orientation,
+                Orientation::Hexagonal,
+                "Hexagonal",
+            );
+        });
+        ui.add(egui::Slider::new(&mut settings.cell_size.x, 1.0..=128.0).text("Cell Width"));
+        ui.add(egui::Slider::new(&mut settings.cell_size.y, 1.0..=128.0).text("Cell Height"));
+        if settings.orientation == Orientation::Hexagonal {
+            let hex = &mut settings.hex;
+            ui.horizontal(|hui| {
+                hui.selectable_value(&mut hex.stagger_axis, StaggerAxis::Y, "Pointy-top");
+                hui.selectable_value(&mut hex.stagger_axis, StaggerAxis::X, "Flat-top");
+            });
+            ui.horizontal(|hui| {
+                hui.label("Stagger");
+                hui.selectable_value(&mut hex.stagger_index, StaggerIndex::Odd, "Odd");
+                hui.selectable_value(&mut hex.stagger_index, StaggerIndex::Even, "Even");
+            });
+            // Sides longer than the cell along the stagger axis bend the corners in.
+            let max_side = match settings.hex.stagger_axis {
+                StaggerAxis::Y => settings.cell_size.y,
+                StaggerAxis::X => settings.cell_size.x,
+            };
+            settings.hex.side_length = settings.hex.side_length.min(max_side);
+            ui.add(
+                egui::Slider::new(&mut settings.hex.side_length, 0.0..=max_side)
+                    .text("Hex Side Length"),
+            );
+        }
+        ui.add_enabled_ui(settings.mode == MapMode::Bounded, |ui| {
+            ui.add(egui::Slider::new(&mut settings.grid_width, 0..=50).text("Grid Width"));
+            ui.add(egui::Slider::new(&mut settings.grid_height, 0..=50).text("Grid Height"));
+        });
 
         // ui.label(state.tile_folder.clone().unwrap_or(String::from("None")));
 
         ui.heading("Tiles");
         ui.vertical(|vui| {
-            if let Some(tile_a) = tile_assets.get(&tile_handle.0) {
-                for tile in &tile_a.tiles {
-                    if vui
-                        .selectable_label(
-                            if let Some(t) = &state.paint_tile {
-                                t.name == tile.name
-                            } else {
-                                false
-                            },
-                            tile.name.clone(),
-                        )
-                        .clicked()
-                    {
-                        state.paint_tile = Some((*tile).clone());
-                    }
+            for tile in tile_handle.tiles(&tile_assets) {
+                if vui
+                    .selectable_label(
+                        if let Some(t) = &state.paint_tile {
+                            t.name == tile.name
+                        } else {
+                            false
+                        },
+                        tile.name.clone(),
+                    )
+                    .clicked()
+                {
+                    state.paint_tile = Some((*tile).clone());
                 }
             }
+            let mut transform = brush_transform.0;
+            vui.horizontal(|hui| {
+                hui.checkbox(&mut transform.flip_x, "Flip X");
+                hui.checkbox(&mut transform.flip_y, "Flip Y");
+                hui.checkbox(&mut transform.rotated, "Rotate");
+            });
+            if transform != brush_transform.0 {
+                brush_transform.0 = transform;
+            }
+            if vui.button("New Tileset").clicked() && windows.wizard.0.is_none() {
+                windows.wizard.0 = Some(WizardState::default());
+            }
+            if vui.button("Pack Tilesets").clicked() && windows.packer.0.is_none() {
+                windows.packer.0 = Some(PackerState::default());
+            }
+            if vui.button("Replace Tiles").clicked() && windows.replacer.0.is_none() {
+                windows.replacer.0 = Some(ReplaceState::default());
+            }
+            if vui.button("Noise Terrain").clicked() && windows.noise.0.is_none() {
+                windows.noise.0 = Some(NoiseSettings::default());
+            }
+            if vui.button("Wave Function Collapse").clicked() && windows.wfc.0.is_none() {
+                windows.wfc.0 = Some(WfcSettings::default());
+            }
+            if vui.button("Dungeon").clicked() && windows.dungeon.0.is_none() {
+                windows.dungeon.0 = Some(DungeonSettings::default());
+            }
+            if vui.button("Script").clicked() && windows.script.0.is_none() {
+                windows.script.0 = Some(ConsoleState::default());
+            }
             if vui.button("Apply").clicked() {
-                // state.tile_size = settings.tile_size;
                 // state.grid_width = settings.grid_width;
                 // state.grid_height = settings.grid_height;
                 resize_events.send(ResizeEvent::from((*settings).clone()));
             }
+            if vui.button("New Map").clicked() {
+                resize_events.send(ResizeEvent {
+                    clear: true,
+                    ..ResizeEvent::from((*settings).clone())
+                });
+            }
+        });
+
+        if let Some(tile) = state.paint_tile.as_ref().filter(|t| !t.terrains.is_empty()) {
+            ui.heading("Terrains");
+            for set in &tile.terrains {
+                let selected = terrain_brush
+                    .0
+                    .as_ref()
+                    .is_some_and(|(path, s)| *path == tile.path && s.name == set.name);
+                if ui.selectable_label(selected, set.name.clone()).clicked() {
+                    terrain_brush.0 = Some((tile.path.clone(), set.clone()));
+                    tools.next_action.set(ActionState::Paint);
+                }
+            }
+        }
+
+        ui.heading("Project");
+        ui.label(&project.project.name);
+        ui.horizontal(|hui| {
+            if hui.button("Open Project").clicked() {
+                let mut dialog = FileDialog::open_file(None);
+                dialog.open();
+                map_dialog.0 = Some((MapFileAction::OpenProject, dialog));
+            }
+            if hui.button("Save Project").clicked() {
+                let mut dialog =
+                    FileDialog::save_file(project.path.clone()).default_filename("project.ron");
+                dialog.open();
+                map_dialog.0 = Some((MapFileAction::SaveProject, dialog));
+            }
+        });
+        for root in &project.project.asset_roots {
+            ui.label(format!("project:// {}", root.display()));
+        }
+        if ui.button("Add Folder").clicked() {
+            let mut dialog = FileDialog::select_folder(None);
+            dialog.open();
+            map_dialog.0 = Some((MapFileAction::AddFolder, dialog));
+        }
+        for map in &project.project.maps {
+            if ui.button(map.to_string_lossy()).clicked() {
+                file_events.load.send(LoadMapEvent(project.resolve(map)));
+            }
+        }
+
+        ui.heading("Map");
+        ui.horizontal(|hui| {
+            if hui.button("Open").clicked() {
+                let mut dialog = FileDialog::open_file(None);
+                dialog.open();
+                map_dialog.0 = Some((MapFileAction::Open, dialog));
+            }
+            if hui.button("Save").clicked() {
+                let mut dialog = FileDialog::save_file(None).default_filename("map.ron");
+                dialog.open();
+                map_dialog.0 = Some((MapFileAction::Save, dialog));
+            }
+            if hui.button("Export TMX").clicked() {
+                let mut dialog = FileDialog::save_file(None).default_filename("map.tmx");
+                dialog.open();
+                map_dialog.0 = Some((MapFileAction::Export, dialog));
+            }
         });
     });
 
+    if let Some((action, dialog)) = &mut map_dialog.0 {
+        if dialog.show(contexts.ctx_mut()).selected() {
+            if let Some(path) = dialog.path().map(|p| p.to_path_buf()) {
+                match action {
+                    MapFileAction::Open => file_events.load.send(LoadMapEvent(path)),
+                    MapFileAction::Save => file_events.save.send(SaveMapEvent(path)),
+                    MapFileAction::Export => file_events.export.send(ExportMapEvent(path)),
+                    MapFileAction::OpenProject => {
+                        file_events.open_project.send(OpenProjectEvent(path))
+                    }
+                    MapFileAction::SaveProject => {
+                        file_events.save_project.send(SaveProjectEvent(path))
+                    }
+                    MapFileAction::AddFolder => file_events.add_root.send(AddAssetRootEvent(path)),
+                }
+            }
+        }
+    }
+
     let brush_settings = egui::SidePanel::right("brush-settings");
     brush_settings.show(contexts.ctx_mut(), |ui| {
         ui.heading("Brushes");
-        if ui
-            .selectable_label(brush_state.eq(&BrushState::Single), "Brush")
-            .clicked()
-        {
-            next_brush_state.set(BrushState::Single);
-        }
-        if ui
-            .selectable_label(brush_state.eq(&BrushState::Fill), "Fill")
-            .clicked()
-        {
-            next_brush_state.set(BrushState::Fill);
+        let painting = *tools.action.get() == ActionState::Paint;
+        for (brush, label) in [
+            (BrushState::Single, "Brush"),
+            (BrushState::Fill, "Fill"),
+            (BrushState::Line, "Line"),
+            (BrushState::Rect, "Rect"),
+        ] {
+            let selected = painting && *tools.brush.get() == brush;
+            if ui.selectable_label(selected, label).clicked() {
+                tools.next_brush.set(brush);
+                tools.next_action.set(ActionState::Paint);
+            }
+        }
+        for (tool, label) in [
+            (SelectTool::Marquee, "Select"),
+            (SelectTool::Wand, "Magic Wand"),
+            (SelectTool::SameTile, "Select Tile"),
+        ] {
+            let selected = !painting && *tools.select_tool == tool;
+            if ui.selectable_label(selected, label).clicked() {
+                if *tools.select_tool != tool {
+                    *tools.select_tool = tool;
+                }
+                tools.next_action.set(ActionState::Select);
+            }
         }
     });
 }
 
-fn selected_tile_set(mut commands: Commands, state: Res<MapSettings>) {
+fn layers_ui(
+    mut contexts: EguiContexts,
+    mut map: ResMut<MapData>,
+    mut new_layer: Local<String>,
+    mut automap_events: EventWriter<AutomapEvent>,
+    selection: Res<Selection>,
+) {
+    egui::Window::new("Layers").show(contexts.ctx_mut(), |ui| {
+        // Listed top first, like the draw order seen on screen.
+        for layer in (0..map.layers().len()).rev() {
+            ui.horizontal(|hui| {
+                let mut visible = map.layers()[layer].visible;
+                if hui.checkbox(&mut visible, "").changed() {
+                    map.set_visible(layer, visible);
+                }
+                let name = map.layers()[layer].name.clone();
+                if hui
+                    .selectable_label(map.active_layer() == layer, name)
+                    .clicked()
+                {
+                    map.set_active_layer(layer);
+                }
+            });
+        }
+        ui.horizontal(|hui| {
+            hui.text_edit_singleline(&mut *new_layer);
+            let name = new_layer.trim().to_string();
+            let valid = !name.is_empty() && map.layer_index(&name).is_none();
+            if hui.add_enabled(valid, egui::Button::new("Add")).clicked() {
+                let layer = map.add_layer(name);
+                map.set_active_layer(layer);
+                new_layer.clear();
+            }
+        });
+        ui.horizontal(|hui| {
+            if hui.button("Automap").clicked() {
+                automap_events.send(AutomapEvent { area: None });
+            }
+            let selected = selection.area.is_some()
+                || selection
+                    .picked
+                    .as_ref()
+                    .is_some_and(|cells| !cells.is_empty());
+            if hui
+                .add_enabled(selected, egui::Button::new("Automap Selection"))
+                .clicked()
+            {
+                automap_events.send(AutomapEvent {
+                    area: Some(selection.cells()),
+                });
+            }
+        });
+    });
+}
+
+/// Lists problems found in the definition files, if there are any.
+fn diagnostics_ui(mut contexts: EguiContexts, diagnostics: Res<DefinitionDiagnostics>) {
+    let entries = diagnostics.entries();
+    if entries.is_empty() {
+        return;
+    }
+    egui::Window::new("Diagnostics").show(contexts.ctx_mut(), |ui| {
+        for entry in &entries {
+            let location = match entry.position {
+                Some(position) => format!("{}:{position}", entry.file.display()),
+                None => entry.file.display().to_string(),
+            };
+            ui.horizontal(|hui| {
+                hui.monospace(location);
+                hui.colored_label(egui::Color32::LIGHT_RED, &entry.message);
+            });
+        }
+    });
+}
+
+fn selected_tile_set(
+    mut commands: Commands,
+    state: Res<MapSettings>,
+    palettes: Query<Entity, With<Palette>>,
+) {
+    for palette in &palettes {
+        commands.entity(palette).despawn_recursive();
+    }
     if let Some(tile) = &state.paint_tile {
         if let Some(atlas) = state.atlases.get(&tile.path) {
-            display_selected_tile_set(&mut commands, atlas.clone(), &tile.atlas_definition);
+            display_selected_tile_set(
+                &mut commands,
+                &tile.path,
+                atlas.clone(),
+                state.atlas_definitions.get(&tile.path),
+            );
         }
     }
 }
 
+#[derive(Component)]
+struct Palette;
+
 #[derive(Component)]
 struct Paintable;
 
+/// Size of a frame in the palette, in pixels.
+const PALETTE_FRAME: f32 = 64.;
+
+/// Tint of the palette frames in the stamp being picked or painted.
+const STAMP_TINT: Color = Color::rgb(0.6, 0.8, 1.0);
+
 fn display_selected_tile_set(
     commands: &mut Commands,
+    tileset: &str,
     texture_atlas: Handle<TextureAtlas>,
-    atlas_def: &Option<AtlasDefinition>,
+    atlas_def: Option<&AtlasDefinition>,
 ) {
     commands
-        .spawn(NodeBundle {
-            style: Style {
-                height: Val::Percent(100.0),
-                width: Val::Percent(100.0),
-                flex_direction: FlexDirection::Column,
-                justify_content: JustifyContent::End,
-                align_items: AlignItems::Center,
-                padding: UiRect::all(Val::Px(10.)),
+        .spawn((
+            Palette,
+            NodeBundle {
+                style: Style {
+                    height: Val::Percent(100.0),
+                    width: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::End,
+                    align_items: AlignItems::Center,
+                    padding: UiRect::all(Val::Px(10.)),
+                    ..default()
+                },
                 ..default()
             },
-            ..default()
-        })
+        ))
         .with_children(|parent| {
+            // Wrapped to the atlas's width, so frames sit as they do in the image.
+            let columns = atlas_def.map_or(1, |ad| ad.columns.max(1));
             parent
                 .spawn(NodeBundle {
                     style: Style {
                         flex_direction: FlexDirection::Row,
-                        justify_content: JustifyContent::Center,
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Start,
                         align_items: AlignItems::Center,
+                        max_width: Val::Px(columns as f32 * PALETTE_FRAME),
                         ..default()
                     },
                     ..default()
@@ -197,11 +569,11 @@ fn display_selected_tile_set(
                 .with_children(|parent| {
                     if let Some(ad) = atlas_def {
                         for i in 0..(ad.rows * ad.columns) {
-                            parent.spawn((
+                            let mut image = parent.spawn((
                                 AtlasImageBundle {
                                     style: Style {
-                                        width: Val::Px(64.),
-                                        height: Val::Px(64.),
+                                        width: Val::Px(PALETTE_FRAME),
+                                        height: Val::Px(PALETTE_FRAME),
                                         ..default()
                                     },
                                     texture_atlas: texture_atlas.clone(),
@@ -213,27 +585,98 @@ fn display_selected_tile_set(
                                 },
                                 Interaction::default(),
                                 Paintable,
+                                Brush {
+                                    tileset: tileset.to_string(),
+                                    index: i,
+                                },
                             ));
+                            if let Some(animation) = ad.animation(i) {
+                                image.insert(AnimatedTile(animation.clone()));
+                            }
                         }
                     }
                 });
         });
 }
 
+fn animate_palette(time: Res<Time>, mut images: Query<(&AnimatedTile, &mut UiTextureAtlasImage)>) {
+    for (animated, mut image) in &mut images {
+        let index = animated.0.frame_at(time.elapsed());
+        if image.index != index {
+            image.index = index;
+        }
+    }
+}
+
 fn handle_paint_tile_click(
-    interactions_q: Query<&Interaction, (Changed<Interaction>, With<Paintable>)>,
+    mut commands: Commands,
+    interactions_q: Query<(Entity, &Interaction), (Changed<Interaction>, With<Paintable>)>,
+    selected_q: Query<Entity, With<SelectedBrush>>,
+    mut terrain_brush: ResMut<TerrainBrush>,
+    mut stamp: ResMut<StampBrush>,
+    mut next_action_state: ResMut<NextState<ActionState>>,
 ) {
-    for interaction in &interactions_q {
-        match *interaction {
-            Interaction::Pressed => {
-                info!("Pressed");
+    for (entity, interaction) in &interactions_q {
+        if *interaction == Interaction::Pressed {
+            for selected in &selected_q {
+                commands.entity(selected).remove::<SelectedBrush>();
             }
-            Interaction::Hovered => {
-                info!("Hovered");
+            commands.entity(entity).insert(SelectedBrush);
+            terrain_brush.0 = None;
+            stamp.0 = None;
+            next_action_state.set(ActionState::Paint);
+        }
+    }
+}
+
+/// Dragging across the palette picks the rectangle of frames between the
+/// pressed and released frames as a stamp.
+fn drag_palette_stamp(
+    mouse: Res<Input<MouseButton>>,
+    mut frames: Query<(&Interaction, &Brush, &mut BackgroundColor), With<Paintable>>,
+    state: Res<MapSettings>,
+    mut drag: Local<Option<(String, usize, usize)>>,
+    mut stamp: ResMut<StampBrush>,
+) {
+    for (interaction, brush, _) in &frames {
+        match (interaction, &mut *drag) {
+            (Interaction::Pressed, None) => {
+                *drag = Some((brush.tileset.clone(), brush.index, brush.index));
             }
-            Interaction::None => {
-                info!("None");
+            (Interaction::Hovered, Some((tileset, _, end))) if *tileset == brush.tileset => {
+                *end = brush.index;
             }
+            _ => {}
+        }
+    }
+    let columns = |tileset: &str| {
+        state
+            .atlas_definitions
+            .get(tileset)
+            .map_or(1, |d| d.columns)
+    };
+    if mouse.just_released(MouseButton::Left) {
+        if let Some((tileset, start, end)) = drag.take() {
+            if start != end {
+                stamp.0 = Some(Stamp::from_palette(&tileset, columns(&tileset), start, end));
+            }
+        }
+    }
+
+    let picked = match (&*drag, &stamp.0) {
+        (Some((tileset, start, end)), _) => {
+            Stamp::from_palette(tileset, columns(tileset), *start, *end).cells
+        }
+        (None, Some(stamp)) => stamp.cells.clone(),
+        (None, None) => Vec::new(),
+    };
+    for (_, brush, mut color) in &mut frames {
+        let in_stamp = picked
+            .iter()
+            .any(|c| c.tile.tileset == brush.tileset && c.tile.index == brush.index);
+        let tint = if in_stamp { STAMP_TINT } else { Color::WHITE };
+        if color.0 != tint {
+            color.0 = tint;
         }
     }
 }
@@ -0,0 +1,329 @@
+//! Noise terrain: layers of value, Perlin or simplex noise are summed into a
+//! height for every cell, and bands of height are painted with frames of one
+//! tileset, e.g. water below 0.4, grass up to 0.7 and rock above.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, Simplex, Value};
+
+use crate::{
+    assets::TileDefinition,
+    generator::{region, size_ui, Generated, GeneratedPreview, DEFAULT_SIZE},
+    grid::{rect, GridSize},
+    map::{PaintedTile, TileTransform},
+    MapSettings, TilesData,
+};
+
+pub struct NoiseGenPlugin;
+
+impl Plugin for NoiseGenPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NoiseGenerator>()
+            .add_systems(Update, noise_ui);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Value,
+    Perlin,
+    Simplex,
+}
+
+impl NoiseKind {
+    const ALL: [Self; 3] = [Self::Value, Self::Perlin, Self::Simplex];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Value => "Value",
+            Self::Perlin => "Perlin",
+            Self::Simplex => "Simplex",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoiseLayer {
+    pub kind: NoiseKind,
+    /// Cells across a feature of the first octave.
+    pub scale: f64,
+    pub octaves: usize,
+    /// Share of the layer in the summed height.
+    pub weight: f64,
+}
+
+impl NoiseLayer {
+    fn noise(&self, seed: u32) -> Box<dyn NoiseFn<f64, 2>> {
+        let frequency = 1. / self.scale.max(1.);
+        let octaves = self.octaves.clamp(1, Fbm::<Perlin>::MAX_OCTAVES);
+        match self.kind {
+            NoiseKind::Value => Box::new(
+                Fbm::<Value>::new(seed)
+                    .set_frequency(frequency)
+                    .set_octaves(octaves),
+            ),
+            NoiseKind::Perlin => Box::new(
+                Fbm::<Perlin>::new(seed)
+                    .set_frequency(frequency)
+                    .set_octaves(octaves),
+            ),
+            NoiseKind::Simplex => Box::new(
+                Fbm::<Simplex>::new(seed)
+                    .set_frequency(frequency)
+                    .set_octaves(octaves),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Band {
+    /// Highest height painted with this band, from 0 to 1.
+    pub up_to: f64,
+    /// Frame of the tileset, or `None` to leave the cells empty.
+    pub index: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoiseSettings {
+    pub seed: u32,
+    pub tileset: String,
+    pub layers: Vec<NoiseLayer>,
+    /// Checked in order; heights above every band use the last one.
+    pub bands: Vec<Band>,
+    /// Size generated on infinite maps.
+    pub size: UVec2,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            tileset: String::new(),
+            layers: vec![
+                NoiseLayer {
+                    kind: NoiseKind::Perlin,
+                    scale: 24.,
+                    octaves: 4,
+                    weight: 1.,
+                },
+                NoiseLayer {
+                    kind: NoiseKind::Simplex,
+                    scale: 6.,
+                    octaves: 1,
+                    weight: 0.25,
+                },
+            ],
+            bands: vec![
+                Band {
+                    up_to: 0.4,
+                    index: None,
+                },
+                Band {
+                    up_to: 0.7,
+                    index: Some(0),
+                },
+                Band {
+                    up_to: 1.,
+                    index: Some(1),
+                },
+            ],
+            size: DEFAULT_SIZE,
+        }
+    }
+}
+
+impl NoiseSettings {
+    /// Heights from 0 to 1 for a block of cells, row by row.
+    pub fn heights(&self, min: IVec2, size: UVec2) -> Vec<f64> {
+        let layers: Vec<_> = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| (layer.noise(self.seed.wrapping_add(i as u32)), layer.weight))
+            .collect();
+        let total: f64 = layers.iter().map(|(_, weight)| weight.abs()).sum();
+        let mut heights = Vec::with_capacity((size.x * size.y) as usize);
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                let point = [(min.x + x) as f64, (min.y + y) as f64];
+                let sum: f64 = layers
+                    .iter()
+                    .map(|(noise, weight)| noise.get(point) * weight)
+                    .sum();
+                let height = if total > 0. { sum / total } else { 0. };
+                heights.push(((height + 1.) / 2.).clamp(0., 1.));
+            }
+        }
+        heights
+    }
+
+    fn band(&self, height: f64) -> Option<&Band> {
+        self.bands
+            .iter()
+            .find(|band| height <= band.up_to)
+            .or(self.bands.last())
+    }
+
+    pub fn generate(&self, min: IVec2, size: UVec2) -> Generated {
+        let mut generated = Generated::new(format!("Noise, seed {}", self.seed));
+        let cells = rect(min, min + size.as_ivec2() - 1);
+        for (cell, height) in cells.into_iter().zip(self.heights(min, size)) {
+            let tile = self
+                .band(height)
+                .and_then(|band| band.index)
+                .map(|index| PaintedTile {
+                    tileset: self.tileset.clone(),
+                    index,
+                    transform: TileTransform::default(),
+                });
+            generated.cells.push((cell, tile));
+        }
+        generated
+    }
+}
+
+/// The open noise generator window, if any.
+#[derive(Resource, Default)]
+pub struct NoiseGenerator(pub Option<NoiseSettings>);
+
+fn noise_ui(
+    mut contexts: EguiContexts,
+    mut generator: ResMut<NoiseGenerator>,
+    mut preview: ResMut<GeneratedPreview>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    grid_size: Res<GridSize>,
+) {
+    let Some(noise) = &mut generator.0 else {
+        return;
+    };
+    let tilesets: Vec<_> = tile_handle
+        .tiles(&tile_assets)
+        .filter(|tile| settings.atlases.contains_key(&tile.path))
+        .map(|tile| (tile.path.clone(), tile.name.clone()))
+        .collect();
+    if !tilesets.iter().any(|(path, _)| *path == noise.tileset) {
+        if let Some((path, _)) = tilesets.first() {
+            noise.tileset = path.clone();
+        }
+    }
+    let frames = settings
+        .atlases
+        .get(&noise.tileset)
+        .and_then(|handle| texture_atlases.get(handle))
+        .map_or(1, |atlas| atlas.len().max(1));
+    let mut open = true;
+    let mut generate = false;
+    egui::Window::new("Noise Terrain")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            let selected = tilesets
+                .iter()
+                .find(|(path, _)| *path == noise.tileset)
+                .map_or("", |(_, name)| name.as_str());
+            egui::ComboBox::from_label("Tileset")
+                .selected_text(selected)
+                .show_ui(ui, |cui| {
+                    for (path, name) in &tilesets {
+                        cui.selectable_value(&mut noise.tileset, path.clone(), name);
+                    }
+                });
+            ui.horizontal(|hui| {
+                hui.label("Seed");
+                hui.add(egui::DragValue::new(&mut noise.seed));
+                if hui.button("New Seed").clicked() {
+                    noise.seed = fastrand::u32(..);
+                    generate = true;
+                }
+            });
+            size_ui(ui, &grid_size, &mut noise.size);
+
+            ui.separator();
+            ui.label("Layers");
+            let mut remove = None;
+            for (i, layer) in noise.layers.iter_mut().enumerate() {
+                ui.horizontal(|hui| {
+                    egui::ComboBox::from_id_source(("noise-kind", i))
+                        .selected_text(layer.kind.label())
+                        .show_ui(hui, |cui| {
+                            for kind in NoiseKind::ALL {
+                                cui.selectable_value(&mut layer.kind, kind, kind.label());
+                            }
+                        });
+                    hui.label("Scale");
+                    hui.add(egui::DragValue::new(&mut layer.scale).clamp_range(1.0..=1024.0));
+                    hui.label("Octaves");
+                    hui.add(egui::DragValue::new(&mut layer.octaves).clamp_range(1..=8));
+                    hui.label("Weight");
+                    hui.add(
+                        egui::DragValue::new(&mut layer.weight)
+                            .speed(0.05)
+                            .clamp_range(0.0..=10.0),
+                    );
+                    if hui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                noise.layers.remove(i);
+            }
+            if ui.button("Add Layer").clicked() {
+                noise.layers.push(NoiseLayer {
+                    kind: NoiseKind::Perlin,
+                    scale: 16.,
+                    octaves: 1,
+                    weight: 0.5,
+                });
+            }
+
+            ui.separator();
+            ui.label("Bands, lowest first");
+            let mut remove = None;
+            for (i, band) in noise.bands.iter_mut().enumerate() {
+                ui.horizontal(|hui| {
+                    hui.label("Up to");
+                    hui.add(egui::Slider::new(&mut band.up_to, 0.0..=1.0));
+                    let mut paint = band.index.is_some();
+                    hui.checkbox(&mut paint, "Frame");
+                    let mut index = band.index.unwrap_or(0);
+                    hui.add_enabled(
+                        paint,
+                        egui::DragValue::new(&mut index).clamp_range(0..=frames - 1),
+                    );
+                    band.index = paint.then_some(index);
+                    if hui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                noise.bands.remove(i);
+            }
+            if ui.button("Add Band").clicked() {
+                noise.bands.push(Band {
+                    up_to: 1.,
+                    index: Some(0),
+                });
+            }
+
+            ui.separator();
+            let valid = !noise.tileset.is_empty() && !noise.bands.is_empty();
+            if ui
+                .add_enabled(valid, egui::Button::new("Preview"))
+                .clicked()
+            {
+                generate = true;
+            }
+        });
+    if generate && !noise.tileset.is_empty() {
+        let (min, size) = region(&grid_size, noise.size);
+        preview.0 = Some(noise.generate(min, size));
+    }
+    if !open {
+        generator.0 = None;
+    }
+}
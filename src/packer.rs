@@ -0,0 +1,593 @@
+//! Packs several tilesets into one atlas image with a matching definition
+//! file, and moves painted tiles over to it.
+//!
+//! Frames are laid out on a uniform grid sized to the largest frame, with
+//! transparent padding between them. Extrusion repeats each frame's edge
+//! pixels outwards, so filtering at the edge of a frame never samples its
+//! neighbours.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+use bevy::{
+    asset::io::file::FileAssetReader,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_egui::{egui, EguiContexts};
+use thiserror::Error;
+
+use crate::{
+    assets::{
+        Adjacency, AnimationFrame, AtlasDefinition, TerrainSet, Tile, TileAnimation, TileDefinition,
+    },
+    map::{read_map_file, write_map_file, MapData, MapFileError, PaintedTile},
+    project::CurrentProject,
+    MapSettings, TilesData,
+};
+
+/// Where packed atlases and their definitions are written, under `assets`.
+const PACK_DIR: &str = "packed";
+
+pub struct PackerPlugin;
+
+impl Plugin for PackerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TilesetPacker>()
+            .add_event::<PackTilesetsEvent>()
+            .add_systems(Update, (packer_ui, pack_tilesets).chain());
+    }
+}
+
+/// Pack tilesets into `assets/packed/<name>.png` and `<name>.ron`, and add
+/// the definition to the project.
+#[derive(Event, Clone)]
+pub struct PackTilesetsEvent {
+    pub name: String,
+    /// Paths of the tilesets to pack, which is also the order of their frames.
+    pub tilesets: Vec<String>,
+    /// Transparent pixels between extruded frames.
+    pub padding: u32,
+    /// How far each frame's edge pixels are repeated outwards.
+    pub extrude: u32,
+    /// Move the open map and the project's maps to the packed tileset.
+    pub migrate: bool,
+}
+
+/// The open packer window, if any.
+#[derive(Resource, Default)]
+pub struct TilesetPacker(pub Option<PackerState>);
+
+pub struct PackerState {
+    name: String,
+    selected: Vec<String>,
+    padding: u32,
+    extrude: u32,
+    migrate: bool,
+    error: Option<String>,
+}
+
+impl Default for PackerState {
+    fn default() -> Self {
+        Self {
+            name: "packed".to_string(),
+            selected: Vec::new(),
+            padding: 2,
+            extrude: 1,
+            migrate: true,
+            error: None,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum PackError {
+    #[error("Could not write packed files: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not write definition file: {0}")]
+    Ron(#[from] ron::Error),
+    #[error("Could not write packed image: {0}")]
+    Image(String),
+    #[error("Tileset {0:?} is not loaded")]
+    NotLoaded(String),
+    #[error("No tilesets to pack")]
+    Empty,
+    #[error("{0:?} is not a file name")]
+    BadName(String),
+    #[error("{0}")]
+    Map(#[from] MapFileError),
+}
+
+/// A tileset to pack, with its atlas as built by the editor.
+pub struct PackSource<'a> {
+    pub tile: &'a Tile,
+    pub definition: &'a AtlasDefinition,
+    pub atlas: &'a TextureAtlas,
+    pub image: &'a Image,
+}
+
+/// The packed atlas image and definition entry, and the packed indices of
+/// each source tileset's frames.
+pub struct PackedAtlas {
+    pub image: Image,
+    pub tile: Tile,
+    pub frames: HashMap<String, Range<usize>>,
+}
+
+impl PackedAtlas {
+    /// The packed tile that replaces `tile`, if it came from a packed tileset
+    /// and its index is one of that tileset's frames.
+    pub fn remap(&self, tile: &PaintedTile) -> Option<PaintedTile> {
+        let frames = self.frames.get(&tile.tileset)?;
+        let index = frames.start + tile.index;
+        frames.contains(&index).then(|| PaintedTile {
+            tileset: self.tile.path.clone(),
+            index,
+            transform: tile.transform,
+        })
+    }
+
+    /// `remap`, counting the tiles of packed tilesets left as they were
+    /// because their index is past the end of the tileset.
+    fn remapper<'a>(
+        &'a self,
+        skipped: &'a Cell<usize>,
+    ) -> impl Fn(&PaintedTile) -> Option<PaintedTile> + 'a {
+        move |tile| {
+            let packed = self.remap(tile);
+            if packed.is_none() && self.frames.contains_key(&tile.tileset) {
+                skipped.set(skipped.get() + 1);
+            }
+            packed
+        }
+    }
+}
+
+struct Frame {
+    size: UVec2,
+    /// Rgba8 pixels, row by row.
+    data: Vec<u8>,
+}
+
+fn frames(source: &PackSource) -> Result<Vec<Frame>, PackError> {
+    let converted;
+    let image = if source.image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb {
+        source.image
+    } else {
+        converted = source
+            .image
+            .convert(TextureFormat::Rgba8UnormSrgb)
+            .ok_or_else(|| PackError::NotLoaded(source.tile.path.clone()))?;
+        &converted
+    };
+    let width = image.width() as usize;
+    Ok(source
+        .atlas
+        .textures
+        .iter()
+        .map(|rect| {
+            let min = rect.min.as_uvec2();
+            let size = rect.size().as_uvec2();
+            let mut data = Vec::with_capacity((size.x * size.y * 4) as usize);
+            for y in min.y..min.y + size.y {
+                let start = (y as usize * width + min.x as usize) * 4;
+                data.extend_from_slice(&image.data[start..start + size.x as usize * 4]);
+            }
+            Frame { size, data }
+        })
+        .collect())
+}
+
+fn offset_indices(animation: &TileAnimation, first: usize) -> TileAnimation {
+    TileAnimation {
+        index: first + animation.index,
+        frames: animation
+            .frames
+            .iter()
+            .map(|frame| AnimationFrame {
+                index: first + frame.index,
+                duration: frame.duration,
+            })
+            .collect(),
+    }
+}
+
+/// Packs the frames of every source into one grid atlas, in source order.
+/// Frames smaller than the grid cell are placed by the first source's anchor.
+pub fn pack_atlas(
+    name: &str,
+    path: &str,
+    sources: &[PackSource],
+    padding: u32,
+    extrude: u32,
+) -> Result<PackedAtlas, PackError> {
+    let Some(first_source) = sources.first() else {
+        return Err(PackError::Empty);
+    };
+    let mut all_frames = Vec::new();
+    let mut packed_frames = HashMap::new();
+    let mut animations = Vec::new();
+    let mut terrains = Vec::new();
+    let mut adjacency = Adjacency::default();
+    for source in sources {
+        let first = all_frames.len();
+        all_frames.extend(frames(source)?);
+        packed_frames.insert(source.tile.path.clone(), first..all_frames.len());
+        animations.extend(
+            source
+                .definition
+                .animations
+                .iter()
+                .map(|animation| offset_indices(animation, first)),
+        );
+        terrains.extend(source.tile.terrains.iter().map(|set| {
+            TerrainSet {
+                // Sets from different tilesets may share a name.
+                name: if sources.len() > 1 {
+                    format!("{} {}", source.tile.name, set.name)
+                } else {
+                    set.name.clone()
+                },
+                kind: set.kind,
+                tiles: set
+                    .tiles
+                    .iter()
+                    .map(|(mask, i)| (*mask, first + i))
+                    .collect(),
+            }
+        }));
+        let rules = &source.tile.adjacency;
+        let offset = |(a, b): &(usize, usize)| (first + a, first + b);
+        adjacency
+            .horizontal
+            .extend(rules.horizontal.iter().map(offset));
+        adjacency.vertical.extend(rules.vertical.iter().map(offset));
+        adjacency.weights.extend(
+            rules
+                .weights
+                .iter()
+                .map(|(index, weight)| (first + index, *weight)),
+        );
+    }
+    if all_frames.is_empty() {
+        return Err(PackError::Empty);
+    }
+
+    let cell = all_frames
+        .iter()
+        .map(|frame| frame.size)
+        .reduce(UVec2::max)
+        .unwrap();
+    let columns = (all_frames.len() as f32).sqrt().ceil() as u32;
+    let rows = (all_frames.len() as u32).div_ceil(columns);
+    let step = cell + 2 * extrude + padding;
+    let size = UVec2::new(columns, rows) * step - padding;
+    let mut data = vec![0; (size.x * size.y * 4) as usize];
+    let anchor = first_source.definition.anchor.offset(false);
+    for (i, frame) in all_frames.iter().enumerate() {
+        // Pin the frame's anchor point to the same point of its cell.
+        let slack = (cell - frame.size).as_vec2();
+        let inset = (slack * Vec2::new(anchor.x + 0.5, 0.5 - anchor.y))
+            .round()
+            .as_uvec2();
+        let origin = UVec2::new(i as u32 % columns, i as u32 / columns) * step + extrude;
+        let extent = cell.as_ivec2() + extrude as i32;
+        for y in -(extrude as i32)..extent.y {
+            for x in -(extrude as i32)..extent.x {
+                // Positions outside the cell take the nearest edge pixel.
+                let inner = IVec2::new(x, y).clamp(IVec2::ZERO, cell.as_ivec2() - 1);
+                let local = inner - inset.as_ivec2();
+                if local.cmplt(IVec2::ZERO).any() || local.cmpge(frame.size.as_ivec2()).any() {
+                    continue;
+                }
+                let local = local.as_uvec2();
+                let from = ((local.y * frame.size.x + local.x) * 4) as usize;
+                let target = (origin.as_ivec2() + IVec2::new(x, y)).as_uvec2();
+                let to = ((target.y * size.x + target.x) * 4) as usize;
+                data[to..to + 4].copy_from_slice(&frame.data[from..from + 4]);
+            }
+        }
+    }
+
+    let image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let spacing = padding + 2 * extrude;
+    let definition = AtlasDefinition {
+        tile_size: cell.as_vec2(),
+        columns: columns as usize,
+        rows: rows as usize,
+        padding: (spacing > 0).then(|| Vec2::splat(spacing as f32)),
+        offsest: (extrude > 0).then(|| Vec2::splat(extrude as f32)),
+        render_size: first_source.definition.render_size,
+        anchor: first_source.definition.anchor,
+        animations,
+    };
+    Ok(PackedAtlas {
+        image,
+        tile: Tile {
+            name: name.to_string(),
+            path: path.to_string(),
+            atlas_definition: Some(definition),
+            images: None,
+            terrains,
+            adjacency,
+        },
+        frames: packed_frames,
+    })
+}
+
+fn assets_dir() -> PathBuf {
+    FileAssetReader::get_base_path().join("assets")
+}
+
+fn packer_ui(
+    mut contexts: EguiContexts,
+    mut packer: ResMut<TilesetPacker>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    settings: Res<MapSettings>,
+    mut events: EventWriter<PackTilesetsEvent>,
+) {
+    let Some(state) = &mut packer.0 else {
+        return;
+    };
+    let mut open = true;
+    let mut pack = false;
+    egui::Window::new("Pack Tilesets")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|hui| {
+                hui.label("Name");
+                hui.text_edit_singleline(&mut state.name);
+            });
+            for tile in tile_handle.tiles(&tile_assets) {
+                if !settings.atlases.contains_key(&tile.path) {
+                    continue;
+                }
+                let mut checked = state.selected.contains(&tile.path);
+                if ui.checkbox(&mut checked, &tile.name).changed() {
+                    if checked {
+                        state.selected.push(tile.path.clone());
+                    } else {
+                        state.selected.retain(|path| *path != tile.path);
+                    }
+                }
+            }
+            ui.horizontal(|hui| {
+                hui.label("Padding");
+                hui.add(egui::DragValue::new(&mut state.padding).clamp_range(0..=64));
+                hui.label("Extrude");
+                hui.add(egui::DragValue::new(&mut state.extrude).clamp_range(0..=16));
+            });
+            ui.checkbox(&mut state.migrate, "Move maps to the packed tileset");
+            if let Some(error) = &state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+            let valid = !state.name.is_empty() && !state.selected.is_empty();
+            pack = ui.add_enabled(valid, egui::Button::new("Pack")).clicked();
+        });
+    if pack {
+        events.send(PackTilesetsEvent {
+            name: state.name.clone(),
+            tilesets: state.selected.clone(),
+            padding: state.padding,
+            extrude: state.extrude,
+            migrate: state.migrate,
+        });
+    } else if !open {
+        packer.0 = None;
+    }
+}
+
+fn pack_tilesets(
+    mut reader: EventReader<PackTilesetsEvent>,
+    mut packer: ResMut<TilesetPacker>,
+    mut tile_handle: ResMut<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    images: Res<Assets<Image>>,
+    mut map: ResMut<MapData>,
+    mut project: ResMut<CurrentProject>,
+    asset_server: Res<AssetServer>,
+) {
+    for event in reader.read() {
+        let result = pack_event(
+            event,
+            &tile_handle,
+            &tile_assets,
+            &settings,
+            &texture_atlases,
+            &images,
+        );
+        let packed = match result {
+            Ok(packed) => packed,
+            Err(e) => {
+                error!("{e}");
+                if let Some(state) = &mut packer.0 {
+                    state.error = Some(e.to_string());
+                }
+                continue;
+            }
+        };
+        let definition = format!("{PACK_DIR}/{}.ron", event.name);
+        if !project.project.definitions.contains(&definition) {
+            project.project.definitions.push(definition.clone());
+            tile_handle.0.push(asset_server.load(definition));
+        }
+        if event.migrate {
+            let skipped = Cell::new(0);
+            let changed = map.remap(packed.remapper(&skipped));
+            info!(
+                "Moved {changed} cells of the open map to {}",
+                packed.tile.path
+            );
+            warn_skipped("the open map", skipped.get());
+            for path in &project.project.maps {
+                let path = project.resolve(path);
+                match migrate_map_file(&path, &packed) {
+                    Ok((changed, skipped)) => {
+                        info!("Moved {changed} cells of {}", path.display());
+                        warn_skipped(&path.display().to_string(), skipped);
+                    }
+                    Err(e) => error!("{}: {e}", path.display()),
+                }
+            }
+        }
+        info!(
+            "Packed {} tilesets into {}",
+            event.tilesets.len(),
+            packed.tile.path
+        );
+        packer.0 = None;
+    }
+}
+
+/// Packs the tilesets of an event and writes the image and definition file.
+fn pack_event(
+    event: &PackTilesetsEvent,
+    tile_handle: &TilesData,
+    tile_assets: &Assets<TileDefinition>,
+    settings: &MapSettings,
+    texture_atlases: &Assets<TextureAtlas>,
+    images: &Assets<Image>,
+) -> Result<PackedAtlas, PackError> {
+    // The name becomes file names, which must stay in the packed folder.
+    let components: Vec<_> = Path::new(&event.name).components().collect();
+    if !matches!(components[..], [Component::Normal(_)]) || event.name.contains(['/', '\\']) {
+        return Err(PackError::BadName(event.name.clone()));
+    }
+    let sources = event
+        .tilesets
+        .iter()
+        .map(|path| {
+            let not_loaded = || PackError::NotLoaded(path.clone());
+            let tile = tile_handle
+                .tiles(tile_assets)
+                .find(|tile| tile.path == *path)
+                .ok_or_else(not_loaded)?;
+            let definition = settings
+                .atlas_definitions
+                .get(path)
+                .ok_or_else(not_loaded)?;
+            let atlas = settings
+                .atlases
+                .get(path)
+                .and_then(|handle| texture_atlases.get(handle))
+                .ok_or_else(not_loaded)?;
+            let image = images.get(&atlas.texture).ok_or_else(not_loaded)?;
+            Ok(PackSource {
+                tile,
+                definition,
+                atlas,
+                image,
+            })
+        })
+        .collect::<Result<Vec<_>, PackError>>()?;
+    let image_path = format!("{PACK_DIR}/{}.png", event.name);
+    let packed = pack_atlas(
+        &event.name,
+        &image_path,
+        &sources,
+        event.padding,
+        event.extrude,
+    )?;
+
+    let dir = assets_dir().join(PACK_DIR);
+    std::fs::create_dir_all(&dir)?;
+    packed
+        .image
+        .clone()
+        .try_into_dynamic()
+        .map_err(|e| PackError::Image(e.to_string()))?
+        .save(assets_dir().join(&image_path))
+        .map_err(|e| PackError::Image(e.to_string()))?;
+    let definition = TileDefinition {
+        tiles: vec![packed.tile.clone()],
+    };
+    let config = ron::ser::PrettyConfig::default().struct_names(true);
+    std::fs::write(
+        dir.join(format!("{}.ron", event.name)),
+        ron::ser::to_string_pretty(&definition, config)?,
+    )?;
+    Ok(packed)
+}
+
+/// Moves a map file's tiles to the packed atlas, returning how many moved and
+/// how many were skipped.
+fn migrate_map_file(path: &Path, packed: &PackedAtlas) -> Result<(usize, usize), PackError> {
+    let mut file = read_map_file(path)?;
+    let skipped = Cell::new(0);
+    let changed = file.remap(packed.remapper(&skipped));
+    if changed > 0 {
+        // The packed definition carries the animations now.
+        file.animations
+            .retain(|saved| !packed.frames.contains_key(&saved.tileset));
+        write_map_file(path, &file)?;
+    }
+    Ok((changed, skipped.get()))
+}
+
+fn warn_skipped(map: &str, skipped: usize) {
+    if skipped > 0 {
+        warn!(
+            "{skipped} cells of {map} use frames past the end of their tileset and were not moved"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remap_only_moves_frames_of_their_tileset() {
+        let packed = PackedAtlas {
+            image: Image::default(),
+            tile: Tile {
+                name: "packed".into(),
+                path: "packed/all.png".into(),
+                atlas_definition: None,
+                images: None,
+                terrains: Vec::new(),
+                adjacency: default(),
+            },
+            frames: HashMap::from([("grass.png".into(), 0..4), ("water.png".into(), 4..6)]),
+        };
+        let tile = |tileset: &str, index| PaintedTile {
+            tileset: tileset.into(),
+            index,
+            transform: default(),
+        };
+        assert_eq!(
+            packed.remap(&tile("water.png", 1)),
+            Some(tile("packed/all.png", 5))
+        );
+        assert_eq!(packed.remap(&tile("grass.png", 4)), None);
+        assert_eq!(packed.remap(&tile("trees.png", 0)), None);
+
+        let skipped = Cell::new(0);
+        let remap = packed.remapper(&skipped);
+        for t in [
+            tile("grass.png", 3),
+            tile("grass.png", 9),
+            tile("trees.png", 9),
+        ] {
+            remap(&t);
+        }
+        assert_eq!(skipped.get(), 1);
+    }
+}
@@ -0,0 +1,413 @@
+//! Projects: a manifest listing a game's tile definition files, maps and asset
+//! folders, so each game can keep its own tile library.
+//!
+//! Assets under the project's folders are loaded through the `project://`
+//! asset source, which searches the folders in order.
+
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use bevy::{
+    asset::{
+        io::{
+            file::FileAssetReader, AssetReader, AssetReaderError, AssetSourceBuilder,
+            AssetSourceId, PathStream, Reader, VecReader,
+        },
+        AssetPath, UntypedAssetId,
+    },
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{assets::TileDefinition, map::LoadMapEvent, TilesData};
+
+/// Name of the asset source serving files from the project's folders.
+pub const PROJECT_SOURCE: &str = "project";
+
+/// Definitions loaded when no project is open.
+pub const DEFAULT_DEFINITIONS: &str = "data/tiles.ron";
+
+/// Must be added before `DefaultPlugins`, as asset sources are fixed once the
+/// asset server starts.
+pub struct ProjectPlugin;
+
+impl Plugin for ProjectPlugin {
+    fn build(&self, app: &mut App) {
+        let roots = ProjectRoots::default();
+        let reader_roots = roots.clone();
+        app.register_asset_source(
+            PROJECT_SOURCE,
+            AssetSourceBuilder::default()
+                .with_reader(move || Box::new(ProjectAssetReader(reader_roots.clone()))),
+        )
+        .insert_resource(roots)
+        .init_resource::<CurrentProject>()
+        .add_event::<OpenProjectEvent>()
+        .add_event::<SaveProjectEvent>()
+        .add_event::<AddAssetRootEvent>()
+        .add_systems(Update, (open_project, save_project, add_asset_root));
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Project {
+    pub name: String,
+    /// Tile definition files, as asset paths such as `data/tiles.ron` or
+    /// `project://tiles.ron`.
+    pub definitions: Vec<String>,
+    /// Map files, relative to the project file.
+    #[serde(default)]
+    pub maps: Vec<PathBuf>,
+    /// Folders searched in order for `project://` assets, relative to the
+    /// project file.
+    #[serde(default)]
+    pub asset_roots: Vec<PathBuf>,
+}
+
+impl Default for Project {
+    fn default() -> Self {
+        Self {
+            name: "Untitled".to_string(),
+            definitions: vec![DEFAULT_DEFINITIONS.to_string()],
+            maps: Vec::new(),
+            asset_roots: Vec::new(),
+        }
+    }
+}
+
+/// The open project, and the file it came from if it was loaded from disk.
+#[derive(Resource, Default)]
+pub struct CurrentProject {
+    pub path: Option<PathBuf>,
+    pub project: Project,
+}
+
+impl CurrentProject {
+    /// A path from the project file, made relative to the project's folder.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        match self.path.as_ref().and_then(|p| p.parent()) {
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// The inverse of `resolve` for paths inside the project's folder; other
+    /// paths are kept as they are.
+    fn relative(&self, path: &Path) -> PathBuf {
+        self.path
+            .as_ref()
+            .and_then(|p| p.parent())
+            .and_then(|dir| path.strip_prefix(dir).ok())
+            .map_or_else(|| path.to_path_buf(), Path::to_path_buf)
+    }
+
+    /// The project's asset folders, resolved.
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.project
+            .asset_roots
+            .iter()
+            .map(|root| self.resolve(root))
+            .collect()
+    }
+}
+
+/// Folders behind the `project://` asset source, shared with its reader.
+#[derive(Resource, Clone, Default)]
+pub struct ProjectRoots(Arc<RwLock<Vec<PathBuf>>>);
+
+impl ProjectRoots {
+    pub fn set(&self, roots: Vec<PathBuf>) {
+        *self.0.write().unwrap() = roots;
+    }
+
+    /// The `project://` asset path of a file inside one of the folders.
+    pub fn asset_path(&self, file: &Path) -> Option<String> {
+        self.0.read().unwrap().iter().find_map(|root| {
+            let relative = file.strip_prefix(root).ok()?;
+            Some(format!(
+                "{PROJECT_SOURCE}://{}",
+                relative.to_string_lossy().replace('\\', "/")
+            ))
+        })
+    }
+
+    /// The file a `project://` path refers to: the first root holding it.
+    /// Paths that could leave the roots, e.g. through `..`, are refused.
+    pub fn find(&self, path: &Path) -> Option<PathBuf> {
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|root| root.join(path))
+            .find(|full| full.exists())
+    }
+}
+
+/// Where an asset lives on disk, for assets from the default source or the
+/// project's folders.
+pub fn asset_file(roots: &ProjectRoots, path: &AssetPath) -> Option<PathBuf> {
+    match path.source() {
+        AssetSourceId::Default => Some(
+            FileAssetReader::get_base_path()
+                .join("assets")
+                .join(path.path()),
+        ),
+        AssetSourceId::Name(name) if **name == *PROJECT_SOURCE => roots.find(path.path()),
+        AssetSourceId::Name(_) => None,
+    }
+}
+
+fn is_project_asset(path: &AssetPath) -> bool {
+    matches!(path.source(), AssetSourceId::Name(name) if **name == *PROJECT_SOURCE)
+}
+
+/// Reloads the loaded `project://` assets, which may come from other files
+/// once the roots change. Loading the same path again would only return the
+/// cached asset.
+fn reload_project_assets(
+    asset_server: &AssetServer,
+    images: &Assets<Image>,
+    definitions: &Assets<TileDefinition>,
+) {
+    let ids = images
+        .ids()
+        .map(UntypedAssetId::from)
+        .chain(definitions.ids().map(UntypedAssetId::from));
+    for id in ids {
+        if let Some(path) = asset_server.get_path(id).filter(is_project_asset) {
+            asset_server.reload(path);
+        }
+    }
+}
+
+struct ProjectAssetReader(ProjectRoots);
+
+impl ProjectAssetReader {
+    fn find(&self, path: &Path) -> Result<PathBuf, AssetReaderError> {
+        self.0
+            .find(path)
+            .ok_or_else(|| AssetReaderError::NotFound(path.to_path_buf()))
+    }
+}
+
+impl AssetReader for ProjectAssetReader {
+    fn read<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<Box<Reader<'a>>, AssetReaderError>> {
+        Box::pin(async move {
+            let bytes = std::fs::read(self.find(path)?)?;
+            let reader: Box<Reader> = Box::new(VecReader::new(bytes));
+            Ok(reader)
+        })
+    }
+
+    fn read_meta<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<Box<Reader<'a>>, AssetReaderError>> {
+        let mut meta = path.as_os_str().to_owned();
+        meta.push(".meta");
+        Box::pin(async move {
+            let bytes = std::fs::read(self.find(Path::new(&meta))?)?;
+            let reader: Box<Reader> = Box::new(VecReader::new(bytes));
+            Ok(reader)
+        })
+    }
+
+    fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<Box<PathStream>, AssetReaderError>> {
+        Box::pin(async move {
+            let entries: Vec<_> = std::fs::read_dir(self.find(path)?)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| path.join(entry.file_name()))
+                .collect();
+            let stream: Box<PathStream> = Box::new(futures_lite::stream::iter(entries));
+            Ok(stream)
+        })
+    }
+
+    fn is_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<bool, AssetReaderError>> {
+        Box::pin(async move { Ok(self.find(path)?.is_dir()) })
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ProjectFileError {
+    #[error("Could not read project file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse project file: {0}")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("Could not write project file: {0}")]
+    Ron(#[from] ron::Error),
+}
+
+pub fn read_project_file(path: &Path) -> Result<Project, ProjectFileError> {
+    let contents = std::fs::read(path)?;
+    Ok(ron::de::from_bytes(&contents)?)
+}
+
+pub fn write_project_file(path: &Path, project: &Project) -> Result<(), ProjectFileError> {
+    let config = ron::ser::PrettyConfig::default().struct_names(true);
+    std::fs::write(path, ron::ser::to_string_pretty(project, config)?)?;
+    Ok(())
+}
+
+/// Open a project file, replacing the tile library and opening its first map.
+#[derive(Event)]
+pub struct OpenProjectEvent(pub PathBuf);
+
+fn open_project(
+    mut reader: EventReader<OpenProjectEvent>,
+    mut current: ResMut<CurrentProject>,
+    roots: Res<ProjectRoots>,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    definitions: Res<Assets<TileDefinition>>,
+    mut tiles_data: ResMut<TilesData>,
+    mut load_events: EventWriter<LoadMapEvent>,
+) {
+    for event in reader.read() {
+        let project = match read_project_file(&event.0) {
+            Ok(project) => project,
+            Err(e) => {
+                error!("{e}");
+                continue;
+            }
+        };
+        *current = CurrentProject {
+            path: Some(event.0.clone()),
+            project,
+        };
+        roots.set(current.roots());
+        let project = &current.project;
+        tiles_data.0 = project
+            .definitions
+            .iter()
+            .map(|path| asset_server.load(path.clone()))
+            .collect();
+        reload_project_assets(&asset_server, &images, &definitions);
+        if let Some(map) = project.maps.first() {
+            load_events.send(LoadMapEvent(current.resolve(map)));
+        }
+        info!("Opened project {}", project.name);
+    }
+}
+
+/// Save the open project, which then lives at the given path.
+#[derive(Event)]
+pub struct SaveProjectEvent(pub PathBuf);
+
+fn save_project(mut reader: EventReader<SaveProjectEvent>, mut current: ResMut<CurrentProject>) {
+    for event in reader.read() {
+        // Keep folders pointing at the same place from the new location.
+        let roots = current.roots();
+        let maps: Vec<_> = current
+            .project
+            .maps
+            .iter()
+            .map(|map| current.resolve(map))
+            .collect();
+        current.path = Some(event.0.clone());
+        current.project.asset_roots = roots.iter().map(|root| current.relative(root)).collect();
+        current.project.maps = maps.iter().map(|map| current.relative(map)).collect();
+        match write_project_file(&event.0, &current.project) {
+            Ok(()) => info!("Saved project to {}", event.0.display()),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+/// Serve files from a folder through `project://`, e.g. a game repository's
+/// art directory, without copying them into the editor's assets.
+#[derive(Event)]
+pub struct AddAssetRootEvent(pub PathBuf);
+
+fn add_asset_root(
+    mut reader: EventReader<AddAssetRootEvent>,
+    mut current: ResMut<CurrentProject>,
+    roots: Res<ProjectRoots>,
+    tiles_data: Res<TilesData>,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    definitions: Res<Assets<TileDefinition>>,
+) {
+    let mut added = false;
+    for event in reader.read() {
+        let root = current.relative(&event.0);
+        if !current.project.asset_roots.contains(&root) {
+            current.project.asset_roots.push(root);
+            added = true;
+        }
+    }
+    if added {
+        roots.set(current.roots());
+        reload_project_assets(&asset_server, &images, &definitions);
+        // Images that could not be found before are not in `images`.
+        let missing = |path: &AssetPath| {
+            asset_server
+                .get_handle::<Image>(path.clone())
+                .is_none_or(|handle| !images.contains(&handle))
+        };
+        for tile in tiles_data.tiles(&definitions) {
+            let paths: Vec<_> = match &tile.images {
+                Some(collection) => collection.paths(&tile.path).collect(),
+                None => vec![tile.path.clone()],
+            };
+            for path in paths.iter().map(|path| AssetPath::parse(path)) {
+                if is_project_asset(&path) && missing(&path) {
+                    asset_server.reload(path.into_owned());
+                }
+            }
+        }
+        // Definitions outside the project are not reloaded above, but may use
+        // the new images too.
+        for path in tiles_data.0.iter().filter_map(|h| asset_server.get_path(h)) {
+            if !is_project_asset(&path) {
+                asset_server.reload(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_searches_roots_in_order_and_stays_inside_them() {
+        let base = std::env::temp_dir().join(format!("tile-editor-roots-{}", std::process::id()));
+        let (first, second) = (base.join("first"), base.join("second"));
+        std::fs::create_dir_all(second.join("tiles")).unwrap();
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::write(second.join("tiles/grass.png"), []).unwrap();
+        std::fs::write(base.join("secret.txt"), []).unwrap();
+        let roots = ProjectRoots::default();
+        roots.set(vec![first.clone(), second.clone()]);
+
+        assert_eq!(
+            roots.find(Path::new("tiles/grass.png")),
+            Some(second.join("tiles/grass.png"))
+        );
+        assert_eq!(roots.find(Path::new("../secret.txt")), None);
+        assert_eq!(roots.find(Path::new("tiles/../../secret.txt")), None);
+        assert_eq!(roots.find(&base.join("secret.txt")), None);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
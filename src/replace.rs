@@ -0,0 +1,309 @@
+//! Find and replace of painted tiles, e.g. after frames of an atlas have been
+//! moved around. Each `(tileset, index)` pair is swapped for another in the
+//! open map, or in the open map and every map file of the project, keeping
+//! the tiles' flips. The whole replacement is one undo step.
+
+use std::{path::Path, str::FromStr};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use thiserror::Error;
+
+use crate::{
+    history::{FileEdit, History},
+    map::{map_file_contents, MapData, MapFile, MapFileError, PaintedTile},
+    project::CurrentProject,
+};
+
+pub struct ReplacePlugin;
+
+impl Plugin for ReplacePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileReplacer>()
+            .add_event::<ReplaceTilesEvent>()
+            .add_systems(Update, (replace_ui, replace_tiles).chain());
+    }
+}
+
+/// A frame of a tileset, written `tileset:index`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileRef {
+    pub tileset: String,
+    pub index: usize,
+}
+
+impl FromStr for TileRef {
+    type Err = ReplaceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Split at the last colon, tileset paths may hold `project://`.
+        let bad = || ReplaceError::BadTile(s.to_string());
+        let (tileset, index) = s.trim().rsplit_once(':').ok_or_else(bad)?;
+        if tileset.is_empty() {
+            return Err(bad());
+        }
+        Ok(Self {
+            tileset: tileset.to_string(),
+            index: index.parse().map_err(|_| bad())?,
+        })
+    }
+}
+
+impl TileRef {
+    fn matches(&self, tile: &PaintedTile) -> bool {
+        self.tileset == tile.tileset && self.index == tile.index
+    }
+}
+
+/// Pairs of tiles to find and what to put in their place. Each tile is
+/// replaced by the first pair it matches, so pairs can swap tiles.
+#[derive(Clone, Debug, Default)]
+pub struct Replacements(pub Vec<(TileRef, TileRef)>);
+
+impl Replacements {
+    pub fn remap(&self, tile: &PaintedTile) -> Option<PaintedTile> {
+        let (_, to) = self.0.iter().find(|(from, _)| from.matches(tile))?;
+        let replaced = PaintedTile {
+            tileset: to.tileset.clone(),
+            index: to.index,
+            transform: tile.transform,
+        };
+        (replaced != *tile).then_some(replaced)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplaceScope {
+    #[default]
+    OpenMap,
+    /// The open map and every map file of the project.
+    Project,
+}
+
+#[derive(Event)]
+pub struct ReplaceTilesEvent {
+    pub replacements: Replacements,
+    pub scope: ReplaceScope,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ReplaceError {
+    #[error("Expected a tile as tileset:index, got {0:?}")]
+    BadTile(String),
+    #[error("Could not access map file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Map(#[from] MapFileError),
+}
+
+/// Replaces tiles in a saved map, returning how many cells changed and, when
+/// `write` is set and any did, the file's contents before and after.
+pub fn replace_in_file(
+    path: &Path,
+    replacements: &Replacements,
+    write: bool,
+) -> Result<(usize, Option<FileEdit>), ReplaceError> {
+    let before = std::fs::read(path)?;
+    let mut file: MapFile = ron::de::from_bytes(&before).map_err(MapFileError::from)?;
+    let changed = file.remap(|tile| replacements.remap(tile));
+    if changed == 0 || !write {
+        return Ok((changed, None));
+    }
+    let after = map_file_contents(&file)?.into_bytes();
+    std::fs::write(path, &after)?;
+    let edit = FileEdit {
+        path: path.to_path_buf(),
+        before,
+        after,
+    };
+    Ok((changed, Some(edit)))
+}
+
+/// The open replace window, if any.
+#[derive(Resource, Default)]
+pub struct TileReplacer(pub Option<ReplaceState>);
+
+pub struct ReplaceState {
+    pairs: Vec<(String, String)>,
+    scope: ReplaceScope,
+    /// Outcome of the last preview or replacement.
+    message: Option<String>,
+    error: Option<String>,
+}
+
+impl Default for ReplaceState {
+    fn default() -> Self {
+        Self {
+            pairs: vec![(String::new(), String::new())],
+            scope: ReplaceScope::default(),
+            message: None,
+            error: None,
+        }
+    }
+}
+
+impl ReplaceState {
+    fn replacements(&self) -> Result<Replacements, ReplaceError> {
+        self.pairs
+            .iter()
+            .map(|(from, to)| Ok((from.parse()?, to.parse()?)))
+            .collect::<Result<_, _>>()
+            .map(Replacements)
+    }
+}
+
+/// Counts the cells a replacement would change, without changing them.
+fn preview(
+    replacements: &Replacements,
+    scope: ReplaceScope,
+    map: &MapData,
+    project: &CurrentProject,
+) -> String {
+    let in_map = map.count(|tile| replacements.remap(tile).is_some());
+    if scope == ReplaceScope::OpenMap {
+        return format!("{in_map} cells in the open map");
+    }
+    let mut files = 0;
+    let mut in_files = 0;
+    for path in &project.project.maps {
+        let path = project.resolve(path);
+        match replace_in_file(&path, replacements, false) {
+            Ok((0, _)) => {}
+            Ok((changed, _)) => {
+                files += 1;
+                in_files += changed;
+            }
+            Err(e) => error!("{}: {e}", path.display()),
+        }
+    }
+    format!("{in_map} cells in the open map, {in_files} cells in {files} map files")
+}
+
+fn replace_ui(
+    mut contexts: EguiContexts,
+    mut replacer: ResMut<TileReplacer>,
+    map: Res<MapData>,
+    project: Res<CurrentProject>,
+    mut events: EventWriter<ReplaceTilesEvent>,
+) {
+    let Some(state) = &mut replacer.0 else {
+        return;
+    };
+    let mut open = true;
+    let mut action = None;
+    egui::Window::new("Replace Tiles")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("Tiles as tileset:index");
+            let mut remove = None;
+            let mut changed = false;
+            for (i, (from, to)) in state.pairs.iter_mut().enumerate() {
+                ui.horizontal(|hui| {
+                    changed |= hui.text_edit_singleline(from).changed();
+                    hui.label("→");
+                    changed |= hui.text_edit_singleline(to).changed();
+                    if hui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                state.pairs.remove(i);
+                changed = true;
+            }
+            if ui.button("Add Pair").clicked() {
+                state.pairs.push((String::new(), String::new()));
+            }
+            ui.horizontal(|hui| {
+                changed |= hui
+                    .radio_value(&mut state.scope, ReplaceScope::OpenMap, "Open map")
+                    .changed();
+                changed |= hui
+                    .radio_value(&mut state.scope, ReplaceScope::Project, "Project maps")
+                    .changed();
+            });
+            if changed {
+                state.message = None;
+                state.error = None;
+            }
+            if let Some(message) = &state.message {
+                ui.label(message);
+            }
+            if let Some(error) = &state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+            ui.horizontal(|hui| {
+                let valid = !state.pairs.is_empty();
+                if hui
+                    .add_enabled(valid, egui::Button::new("Preview"))
+                    .clicked()
+                {
+                    action = Some(false);
+                }
+                if hui
+                    .add_enabled(valid, egui::Button::new("Replace"))
+                    .clicked()
+                {
+                    action = Some(true);
+                }
+            });
+        });
+    if !open {
+        replacer.0 = None;
+        return;
+    }
+    let Some(replace) = action else {
+        return;
+    };
+    match state.replacements() {
+        Ok(replacements) if replace => {
+            state.message = None;
+            state.error = None;
+            events.send(ReplaceTilesEvent {
+                replacements,
+                scope: state.scope,
+            });
+        }
+        Ok(replacements) => {
+            state.message = Some(preview(&replacements, state.scope, &map, &project));
+            state.error = None;
+        }
+        Err(e) => state.error = Some(e.to_string()),
+    }
+}
+
+fn replace_tiles(
+    mut reader: EventReader<ReplaceTilesEvent>,
+    mut map: ResMut<MapData>,
+    mut history: ResMut<History>,
+    mut replacer: ResMut<TileReplacer>,
+    project: Res<CurrentProject>,
+) {
+    for event in reader.read() {
+        // Keep earlier changes out of this step.
+        history.commit(&mut map);
+        let in_map = map.remap(|tile| event.replacements.remap(tile));
+        let mut in_files = 0;
+        if event.scope == ReplaceScope::Project {
+            for path in &project.project.maps {
+                let path = project.resolve(path);
+                match replace_in_file(&path, &event.replacements, true) {
+                    Ok((changed, edit)) => {
+                        in_files += changed;
+                        if let Some(edit) = edit {
+                            history.record_file(edit);
+                        }
+                    }
+                    Err(e) => error!("{}: {e}", path.display()),
+                }
+            }
+        }
+        history.commit(&mut map);
+        let message = format!("Replaced {in_map} cells in the open map, {in_files} in map files");
+        info!("{message}");
+        if let Some(state) = &mut replacer.0 {
+            state.message = Some(message);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Scatter brushes: a weighted set of frames from one tileset, one of which
+//! is picked at random for every painted cell so ground doesn't repeat.
+//!
+//! Picks come from a seeded generator, so painting the same strokes after
+//! setting the same seed gives the same tiles.
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    grid::{Brush, SelectedBrush, TerrainBrush},
+    stamp::StampBrush,
+};
+
+pub struct ScatterPlugin;
+
+impl Plugin for ScatterPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScatterBrush>()
+            .init_resource::<ScatterRng>()
+            .add_systems(Update, scatter_ui);
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scatter {
+    pub tileset: String,
+    pub entries: Vec<ScatterEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScatterEntry {
+    pub index: usize,
+    /// Relative chance of the frame being picked.
+    pub weight: f32,
+}
+
+impl Scatter {
+    /// A frame index picked by weight, or `None` if no frame has any.
+    pub fn pick(&self, rng: &mut fastrand::Rng) -> Option<usize> {
+        let weighted = || self.entries.iter().filter(|entry| entry.weight > 0.);
+        let total: f32 = weighted().map(|entry| entry.weight).sum();
+        if total <= 0. {
+            return None;
+        }
+        let mut roll = rng.f32() * total;
+        for entry in weighted() {
+            if roll < entry.weight {
+                return Some(entry.index);
+            }
+            roll -= entry.weight;
+        }
+        // Rounding can leave the roll just past the last weight.
+        weighted().next_back().map(|entry| entry.index)
+    }
+
+    /// Adds a frame, or makes it more likely if it is already in the set.
+    /// Frames of another tileset start a new set.
+    pub fn add(&mut self, tileset: &str, index: usize) {
+        if self.tileset != tileset {
+            self.tileset = tileset.to_string();
+            self.entries.clear();
+        }
+        match self.entries.iter_mut().find(|entry| entry.index == index) {
+            Some(entry) => entry.weight += 1.,
+            None => self.entries.push(ScatterEntry { index, weight: 1. }),
+        }
+    }
+}
+
+/// The scatter painted instead of the selected brush while active.
+#[derive(Resource, Default)]
+pub struct ScatterBrush {
+    pub active: bool,
+    pub scatter: Scatter,
+}
+
+impl ScatterBrush {
+    /// The scatter to paint with, if it is active and has a frame that can
+    /// be picked.
+    pub fn get(&self) -> Option<&Scatter> {
+        let weighted = self.scatter.entries.iter().any(|entry| entry.weight > 0.);
+        (self.active && weighted).then_some(&self.scatter)
+    }
+}
+
+#[derive(Resource)]
+pub struct ScatterRng {
+    pub seed: u32,
+    pub rng: fastrand::Rng,
+}
+
+impl ScatterRng {
+    pub fn reseed(&mut self, seed: u32) {
+        self.seed = seed;
+        self.rng = fastrand::Rng::with_seed(seed.into());
+    }
+}
+
+impl Default for ScatterRng {
+    fn default() -> Self {
+        let seed = fastrand::u32(..);
+        Self {
+            seed,
+            rng: fastrand::Rng::with_seed(seed.into()),
+        }
+    }
+}
+
+fn scatter_ui(
+    mut contexts: EguiContexts,
+    mut scatter_brush: ResMut<ScatterBrush>,
+    mut scatter_rng: ResMut<ScatterRng>,
+    brush: Query<&Brush, With<SelectedBrush>>,
+    mut terrain_brush: ResMut<TerrainBrush>,
+    mut stamp: ResMut<StampBrush>,
+    mut seed: Local<Option<u32>>,
+) {
+    let mut scatter = scatter_brush.scatter.clone();
+    let mut active = scatter_brush.active;
+    let seed = seed.get_or_insert(scatter_rng.seed);
+    egui::Window::new("Scatter").show(contexts.ctx_mut(), |ui| {
+        if scatter.entries.is_empty() {
+            ui.label("Add frames from the palette to scatter them.");
+        } else {
+            ui.label(&scatter.tileset);
+        }
+        let mut remove = None;
+        for (i, entry) in scatter.entries.iter_mut().enumerate() {
+            ui.horizontal(|hui| {
+                hui.label(format!("Frame {}", entry.index));
+                hui.add(
+                    egui::DragValue::new(&mut entry.weight)
+                        .speed(0.1)
+                        .clamp_range(0.0..=100.0),
+                );
+                if hui.small_button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            scatter.entries.remove(i);
+        }
+        let selected = brush.get_single().ok();
+        if ui
+            .add_enabled(selected.is_some(), egui::Button::new("Add Selected Frame"))
+            .clicked()
+        {
+            if let Some(selected) = selected {
+                scatter.add(&selected.tileset, selected.index);
+            }
+        }
+        ui.checkbox(&mut active, "Paint with scatter");
+        ui.horizontal(|hui| {
+            hui.label("Seed");
+            hui.add(egui::DragValue::new(seed));
+            if hui.button("Restart").clicked() {
+                scatter_rng.reseed(*seed);
+            }
+            if hui.button("Random").clicked() {
+                *seed = fastrand::u32(..);
+                scatter_rng.reseed(*seed);
+            }
+        });
+    });
+    if active && !scatter_brush.active {
+        terrain_brush.0 = None;
+        stamp.0 = None;
+    }
+    if active != scatter_brush.active {
+        scatter_brush.active = active;
+    }
+    if scatter != scatter_brush.scatter {
+        scatter_brush.scatter = scatter;
+    }
+}
@@ -0,0 +1,575 @@
+//! Rhai scripts for repetitive edits, run from the Script window or with
+//! `tile-editor script`. For example, to swap grass for flowers in the
+//! selection:
+//!
+//! ```text
+//! for cell in selection() {
+//!     if get(cell[0], cell[1]) == "Grass:0" {
+//!         set(cell[0], cell[1], "Grass:3");
+//!     }
+//! }
+//! ```
+//!
+//! Tiles are written `tileset:index`, where the tileset is the name or image
+//! path of a loaded tileset, and `()` is an empty cell. Setting a tile keeps the flips and
+//! rotation of the one it replaces. A script has these functions:
+//!
+//! - `get(x, y)`, `get(layer, x, y)`: the tile of a cell.
+//! - `set(x, y, tile)`, `set(layer, x, y, tile)`, `fill(x0, y0, x1, y1, tile)`.
+//!   Rectangles are clipped to bounded maps.
+//! - `painted()`: the painted cells of the active layer as `[x, y]` arrays.
+//! - `map_size()`: `[columns, rows]`, or `()` on infinite maps.
+//! - `layers()`, `active_layer()`, `set_active_layer(layer)`, `add_layer(name)`.
+//! - `selection()`, `select(x0, y0, x1, y1)`, `select_cells(cells)`,
+//!   `clear_selection()`.
+//! - `tilesets()`, `tileset_path(name)`, `frames(tileset)`.
+//!
+//! Cells hold nothing but their tile, so there are no properties to script.
+//! A run in the editor is one undo step.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, INT};
+use thiserror::Error;
+
+use crate::{
+    assets::{Tile, TileDefinition},
+    grid::{rect, rect_len, GridSize, MapMode, MAX_RECT_CELLS},
+    history::History,
+    map::{
+        read_map_file, write_map_file, MapData, MapEdit, MapFile, MapFileError, PaintedTile,
+        TileTransform,
+    },
+    replace::TileRef,
+    selection::Selection,
+    MapSettings, TilesData,
+};
+
+/// Stops scripts stuck in a loop.
+const MAX_OPERATIONS: u64 = 100_000_000;
+
+pub struct ScriptPlugin;
+
+impl Plugin for ScriptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScriptConsole>()
+            .add_systems(Update, script_ui);
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("Could not access file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse tile definitions: {0}")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("{0}")]
+    Map(#[from] MapFileError),
+    #[error("Script failed: {0}")]
+    Eval(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct TilesetInfo {
+    pub name: String,
+    pub path: String,
+    pub frames: usize,
+}
+
+impl TilesetInfo {
+    /// Frames are counted from the definition, for when no atlas is loaded.
+    pub fn from_definition(tile: &Tile) -> Self {
+        let frames = match (&tile.atlas_definition, &tile.images) {
+            (Some(atlas), _) => atlas.columns * atlas.rows,
+            (None, Some(images)) => images.files.len(),
+            (None, None) => 0,
+        };
+        Self {
+            name: tile.name.clone(),
+            path: tile.path.clone(),
+            frames,
+        }
+    }
+}
+
+/// Reads a tile written `tileset:index`, where the tileset is the name or
+/// image path of one of `tilesets`.
+pub fn parse_tile(text: &str, tilesets: &[TilesetInfo]) -> Result<PaintedTile, String> {
+    let tile: TileRef = text.parse().map_err(|e| format!("{e}"))?;
+    let path = tilesets
+        .iter()
+        .find(|tileset| tileset.name == tile.tileset)
+        .or_else(|| tilesets.iter().find(|tileset| tileset.path == tile.tileset))
+        .map(|tileset| tileset.path.clone())
+        .ok_or_else(|| format!("There is no tileset {}", tile.tileset))?;
+    Ok(PaintedTile {
+        tileset: path,
+        index: tile.index,
+        transform: TileTransform::default(),
+    })
+}
+
+/// Writes a tile as `tileset:index`, using the tileset's name when known.
+pub fn tile_text(tile: &PaintedTile, tilesets: &[TilesetInfo]) -> String {
+    let tileset = tilesets
+        .iter()
+        .find(|tileset| tileset.path == tile.tileset)
+        .map_or(tile.tileset.as_str(), |tileset| tileset.name.as_str());
+    format!("{tileset}:{}", tile.index)
+}
+
+/// What a script can see and change.
+pub struct ScriptWorld {
+    pub map: MapData,
+    /// Columns and rows of a bounded map.
+    pub bounds: Option<UVec2>,
+    pub selection: Vec<IVec2>,
+    pub tilesets: Vec<TilesetInfo>,
+    /// Printed by the script.
+    pub output: Vec<String>,
+}
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+fn cell_value(cell: IVec2) -> Dynamic {
+    Dynamic::from_array(vec![
+        Dynamic::from(cell.x as INT),
+        Dynamic::from(cell.y as INT),
+    ])
+}
+
+fn cells_value(cells: impl IntoIterator<Item = IVec2>) -> Array {
+    cells.into_iter().map(cell_value).collect()
+}
+
+impl ScriptWorld {
+    fn cell(&self, x: INT, y: INT) -> Fallible<IVec2> {
+        let outside = || format!("Cell ({x}, {y}) is outside the map").into();
+        let (Ok(cx), Ok(cy)) = (i32::try_from(x), i32::try_from(y)) else {
+            return Err(outside());
+        };
+        let cell = IVec2::new(cx, cy);
+        let inside = self
+            .bounds
+            .is_none_or(|size| cell.cmpge(IVec2::ZERO).all() && cell.cmplt(size.as_ivec2()).all());
+        if !inside {
+            return Err(outside());
+        }
+        Ok(cell)
+    }
+
+    /// The cells of a rectangle, clipped to a bounded map.
+    fn area(&self, x0: INT, y0: INT, x1: INT, y1: INT) -> Fallible<Vec<IVec2>> {
+        let coord = |v: INT| v.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+        let (from, to) = (
+            IVec2::new(coord(x0), coord(y0)),
+            IVec2::new(coord(x1), coord(y1)),
+        );
+        let (mut min, mut max) = (from.min(to), from.max(to));
+        if let Some(size) = self.bounds {
+            min = min.max(IVec2::ZERO);
+            max = max.min(size.as_ivec2() - 1);
+            if min.cmpgt(max).any() {
+                return Ok(Vec::new());
+            }
+        }
+        if rect_len(min, max) > MAX_RECT_CELLS {
+            return Err(format!(
+                "({x0}, {y0}) to ({x1}, {y1}) is more than {MAX_RECT_CELLS} cells"
+            )
+            .into());
+        }
+        Ok(rect(min, max))
+    }
+
+    fn layer(&self, layer: INT) -> Fallible<usize> {
+        usize::try_from(layer)
+            .ok()
+            .filter(|layer| *layer < self.map.layers().len())
+            .ok_or_else(|| format!("There is no layer {layer}").into())
+    }
+
+    fn tile(&self, tile: Dynamic) -> Fallible<Option<PaintedTile>> {
+        if tile.is_unit() {
+            return Ok(None);
+        }
+        let text = tile
+            .into_string()
+            .map_err(|_| "Expected a tile as \"tileset:index\" or ()".to_string())?;
+        Ok(Some(parse_tile(&text, &self.tilesets)?))
+    }
+
+    fn get(&self, layer: usize, x: INT, y: INT) -> Fallible<Dynamic> {
+        let cell = self.cell(x, y)?;
+        Ok(self
+            .map
+            .get_in(layer, cell)
+            .map_or(Dynamic::UNIT, |tile| tile_text(tile, &self.tilesets).into()))
+    }
+
+    fn set(&mut self, layer: usize, x: INT, y: INT, tile: Dynamic) -> Fallible<()> {
+        let cell = self.cell(x, y)?;
+        let mut tile = self.tile(tile)?;
+        if let (Some(tile), Some(old)) = (&mut tile, self.map.get_in(layer, cell)) {
+            tile.transform = old.transform;
+        }
+        if self.map.get_in(layer, cell) != tile.as_ref() {
+            self.map.set_in(layer, cell, tile);
+        }
+        Ok(())
+    }
+
+    fn painted(&self) -> Array {
+        let mut cells: Vec<_> = self.map.tiles().map(|(cell, _)| cell).collect();
+        cells.sort_by_key(|cell| (cell.y, cell.x));
+        cells_value(cells)
+    }
+
+    fn select_cells(&mut self, cells: Array) -> Fallible<()> {
+        let mut selection = Vec::new();
+        for cell in cells {
+            let pair = cell.into_array().ok().and_then(|pair| {
+                let x = pair.first()?.as_int().ok()?;
+                let y = pair.get(1)?.as_int().ok()?;
+                Some((x, y))
+            });
+            let (x, y) = pair.ok_or_else(|| "Expected cells as [x, y] arrays".to_string())?;
+            selection.push(self.cell(x, y)?);
+        }
+        self.selection = selection;
+        Ok(())
+    }
+
+    fn tileset(&self, name: &str) -> Fallible<&TilesetInfo> {
+        self.tilesets
+            .iter()
+            .find(|tileset| tileset.name == name || tileset.path == name)
+            .ok_or_else(|| format!("There is no tileset {name}").into())
+    }
+}
+
+/// Registers the editor's functions, which share `world`.
+fn engine(world: &Arc<Mutex<ScriptWorld>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let w = world.clone();
+    engine.on_print(move |text| w.lock().unwrap().output.push(text.to_string()));
+    let w = world.clone();
+    engine.on_debug(move |text, _, _| w.lock().unwrap().output.push(text.to_string()));
+
+    let w = world.clone();
+    engine.register_fn("get", move |x: INT, y: INT| {
+        let world = w.lock().unwrap();
+        world.get(world.map.active_layer(), x, y)
+    });
+    let w = world.clone();
+    engine.register_fn("get", move |layer: INT, x: INT, y: INT| {
+        let world = w.lock().unwrap();
+        world.get(world.layer(layer)?, x, y)
+    });
+    let w = world.clone();
+    engine.register_fn("set", move |x: INT, y: INT, tile: Dynamic| {
+        let mut world = w.lock().unwrap();
+        let layer = world.map.active_layer();
+        world.set(layer, x, y, tile)
+    });
+    let w = world.clone();
+    engine.register_fn("set", move |layer: INT, x: INT, y: INT, tile: Dynamic| {
+        let mut world = w.lock().unwrap();
+        let layer = world.layer(layer)?;
+        world.set(layer, x, y, tile)
+    });
+    let w = world.clone();
+    engine.register_fn(
+        "fill",
+        move |x0: INT, y0: INT, x1: INT, y1: INT, tile: Dynamic| -> Fallible<()> {
+            let mut world = w.lock().unwrap();
+            let layer = world.map.active_layer();
+            for cell in world.area(x0, y0, x1, y1)? {
+                world.set(layer, cell.x as INT, cell.y as INT, tile.clone())?;
+            }
+            Ok(())
+        },
+    );
+    let w = world.clone();
+    engine.register_fn("painted", move || w.lock().unwrap().painted());
+    let w = world.clone();
+    engine.register_fn("map_size", move || {
+        w.lock().unwrap().bounds.map_or(Dynamic::UNIT, |size| {
+            Dynamic::from_array(vec![
+                Dynamic::from(size.x as INT),
+                Dynamic::from(size.y as INT),
+            ])
+        })
+    });
+
+    let w = world.clone();
+    engine.register_fn("layers", move || -> Array {
+        let world = w.lock().unwrap();
+        world
+            .map
+            .layers()
+            .iter()
+            .map(|layer| layer.name.clone().into())
+            .collect()
+    });
+    let w = world.clone();
+    engine.register_fn("active_layer", move || {
+        w.lock().unwrap().map.active_layer() as INT
+    });
+    let w = world.clone();
+    engine.register_fn("set_active_layer", move |layer: INT| -> Fallible<()> {
+        let mut world = w.lock().unwrap();
+        let layer = world.layer(layer)?;
+        world.map.set_active_layer(layer);
+        Ok(())
+    });
+    let w = world.clone();
+    engine.register_fn("add_layer", move |name: &str| {
+        w.lock().unwrap().map.add_layer(name) as INT
+    });
+
+    let w = world.clone();
+    engine.register_fn("selection", move || {
+        cells_value(w.lock().unwrap().selection.iter().copied())
+    });
+    let w = world.clone();
+    engine.register_fn(
+        "select",
+        move |x0: INT, y0: INT, x1: INT, y1: INT| -> Fallible<()> {
+            let mut world = w.lock().unwrap();
+            world.selection = world.area(x0, y0, x1, y1)?;
+            Ok(())
+        },
+    );
+    let w = world.clone();
+    engine.register_fn("select_cells", move |cells: Array| {
+        w.lock().unwrap().select_cells(cells)
+    });
+    let w = world.clone();
+    engine.register_fn("clear_selection", move || {
+        w.lock().unwrap().selection.clear();
+    });
+
+    let w = world.clone();
+    engine.register_fn("tilesets", move || -> Array {
+        let world = w.lock().unwrap();
+        world
+            .tilesets
+            .iter()
+            .map(|tileset| tileset.name.clone().into())
+            .collect()
+    });
+    let w = world.clone();
+    engine.register_fn("tileset_path", move |name: &str| -> Fallible<String> {
+        Ok(w.lock().unwrap().tileset(name)?.path.clone())
+    });
+    let w = world.clone();
+    engine.register_fn("frames", move |name: &str| -> Fallible<INT> {
+        Ok(w.lock().unwrap().tileset(name)?.frames as INT)
+    });
+    engine
+}
+
+/// Runs a script against `world`, handing the world back with its changes
+/// even when the script fails part way.
+pub fn run(source: &str, world: ScriptWorld) -> (ScriptWorld, Result<(), ScriptError>) {
+    let world = Arc::new(Mutex::new(world));
+    let result = engine(&world)
+        .run(source)
+        .map_err(|e| ScriptError::Eval(e.to_string()));
+    // The engine, and every function holding the world, is gone by now.
+    let world = Arc::into_inner(world).unwrap().into_inner().unwrap();
+    (world, result)
+}
+
+/// Runs a script on a saved map, returning how many cells it changed and
+/// what it printed. The map is only written when `write` is set and the
+/// script succeeds.
+pub fn run_on_file(
+    source: &str,
+    path: &Path,
+    tilesets: &[TilesetInfo],
+    write: bool,
+) -> Result<(usize, Vec<String>), ScriptError> {
+    let mut file = read_map_file(path)?;
+    // The definitions aren't loaded, so keep the saved animations as they are.
+    let animations = std::mem::take(&mut file.animations);
+    let mut grid_size = GridSize::default();
+    let mut map = MapData::default();
+    let mut settings = MapSettings::default();
+    file.apply(&mut grid_size, &mut map, &mut settings);
+    map.take_edits();
+    // Tilesets on the map can be named by path without their definitions.
+    let mut tilesets = tilesets.to_vec();
+    for layer in 0..map.layers().len() {
+        for (_, tile) in map.tiles_in(layer) {
+            if !tilesets.iter().any(|tileset| tileset.path == tile.tileset) {
+                tilesets.push(TilesetInfo {
+                    name: tile.tileset.clone(),
+                    path: tile.tileset.clone(),
+                    frames: 0,
+                });
+            }
+        }
+    }
+    let world = ScriptWorld {
+        map,
+        bounds: (grid_size.mode == MapMode::Bounded)
+            .then(|| UVec2::new(grid_size.cols as u32, grid_size.rows as u32)),
+        selection: Vec::new(),
+        tilesets,
+        output: Vec::new(),
+    };
+    let (mut world, result) = run(source, world);
+    result?;
+    let edits = world.map.take_edits();
+    let changed = edits
+        .iter()
+        .filter(|edit| matches!(edit, MapEdit::Cell(_)))
+        .count();
+    if write && !edits.is_empty() {
+        let mut file = MapFile::new(&grid_size, &world.map, &settings);
+        file.animations = animations;
+        write_map_file(path, &file)?;
+    }
+    Ok((changed, world.output))
+}
+
+/// Tilesets of definition files, read without loading their images.
+pub fn read_tilesets(path: &Path) -> Result<Vec<TilesetInfo>, ScriptError> {
+    let definition: TileDefinition = ron::de::from_bytes(&std::fs::read(path)?)?;
+    Ok(definition
+        .tiles
+        .iter()
+        .map(TilesetInfo::from_definition)
+        .collect())
+}
+
+/// The open script window, if any.
+#[derive(Resource, Default)]
+pub struct ScriptConsole(pub Option<ConsoleState>);
+
+#[derive(Default)]
+pub struct ConsoleState {
+    source: String,
+    /// Script file to load from or save to.
+    path: String,
+    output: Vec<String>,
+    error: Option<String>,
+}
+
+fn script_ui(
+    mut contexts: EguiContexts,
+    mut console: ResMut<ScriptConsole>,
+    mut map: ResMut<MapData>,
+    mut history: ResMut<History>,
+    mut selection: ResMut<Selection>,
+    grid_size: Res<GridSize>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+) {
+    let Some(state) = &mut console.0 else {
+        return;
+    };
+    let mut open = true;
+    let mut run_script = false;
+    egui::Window::new("Script")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|hui| {
+                hui.label("File");
+                hui.text_edit_singleline(&mut state.path);
+                if hui.button("Load").clicked() {
+                    match std::fs::read_to_string(&state.path) {
+                        Ok(source) => {
+                            state.source = source;
+                            state.error = None;
+                        }
+                        Err(e) => state.error = Some(ScriptError::from(e).to_string()),
+                    }
+                }
+                if hui.button("Save").clicked() {
+                    state.error = std::fs::write(&state.path, &state.source)
+                        .err()
+                        .map(|e| ScriptError::from(e).to_string());
+                }
+            });
+            ui.add(
+                egui::TextEdit::multiline(&mut state.source)
+                    .code_editor()
+                    .desired_rows(12)
+                    .desired_width(f32::INFINITY),
+            );
+            ui.horizontal(|hui| {
+                run_script = hui.button("Run").clicked();
+                if hui.button("Clear Output").clicked() {
+                    state.output.clear();
+                    state.error = None;
+                }
+            });
+            egui::ScrollArea::vertical()
+                .max_height(160.)
+                .stick_to_bottom(true)
+                .show(ui, |sui| {
+                    for line in &state.output {
+                        sui.monospace(line);
+                    }
+                });
+            if let Some(error) = &state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+        });
+    if !open {
+        console.0 = None;
+        return;
+    }
+    if !run_script {
+        return;
+    }
+    let tilesets = tile_handle
+        .tiles(&tile_assets)
+        .map(|tile| {
+            let mut info = TilesetInfo::from_definition(tile);
+            if let Some(atlas) = settings
+                .atlases
+                .get(&tile.path)
+                .and_then(|handle| texture_atlases.get(handle))
+            {
+                info.frames = atlas.len();
+            }
+            info
+        })
+        .collect();
+    let mut cells = selection.cells();
+    cells.sort_by_key(|cell| (cell.y, cell.x));
+    // Keep earlier changes out of the script's undo step.
+    history.commit(&mut map);
+    let world = ScriptWorld {
+        map: std::mem::take(&mut *map),
+        bounds: (grid_size.mode == MapMode::Bounded)
+            .then(|| UVec2::new(grid_size.cols as u32, grid_size.rows as u32)),
+        selection: cells.clone(),
+        tilesets,
+        output: Vec::new(),
+    };
+    let (world, result) = run(&state.source, world);
+    *map = world.map;
+    history.commit(&mut map);
+    if world.selection != cells {
+        selection.clear();
+        if !world.selection.is_empty() {
+            selection.picked = Some(world.selection.into_iter().collect());
+        }
+    }
+    state.output.extend(world.output);
+    state.error = result.err().map(|e| e.to_string());
+}
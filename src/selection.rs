@@ -0,0 +1,463 @@
+//! Selections made with the Select tools, with copy, cut and paste.
+//!
+//! The marquee drags out a rectangle of cells on the active layer, the magic
+//! wand picks the contiguous cells holding the clicked tile and "Select Tile"
+//! picks that tile everywhere on the layer; Shift adds to what is already
+//! picked. Ctrl+C copies the selection, Ctrl+X cuts it and Delete clears it,
+//! and the Selection window fills it or replaces its tiles with the brush.
+//!
+//! Ctrl+V pastes the clipboard as a floating selection, which can be dragged
+//! or nudged with the arrow keys until Enter or a click outside it places it;
+//! Escape drops it. The clipboard is kept when another map is opened, so tiles
+//! can be carried between maps.
+
+use std::collections::HashSet;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    autotile,
+    grid::{
+        contiguous, get_coords, rect, tile_sprite, ActionState, Brush, BrushTransform, GridSize,
+        SelectedBrush, TerrainBrush,
+    },
+    map::{MapData, PaintedTile},
+    AppState, MainCamera, MapSettings,
+};
+
+/// Floating tiles are drawn above every layer.
+const FLOATING_Z: f32 = 400.;
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .init_resource::<SelectTool>()
+            .init_resource::<Clipboard>()
+            .add_systems(
+                Update,
+                (select_cells, selection_keys, selection_ui, draw_selection)
+                    .chain()
+                    .run_if(in_state(AppState::Painting))
+                    .run_if(in_state(ActionState::Select)),
+            )
+            .add_systems(Update, draw_floating.run_if(in_state(AppState::Painting)))
+            .add_systems(OnExit(ActionState::Select), place_floating);
+    }
+}
+
+/// How clicking on the map selects cells.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelectTool {
+    #[default]
+    Marquee,
+    /// Contiguous cells holding the same tile.
+    Wand,
+    /// Every cell of the layer holding the same tile.
+    SameTile,
+}
+
+/// Whether two cells hold the same tile, whatever their flips.
+fn same_tile(a: Option<&PaintedTile>, b: Option<&PaintedTile>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.tileset == b.tileset && a.index == b.index,
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+/// Tiles copied from a set of cells, by offset from the corner of their
+/// bounds. Empty cells are left out, so pasting does not erase around the
+/// tiles.
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub size: IVec2,
+    pub cells: Vec<(IVec2, PaintedTile)>,
+}
+
+impl Clip {
+    /// The tiles of the active layer in the given cells.
+    pub fn copy(map: &MapData, cells: &[IVec2]) -> Option<Self> {
+        let min = cells.iter().copied().reduce(IVec2::min)?;
+        let max = cells.iter().copied().reduce(IVec2::max)?;
+        let cells = cells
+            .iter()
+            .filter_map(|cell| map.get(*cell).map(|tile| (*cell - min, tile.clone())))
+            .collect();
+        Some(Self {
+            size: max - min + 1,
+            cells,
+        })
+    }
+}
+
+/// Pasted tiles not yet written to the map.
+#[derive(Clone, Debug)]
+pub struct Floating {
+    pub origin: IVec2,
+    pub clip: Clip,
+}
+
+impl Floating {
+    pub fn area(&self) -> (IVec2, IVec2) {
+        (self.origin, self.origin + self.clip.size - 1)
+    }
+
+    fn contains(&self, cell: IVec2) -> bool {
+        let (min, max) = self.area();
+        (cell.cmpge(min) & cell.cmple(max)).all()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Selection {
+    /// Inclusive range of selected cells.
+    pub area: Option<(IVec2, IVec2)>,
+    /// Cells picked by tile, used instead of `area` when set.
+    pub picked: Option<HashSet<IVec2>>,
+    pub floating: Option<Floating>,
+}
+
+impl Selection {
+    /// The selected cells.
+    pub fn cells(&self) -> Vec<IVec2> {
+        if let Some(picked) = &self.picked {
+            return picked.iter().copied().collect();
+        }
+        self.area.map_or_else(Vec::new, |(min, max)| rect(min, max))
+    }
+
+    pub fn clear(&mut self) {
+        self.area = None;
+        self.picked = None;
+    }
+
+    /// Writes the floating tiles to the active layer.
+    pub fn place(&mut self, grid_size: &GridSize, map: &mut MapData) {
+        let Some(floating) = self.floating.take() else {
+            return;
+        };
+        for (offset, tile) in floating.clip.cells {
+            let cell = floating.origin + offset;
+            if grid_size.contains(cell) && map.get(cell) != Some(&tile) {
+                map.set(cell, Some(tile));
+            }
+        }
+    }
+
+    fn move_floating(&mut self, origin: IVec2) {
+        if let Some(floating) = &mut self.floating {
+            if floating.origin != origin {
+                floating.origin = origin;
+                self.area = Some(floating.area());
+            }
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Clipboard(pub Option<Clip>);
+
+pub fn clear_cells(map: &mut MapData, cells: &[IVec2]) {
+    for cell in cells {
+        if map.get(*cell).is_some() {
+            map.set(*cell, None);
+        }
+    }
+}
+
+/// The cells of the active layer holding the same tile as `start`: the
+/// contiguous ones, or all of them.
+fn pick_cells(
+    grid_size: &GridSize,
+    map: &MapData,
+    start: IVec2,
+    tool: SelectTool,
+) -> HashSet<IVec2> {
+    let target = map.get(start);
+    match tool {
+        SelectTool::Marquee => HashSet::new(),
+        SelectTool::Wand => contiguous(grid_size, map, start, |tile| same_tile(tile, target))
+            .into_iter()
+            .collect(),
+        SelectTool::SameTile => map
+            .tiles()
+            .filter(|(_, tile)| same_tile(Some(tile), target))
+            .map(|(cell, _)| cell)
+            .collect(),
+    }
+}
+
+enum Drag {
+    Marquee(IVec2),
+    /// Moving the floating tiles, from where they were when grabbed.
+    Move {
+        grabbed: IVec2,
+        origin: IVec2,
+    },
+}
+
+fn cursor_cell(
+    window: &Window,
+    camera: (&Camera, &GlobalTransform),
+    grid_size: &GridSize,
+) -> Option<IVec2> {
+    get_coords(window, camera.0, camera.1).and_then(|pos| grid_size.world_to_cell(pos))
+}
+
+fn select_cells(
+    mut contexts: EguiContexts,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    tool: Res<SelectTool>,
+    grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+    mut selection: ResMut<Selection>,
+    mut drag: Local<Option<Drag>>,
+) {
+    if buttons.just_released(MouseButton::Left) {
+        *drag = None;
+    }
+    if contexts.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+    let Some(cell) = cursor_cell(window.single(), camera.single(), &grid_size) else {
+        return;
+    };
+    if buttons.just_pressed(MouseButton::Left) {
+        match &selection.floating {
+            Some(floating) if floating.contains(cell) => {
+                *drag = Some(Drag::Move {
+                    grabbed: cell,
+                    origin: floating.origin,
+                });
+            }
+            _ if *tool == SelectTool::Marquee => {
+                selection.place(&grid_size, &mut map);
+                *drag = Some(Drag::Marquee(cell));
+                selection.picked = None;
+                selection.area = Some((cell, cell));
+            }
+            _ => {
+                selection.place(&grid_size, &mut map);
+                let mut picked = pick_cells(&grid_size, &map, cell, *tool);
+                if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+                    picked.extend(selection.cells());
+                }
+                selection.area = None;
+                selection.picked = Some(picked);
+            }
+        }
+    } else if buttons.pressed(MouseButton::Left) {
+        match *drag {
+            Some(Drag::Marquee(start)) => {
+                let area = Some((start.min(cell), start.max(cell)));
+                if selection.area != area {
+                    selection.area = area;
+                }
+            }
+            Some(Drag::Move { grabbed, origin }) => {
+                selection.move_floating(origin + cell - grabbed);
+            }
+            None => {}
+        }
+    }
+}
+
+fn selection_keys(
+    mut contexts: EguiContexts,
+    keys: Res<Input<KeyCode>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+    mut selection: ResMut<Selection>,
+    mut clipboard: ResMut<Clipboard>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let ctrl = keys.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::SuperLeft,
+        KeyCode::SuperRight,
+    ]);
+    if ctrl && keys.any_just_pressed([KeyCode::C, KeyCode::X]) && selection.floating.is_none() {
+        let cells = selection.cells();
+        if let Some(clip) = Clip::copy(&map, &cells) {
+            clipboard.0 = Some(clip);
+            if keys.just_pressed(KeyCode::X) {
+                clear_cells(&mut map, &cells);
+            }
+        }
+    }
+    if ctrl && keys.just_pressed(KeyCode::V) {
+        if let Some(clip) = &clipboard.0 {
+            selection.place(&grid_size, &mut map);
+            let origin = cursor_cell(window.single(), camera.single(), &grid_size)
+                .or(selection.cells().into_iter().reduce(IVec2::min))
+                .unwrap_or_default();
+            let floating = Floating {
+                origin,
+                clip: clip.clone(),
+            };
+            selection.picked = None;
+            selection.area = Some(floating.area());
+            selection.floating = Some(floating);
+        }
+    }
+    if keys.any_just_pressed([KeyCode::Delete, KeyCode::Back])
+        && selection.floating.take().is_none()
+    {
+        clear_cells(&mut map, &selection.cells());
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        selection.place(&grid_size, &mut map);
+    }
+    if keys.just_pressed(KeyCode::Escape) && selection.floating.take().is_none() {
+        selection.clear();
+    }
+    if let Some(origin) = selection.floating.as_ref().map(|f| f.origin) {
+        let down = grid_size.down();
+        let nudge = [
+            (KeyCode::Up, -down),
+            (KeyCode::Down, down),
+            (KeyCode::Left, IVec2::NEG_X),
+            (KeyCode::Right, IVec2::X),
+        ]
+        .into_iter()
+        .filter(|(key, _)| keys.just_pressed(*key))
+        .map(|(_, step)| step)
+        .sum::<IVec2>();
+        if nudge != IVec2::ZERO {
+            selection.move_floating(origin + nudge);
+        }
+    }
+}
+
+/// Fills the selection, replaces its tiles or clears it.
+fn selection_ui(
+    mut contexts: EguiContexts,
+    brush: Query<&Brush, With<SelectedBrush>>,
+    terrain_brush: Res<TerrainBrush>,
+    brush_transform: Res<BrushTransform>,
+    grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+    selection: Res<Selection>,
+) {
+    if selection.floating.is_some() || (selection.area.is_none() && selection.picked.is_none()) {
+        return;
+    }
+    let cells = selection.cells();
+    let mut fill = None;
+    let mut delete = false;
+    egui::Window::new("Selection").show(contexts.ctx_mut(), |ui| {
+        let painted = cells.iter().filter(|c| map.get(**c).is_some()).count();
+        ui.label(format!("{} cells, {painted} painted", cells.len()));
+        let can_paint = terrain_brush.0.is_some() || brush.get_single().is_ok();
+        ui.horizontal(|hui| {
+            if hui
+                .add_enabled(can_paint, egui::Button::new("Fill"))
+                .on_hover_text("Paint every selected cell with the brush")
+                .clicked()
+            {
+                fill = Some(false);
+            }
+            if hui
+                .add_enabled(can_paint, egui::Button::new("Replace"))
+                .on_hover_text("Paint only the selected cells holding a tile")
+                .clicked()
+            {
+                fill = Some(true);
+            }
+            if hui.button("Delete").clicked() {
+                delete = true;
+            }
+        });
+    });
+    if delete {
+        clear_cells(&mut map, &cells);
+    }
+    let Some(painted_only) = fill else {
+        return;
+    };
+    let cells: Vec<_> = cells
+        .into_iter()
+        .filter(|cell| grid_size.contains(*cell) && (!painted_only || map.get(*cell).is_some()))
+        .collect();
+    if let Some((tileset, set)) = &terrain_brush.0 {
+        autotile::paint_terrain(&grid_size, &mut map, tileset, set, &cells, false);
+    } else if let Ok(current_brush) = brush.get_single() {
+        let tile = PaintedTile {
+            tileset: current_brush.tileset.clone(),
+            index: current_brush.index,
+            transform: brush_transform.0,
+        };
+        for cell in cells {
+            if map.get(cell) != Some(&tile) {
+                map.set(cell, Some(tile.clone()));
+            }
+        }
+    }
+}
+
+fn draw_selection(selection: Res<Selection>, grid_size: Res<GridSize>, mut gizmos: Gizmos) {
+    let outlines = match (&selection.picked, selection.area) {
+        (Some(picked), _) => picked
+            .iter()
+            .flat_map(|cell| grid_size.outline(*cell, *cell))
+            .collect(),
+        (None, Some((min, max))) => grid_size.outline(min, max),
+        (None, None) => return,
+    };
+    for outline in outlines {
+        gizmos.linestrip_2d(outline, Color::YELLOW);
+    }
+}
+
+#[derive(Component)]
+struct FloatingTile;
+
+/// Keeps sprites drawn for the floating tiles.
+fn draw_floating(
+    mut commands: Commands,
+    sprites: Query<Entity, With<FloatingTile>>,
+    selection: Res<Selection>,
+    grid_size: Res<GridSize>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+) {
+    if !selection.is_changed() && !settings.is_changed() && !grid_size.is_changed() {
+        return;
+    }
+    for sprite in &sprites {
+        commands.entity(sprite).despawn();
+    }
+    let Some(floating) = &selection.floating else {
+        return;
+    };
+    for (offset, tile) in &floating.clip.cells {
+        let cell = floating.origin + *offset;
+        if !grid_size.contains(cell) {
+            continue;
+        }
+        let z = FLOATING_Z + grid_size.cell_depth(cell);
+        if let Some(bundle) = tile_sprite(&grid_size, &settings, &texture_atlases, cell, tile, z) {
+            commands.spawn((FloatingTile, bundle));
+        }
+    }
+}
+
+/// Leaving the Select tools places any floating tiles.
+fn place_floating(
+    grid_size: Res<GridSize>,
+    mut map: ResMut<MapData>,
+    mut selection: ResMut<Selection>,
+) {
+    selection.place(&grid_size, &mut map);
+    selection.clear();
+}
@@ -0,0 +1,342 @@
+//! Stamp brushes: blocks of tiles placed in one click, such as a house made
+//! of several atlas frames.
+//!
+//! Stamps are picked by dragging over the palette and can be kept in a named
+//! library at `assets/data/stamps.ron`, which is read at startup and written
+//! whenever it changes.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{asset::io::file::FileAssetReader, prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    grid::{get_coords, tile_sprite, ActionState, BrushTransform, GridSize, TerrainBrush},
+    map::{PaintedTile, TileTransform},
+    AppState, MainCamera, MapSettings,
+};
+
+pub const STAMPS_PATH: &str = "data/stamps.ron";
+
+/// Ghosts are drawn above every layer.
+const GHOST_Z: f32 = 500.;
+
+pub struct StampPlugin;
+
+impl Plugin for StampPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StampBrush>()
+            .init_resource::<StampLibrary>()
+            .add_systems(Startup, load_library)
+            .add_systems(Update, stamps_ui)
+            .add_systems(Update, preview_stamp.run_if(in_state(AppState::Painting)));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub name: String,
+    pub cells: Vec<StampCell>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StampCell {
+    /// Columns right and rows down from the stamp's top left cell, as drawn.
+    pub offset: IVec2,
+    pub tile: PaintedTile,
+}
+
+impl Stamp {
+    /// The frames of a rectangle of the palette, between two frame indices
+    /// of a tileset laid out `columns` wide.
+    pub fn from_palette(tileset: &str, columns: usize, from: usize, to: usize) -> Self {
+        let columns = columns.max(1);
+        let at = |index: usize| IVec2::new((index % columns) as i32, (index / columns) as i32);
+        let (from, to) = (at(from), at(to));
+        let (min, max) = (from.min(to), from.max(to));
+        let mut cells = Vec::new();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                cells.push(StampCell {
+                    offset: IVec2::new(x, y) - min,
+                    tile: PaintedTile {
+                        tileset: tileset.to_string(),
+                        index: y as usize * columns + x as usize,
+                        transform: TileTransform::default(),
+                    },
+                });
+            }
+        }
+        Self {
+            name: String::new(),
+            cells,
+        }
+    }
+
+    /// The tiles to paint with the stamp's top left at `cell`, the whole
+    /// block mirrored and turned by `transform`.
+    pub fn placed(
+        &self,
+        grid_size: &GridSize,
+        cell: IVec2,
+        transform: TileTransform,
+    ) -> Vec<(IVec2, PaintedTile)> {
+        // Offsets are turned on screen, where y points up.
+        let turned: Vec<_> = self
+            .cells
+            .iter()
+            .map(|c| transform.apply(IVec2::new(c.offset.x, -c.offset.y)))
+            .collect();
+        let Some(left) = turned.iter().map(|o| o.x).min() else {
+            return Vec::new();
+        };
+        let top = turned.iter().map(|o| o.y).max().unwrap();
+        let down = grid_size.down().y;
+        self.cells
+            .iter()
+            .zip(turned)
+            .map(|(c, offset)| {
+                let offset = IVec2::new(offset.x - left, (top - offset.y) * down);
+                let tile = PaintedTile {
+                    transform: transform.compose(c.tile.transform),
+                    ..c.tile.clone()
+                };
+                (cell + offset, tile)
+            })
+            .collect()
+    }
+}
+
+/// The stamp painted instead of the selected brush, if any.
+#[derive(Resource, Default)]
+pub struct StampBrush(pub Option<Stamp>);
+
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct StampLibrary {
+    pub stamps: Vec<Stamp>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum StampFileError {
+    #[error("Could not access stamp file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse stamp file: {0}")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("Could not write stamp file: {0}")]
+    Ron(#[from] ron::Error),
+}
+
+pub fn read_stamp_file(path: &Path) -> Result<StampLibrary, StampFileError> {
+    let contents = std::fs::read(path)?;
+    Ok(ron::de::from_bytes(&contents)?)
+}
+
+pub fn write_stamp_file(path: &Path, library: &StampLibrary) -> Result<(), StampFileError> {
+    let config = ron::ser::PrettyConfig::default().struct_names(true);
+    std::fs::write(path, ron::ser::to_string_pretty(library, config)?)?;
+    Ok(())
+}
+
+fn library_path() -> PathBuf {
+    FileAssetReader::get_base_path()
+        .join("assets")
+        .join(STAMPS_PATH)
+}
+
+fn load_library(mut library: ResMut<StampLibrary>) {
+    let path = library_path();
+    if !path.exists() {
+        return;
+    }
+    match read_stamp_file(&path) {
+        Ok(loaded) => *library = loaded,
+        Err(e) => error!("{e}"),
+    }
+}
+
+fn stamps_ui(
+    mut contexts: EguiContexts,
+    mut stamp: ResMut<StampBrush>,
+    mut library: ResMut<StampLibrary>,
+    mut terrain_brush: ResMut<TerrainBrush>,
+    mut next_action_state: ResMut<NextState<ActionState>>,
+    mut name: Local<String>,
+) {
+    let mut changed = false;
+    egui::Window::new("Stamps").show(contexts.ctx_mut(), |ui| {
+        let mut remove = None;
+        for (i, saved) in library.stamps.iter().enumerate() {
+            ui.horizontal(|hui| {
+                let selected = stamp.0.as_ref() == Some(saved);
+                if hui.selectable_label(selected, &saved.name).clicked() {
+                    stamp.0 = Some(saved.clone());
+                    terrain_brush.0 = None;
+                    next_action_state.set(ActionState::Paint);
+                }
+                if hui.small_button("Delete").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            library.stamps.remove(i);
+            changed = true;
+        }
+        let Some(current) = stamp.0.clone() else {
+            ui.label("Drag over the palette to make a stamp.");
+            return;
+        };
+        ui.label(format!("Current stamp: {} tiles", current.cells.len()));
+        ui.horizontal(|hui| {
+            hui.text_edit_singleline(&mut *name);
+            let new_name = name.trim().to_string();
+            let taken = library.stamps.iter().any(|s| s.name == new_name);
+            let valid = !new_name.is_empty() && !taken;
+            if hui.add_enabled(valid, egui::Button::new("Save")).clicked() {
+                let saved = Stamp {
+                    name: new_name,
+                    ..current
+                };
+                library.stamps.push(saved.clone());
+                stamp.0 = Some(saved);
+                name.clear();
+                changed = true;
+            }
+        });
+        if ui.button("Clear").clicked() {
+            stamp.0 = None;
+        }
+    });
+    if changed {
+        if let Err(e) = write_stamp_file(&library_path(), &library) {
+            error!("{e}");
+        }
+    }
+}
+
+#[derive(Component)]
+struct StampGhost;
+
+/// Draws the stamp faintly where it would be placed.
+fn preview_stamp(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    ghosts: Query<Entity, With<StampGhost>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    stamp: Res<StampBrush>,
+    brush_transform: Res<BrushTransform>,
+    action_state: Res<State<ActionState>>,
+    grid_size: Res<GridSize>,
+    settings: Res<MapSettings>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut last: Local<Option<IVec2>>,
+) {
+    let (cam, cam_transform) = camera.single();
+    let cell = (*action_state.get() == ActionState::Paint
+        && !contexts.ctx_mut().is_pointer_over_area())
+    .then(|| get_coords(window.single(), cam, cam_transform))
+    .flatten()
+    .and_then(|pos| grid_size.world_to_cell(pos))
+    .filter(|_| stamp.0.is_some());
+    let unchanged = !stamp.is_changed()
+        && !brush_transform.is_changed()
+        && !settings.is_changed()
+        && !grid_size.is_changed();
+    if *last == cell && unchanged {
+        return;
+    }
+    *last = cell;
+    for ghost in &ghosts {
+        commands.entity(ghost).despawn();
+    }
+    let (Some(cell), Some(stamp)) = (cell, &stamp.0) else {
+        return;
+    };
+    for (target, tile) in stamp.placed(&grid_size, cell, brush_transform.0) {
+        if !grid_size.contains(target) {
+            continue;
+        }
+        let z = GHOST_Z + grid_size.cell_depth(target);
+        if let Some(mut bundle) =
+            tile_sprite(&grid_size, &settings, &texture_atlases, target, &tile, z)
+        {
+            bundle.sprite.color = Color::rgba(1., 1., 1., 0.5);
+            commands.spawn((StampGhost, bundle));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Orientation;
+
+    fn indices(placed: &[(IVec2, PaintedTile)]) -> Vec<(IVec2, usize)> {
+        placed
+            .iter()
+            .map(|(cell, tile)| (*cell, tile.index))
+            .collect()
+    }
+
+    #[test]
+    fn palette_rectangle_is_picked_either_way() {
+        let stamp = Stamp::from_palette("house.png", 4, 6, 1);
+        let picked: Vec<_> = stamp
+            .cells
+            .iter()
+            .map(|c| (c.offset, c.tile.index))
+            .collect();
+        assert_eq!(
+            picked,
+            [
+                (IVec2::new(0, 0), 1),
+                (IVec2::new(1, 0), 2),
+                (IVec2::new(0, 1), 5),
+                (IVec2::new(1, 1), 6)
+            ]
+        );
+        assert_eq!(stamp, Stamp::from_palette("house.png", 4, 1, 6));
+    }
+
+    #[test]
+    fn rows_go_down_the_screen() {
+        let stamp = Stamp::from_palette("house.png", 2, 0, 3);
+        let at = IVec2::new(5, 5);
+        let mut grid_size = GridSize::default();
+        let placed = stamp.placed(&grid_size, at, default());
+        assert_eq!(
+            indices(&placed),
+            [
+                (IVec2::new(5, 5), 0),
+                (IVec2::new(6, 5), 1),
+                (IVec2::new(5, 4), 2),
+                (IVec2::new(6, 4), 3)
+            ]
+        );
+        grid_size.orientation = Orientation::Isometric;
+        let placed = stamp.placed(&grid_size, at, default());
+        assert_eq!(placed[2].0, IVec2::new(5, 6));
+    }
+
+    #[test]
+    fn transforms_move_the_whole_block() {
+        let stamp = Stamp::from_palette("fence.png", 2, 0, 1);
+        let grid_size = GridSize::default();
+        let mirrored = stamp.placed(&grid_size, IVec2::ZERO, TileTransform::default().flip_x());
+        assert_eq!(
+            indices(&mirrored),
+            [(IVec2::new(1, 0), 0), (IVec2::new(0, 0), 1)]
+        );
+        let turn = TileTransform::default().rotate();
+        let turned = stamp.placed(&grid_size, IVec2::ZERO, turn);
+        assert_eq!(
+            indices(&turned),
+            [(IVec2::new(0, 0), 0), (IVec2::new(0, -1), 1)]
+        );
+        assert!(turned.iter().all(|(_, tile)| tile.transform == turn));
+    }
+}
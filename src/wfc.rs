@@ -0,0 +1,846 @@
+//! Wave function collapse: fills cells so that every pair of neighbours is
+//! one that was seen together, either in a painted example layer or in the
+//! adjacency rules of a tileset definition.
+//!
+//! The simple tiled model only learns which tile may sit next to which. The
+//! overlapping model learns every N×N block of the example and places blocks
+//! that agree where they overlap, which keeps larger shapes intact. Both only
+//! constrain row and column neighbours, hexagonal maps included.
+//!
+//! A run only depends on the model, the cells and the seed, so the same seed
+//! always gives the same result.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet},
+};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use thiserror::Error;
+
+use crate::{
+    assets::{Adjacency, TileDefinition},
+    generator::{region, size_ui, Generated, GeneratedPreview, DEFAULT_SIZE},
+    grid::{rect, GridSize},
+    map::{MapData, PaintedTile, TileTransform},
+    selection::Selection,
+    TilesData,
+};
+
+pub struct WfcPlugin;
+
+impl Plugin for WfcPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WfcGenerator>()
+            .add_systems(Update, wfc_ui);
+    }
+}
+
+/// The states a cell may still take, one bit each.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct States(Vec<u64>);
+
+impl States {
+    fn none(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
+    }
+
+    fn all(len: usize) -> Self {
+        let mut states = Self::none(len);
+        for state in 0..len {
+            states.insert(state);
+        }
+        states
+    }
+
+    fn insert(&mut self, state: usize) {
+        self.0[state / 64] |= 1 << (state % 64);
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|word| *word == 0)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words(|word, _| word)
+    }
+
+    /// The states not in `other`.
+    fn difference<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = usize> + 'a {
+        self.words(|word, i| word & !other.0[i])
+    }
+
+    /// The set bits of each word after `f`, as states.
+    fn words<'a>(&'a self, f: impl Fn(u64, usize) -> u64 + 'a) -> impl Iterator<Item = usize> + 'a {
+        self.0.iter().enumerate().flat_map(move |(i, word)| {
+            let mut word = f(*word, i);
+            std::iter::from_fn(move || {
+                let bit = (word != 0).then(|| word.trailing_zeros() as usize)?;
+                word &= word - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    /// Keeps the states also in `other`, returning whether any were removed.
+    fn intersect_with(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            changed |= *word & !other != 0;
+            *word &= other;
+        }
+        changed
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum WfcError {
+    #[error("The example layer has no painted tiles")]
+    EmptyExample,
+    #[error("The painted area of the example is smaller than {0}x{0} cells")]
+    ExampleTooSmall(usize),
+    #[error("Tileset {0} has no adjacency rules")]
+    NoRules(String),
+    #[error("Nothing to fill")]
+    NoCells,
+}
+
+/// Where a run failed.
+#[derive(Debug)]
+pub struct Contradiction {
+    /// The cell left with no possible tile, then its neighbours.
+    pub cells: Vec<IVec2>,
+    /// Cells decided before the run failed.
+    pub partial: Vec<(IVec2, Option<PaintedTile>)>,
+}
+
+/// The possible states of a cell and which may neighbour which.
+pub struct Model {
+    /// The tile painted for each state.
+    tiles: Vec<Option<PaintedTile>>,
+    weights: Vec<f64>,
+    /// Offsets to the neighbours a cell's state constrains.
+    offsets: Vec<IVec2>,
+    /// `allowed[o][a]` holds the states allowed at `cell + offsets[o]` while
+    /// `cell` is in state `a`.
+    allowed: Vec<Vec<States>>,
+}
+
+const SIDES: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// Cells sorted by row, so that learning doesn't depend on storage order.
+fn sorted<'a>(
+    tiles: impl IntoIterator<Item = (IVec2, &'a PaintedTile)>,
+) -> Vec<(IVec2, PaintedTile)> {
+    let mut tiles: Vec<_> = tiles
+        .into_iter()
+        .map(|(cell, tile)| (cell, tile.clone()))
+        .collect();
+    tiles.sort_by_key(|(cell, _)| (cell.y, cell.x));
+    tiles
+}
+
+impl Model {
+    fn empty(tiles: Vec<Option<PaintedTile>>, weights: Vec<f64>, offsets: Vec<IVec2>) -> Self {
+        let allowed = offsets
+            .iter()
+            .map(|_| vec![States::none(tiles.len()); tiles.len()])
+            .collect();
+        Self {
+            tiles,
+            weights,
+            offsets,
+            allowed,
+        }
+    }
+
+    fn offset_index(&self, offset: IVec2) -> usize {
+        self.offsets.iter().position(|o| *o == offset).unwrap()
+    }
+
+    /// Allows state `b` at `offset` from state `a`, and `a` the other way.
+    fn allow(&mut self, a: usize, offset: IVec2, b: usize) {
+        let forward = self.offset_index(offset);
+        let back = self.offset_index(-offset);
+        self.allowed[forward][a].insert(b);
+        self.allowed[back][b].insert(a);
+    }
+
+    /// Learns which tiles were painted next to each other, and how often
+    /// each tile was used.
+    pub fn simple_tiled<'a>(
+        example: impl IntoIterator<Item = (IVec2, &'a PaintedTile)>,
+    ) -> Result<Self, WfcError> {
+        let example = sorted(example);
+        if example.is_empty() {
+            return Err(WfcError::EmptyExample);
+        }
+        let mut states = HashMap::new();
+        let mut tiles = Vec::new();
+        let mut weights = Vec::new();
+        for (_, tile) in &example {
+            let state = *states.entry(tile.clone()).or_insert_with(|| {
+                tiles.push(Some(tile.clone()));
+                weights.push(0.);
+                tiles.len() - 1
+            });
+            weights[state] += 1.;
+        }
+        let mut model = Self::empty(tiles, weights, SIDES.to_vec());
+        let at: HashMap<_, _> = example
+            .iter()
+            .map(|(cell, tile)| (*cell, &states[tile]))
+            .collect();
+        for (cell, a) in &at {
+            for side in [IVec2::X, IVec2::Y] {
+                if let Some(b) = at.get(&(*cell + side)) {
+                    model.allow(**a, side, **b);
+                }
+            }
+        }
+        Ok(model)
+    }
+
+    /// Uses the adjacency rules of a tileset. `down` is the cell offset
+    /// that is down on screen.
+    pub fn from_rules(tileset: &str, rules: &Adjacency, down: IVec2) -> Result<Self, WfcError> {
+        if rules.is_empty() {
+            return Err(WfcError::NoRules(tileset.to_string()));
+        }
+        let frames: BTreeSet<usize> = rules
+            .horizontal
+            .iter()
+            .chain(&rules.vertical)
+            .flat_map(|(a, b)| [*a, *b])
+            .collect();
+        let frames: Vec<_> = frames.into_iter().collect();
+        let tiles = frames
+            .iter()
+            .map(|index| {
+                Some(PaintedTile {
+                    tileset: tileset.to_string(),
+                    index: *index,
+                    transform: TileTransform::default(),
+                })
+            })
+            .collect();
+        let weights = frames
+            .iter()
+            .map(|index| rules.weights.get(index).map_or(1., |w| *w as f64))
+            .collect();
+        let mut model = Self::empty(tiles, weights, SIDES.to_vec());
+        let state = |index: &usize| frames.binary_search(index).unwrap();
+        for (a, b) in &rules.horizontal {
+            model.allow(state(a), IVec2::X, state(b));
+        }
+        for (a, b) in &rules.vertical {
+            model.allow(state(a), down, state(b));
+        }
+        Ok(model)
+    }
+
+    /// Learns every `n`×`n` block of the painted area of the example, empty
+    /// cells included. Each state is a block, painting its first cell.
+    pub fn overlapping<'a>(
+        example: impl IntoIterator<Item = (IVec2, &'a PaintedTile)>,
+        n: usize,
+    ) -> Result<Self, WfcError> {
+        let example: HashMap<_, _> = sorted(example).into_iter().collect();
+        let min = example.keys().copied().reduce(IVec2::min);
+        let max = example.keys().copied().reduce(IVec2::max);
+        let (Some(min), Some(max)) = (min, max) else {
+            return Err(WfcError::EmptyExample);
+        };
+        let n = n.max(1) as i32;
+        if max.x - min.x + 1 < n || max.y - min.y + 1 < n {
+            return Err(WfcError::ExampleTooSmall(n as usize));
+        }
+        let mut states = HashMap::new();
+        let mut patterns: Vec<Vec<Option<PaintedTile>>> = Vec::new();
+        let mut weights = Vec::new();
+        for origin in rect(min, max - (n - 1)) {
+            let pattern: Vec<_> = rect(IVec2::ZERO, IVec2::splat(n - 1))
+                .into_iter()
+                .map(|offset| example.get(&(origin + offset)).cloned())
+                .collect();
+            let state = *states.entry(pattern.clone()).or_insert_with(|| {
+                patterns.push(pattern);
+                weights.push(0.);
+                patterns.len() - 1
+            });
+            weights[state] += 1.;
+        }
+        let offsets: Vec<_> = rect(IVec2::splat(1 - n), IVec2::splat(n - 1))
+            .into_iter()
+            .filter(|offset| *offset != IVec2::ZERO)
+            .collect();
+        let tiles = patterns.iter().map(|pattern| pattern[0].clone()).collect();
+        let mut model = Self::empty(tiles, weights, offsets.clone());
+        let at = |cell: IVec2| (cell.y * n + cell.x) as usize;
+        for (o, offset) in offsets.iter().enumerate() {
+            // Cells of `a` also covered by `b` placed at `offset`.
+            let overlap: Vec<_> = rect(IVec2::ZERO, IVec2::splat(n - 1))
+                .into_iter()
+                .filter(|cell| {
+                    let other = *cell - *offset;
+                    other.cmpge(IVec2::ZERO).all() && other.cmplt(IVec2::splat(n)).all()
+                })
+                .collect();
+            for (a, first) in patterns.iter().enumerate() {
+                for (b, second) in patterns.iter().enumerate() {
+                    if overlap
+                        .iter()
+                        .all(|cell| first[at(*cell)] == second[at(*cell - *offset)])
+                    {
+                        model.allowed[o][a].insert(b);
+                    }
+                }
+            }
+        }
+        Ok(model)
+    }
+
+    /// The starting states of `cells`, which must fit any painted
+    /// neighbours outside of them that the model knows.
+    pub fn initial_wave<'a>(
+        &self,
+        cells: &[IVec2],
+        outside: impl Fn(IVec2) -> Option<&'a PaintedTile>,
+    ) -> Vec<States> {
+        let inside: HashSet<_> = cells.iter().copied().collect();
+        let states = self.tiles.len();
+        cells
+            .iter()
+            .map(|cell| {
+                let mut wave = States::all(states);
+                for (o, offset) in self.offsets.iter().enumerate() {
+                    let neighbour = *cell + *offset;
+                    if inside.contains(&neighbour) {
+                        continue;
+                    }
+                    let Some(tile) = outside(neighbour) else {
+                        continue;
+                    };
+                    let mut fits = States::none(states);
+                    for b in (0..states).filter(|b| self.tiles[*b].as_ref() == Some(tile)) {
+                        fits.insert(b);
+                    }
+                    if fits.is_empty() {
+                        continue;
+                    }
+                    let mut allowed = States::none(states);
+                    for a in 0..states {
+                        let mut next_to = self.allowed[o][a].clone();
+                        next_to.intersect_with(&fits);
+                        if !next_to.is_empty() {
+                            allowed.insert(a);
+                        }
+                    }
+                    wave.intersect_with(&allowed);
+                }
+                wave
+            })
+            .collect()
+    }
+
+    /// Collapses `cells`, starting from `wave`, until each has one state.
+    pub fn solve(
+        &self,
+        cells: &[IVec2],
+        mut wave: Vec<States>,
+        seed: u64,
+    ) -> Result<Vec<(IVec2, Option<PaintedTile>)>, Contradiction> {
+        let index: HashMap<_, _> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| (*cell, i))
+            .collect();
+        let neighbours: Vec<Vec<Option<usize>>> = cells
+            .iter()
+            .map(|cell| {
+                self.offsets
+                    .iter()
+                    .map(|offset| index.get(&(*cell + *offset)).copied())
+                    .collect()
+            })
+            .collect();
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let mut changed: Vec<usize> = (0..cells.len()).collect();
+        if let Some(i) = wave.iter().position(States::is_empty) {
+            return Err(self.contradiction(cells, &wave, &neighbours, i));
+        }
+        let mut entropies = Entropies::new(self, &wave, &mut rng);
+        loop {
+            if let Err(i) = self.propagate(&mut wave, &neighbours, &mut changed, &mut entropies) {
+                return Err(self.contradiction(cells, &wave, &neighbours, i));
+            }
+            let Some(i) = entropies.lowest(&wave) else {
+                break;
+            };
+            let state = self.pick(&wave[i], &mut rng);
+            wave[i] = States::none(self.tiles.len());
+            wave[i].insert(state);
+            changed.push(i);
+        }
+        Ok(cells
+            .iter()
+            .zip(&wave)
+            .map(|(cell, states)| (*cell, self.tiles[states.iter().next().unwrap()].clone()))
+            .collect())
+    }
+
+    /// Narrows the neighbours of changed cells until nothing changes, or
+    /// returns the first cell left with no state.
+    fn propagate(
+        &self,
+        wave: &mut [States],
+        neighbours: &[Vec<Option<usize>>],
+        changed: &mut Vec<usize>,
+        entropies: &mut Entropies,
+    ) -> Result<(), usize> {
+        while let Some(i) = changed.pop() {
+            for (o, neighbour) in neighbours[i].iter().enumerate() {
+                let Some(j) = *neighbour else {
+                    continue;
+                };
+                let mut allowed = States::none(self.tiles.len());
+                for a in wave[i].iter() {
+                    allowed.union_with(&self.allowed[o][a]);
+                }
+                let mut narrowed = false;
+                for state in wave[j].difference(&allowed) {
+                    entropies.remove(self, j, state);
+                    narrowed = true;
+                }
+                if narrowed {
+                    wave[j].intersect_with(&allowed);
+                    if wave[j].is_empty() {
+                        changed.clear();
+                        return Err(j);
+                    }
+                    entropies.push(j, &wave[j]);
+                    changed.push(j);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn weight(&self, state: usize) -> f64 {
+        self.weights[state].max(1e-3)
+    }
+
+    fn pick(&self, states: &States, rng: &mut fastrand::Rng) -> usize {
+        let total: f64 = states.iter().map(|state| self.weight(state)).sum();
+        let mut roll = rng.f64() * total;
+        let mut last = 0;
+        for state in states.iter() {
+            if roll < self.weight(state) {
+                return state;
+            }
+            roll -= self.weight(state);
+            last = state;
+        }
+        // Rounding can leave the roll just past the last weight.
+        last
+    }
+
+    fn contradiction(
+        &self,
+        cells: &[IVec2],
+        wave: &[States],
+        neighbours: &[Vec<Option<usize>>],
+        failed: usize,
+    ) -> Contradiction {
+        let mut conflicting = vec![cells[failed]];
+        conflicting.extend(neighbours[failed].iter().flatten().map(|j| cells[*j]));
+        let partial = cells
+            .iter()
+            .zip(wave)
+            .filter(|(_, states)| states.len() == 1)
+            .map(|(cell, states)| (*cell, self.tiles[states.iter().next().unwrap()].clone()))
+            .collect();
+        Contradiction {
+            cells: conflicting,
+            partial,
+        }
+    }
+}
+
+/// Entropy of each cell for [`Model::solve`], kept up to date as states are
+/// removed. Undecided cells sit in a heap by entropy; entries left behind when
+/// a cell narrows again are skipped when popped.
+struct Entropies {
+    sum: Vec<f64>,
+    sum_log: Vec<f64>,
+    /// Breaks ties between cells of equal entropy.
+    noise: Vec<f64>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl Entropies {
+    fn new(model: &Model, wave: &[States], rng: &mut fastrand::Rng) -> Self {
+        let mut entropies = Self {
+            sum: vec![0.; wave.len()],
+            sum_log: vec![0.; wave.len()],
+            noise: wave.iter().map(|_| rng.f64() * 1e-6).collect(),
+            heap: BinaryHeap::with_capacity(wave.len()),
+        };
+        for (i, states) in wave.iter().enumerate() {
+            for state in states.iter() {
+                let weight = model.weight(state);
+                entropies.sum[i] += weight;
+                entropies.sum_log[i] += weight * weight.ln();
+            }
+            entropies.push(i, states);
+        }
+        entropies
+    }
+
+    fn remove(&mut self, model: &Model, i: usize, state: usize) {
+        let weight = model.weight(state);
+        self.sum[i] -= weight;
+        self.sum_log[i] -= weight * weight.ln();
+    }
+
+    /// The entropy as bits, which order like the value as it is never
+    /// negative.
+    fn entropy(&self, i: usize) -> u64 {
+        let entropy = self.sum[i].ln() - self.sum_log[i] / self.sum[i] + self.noise[i];
+        entropy.max(0.).to_bits()
+    }
+
+    fn push(&mut self, i: usize, states: &States) {
+        if states.len() > 1 {
+            self.heap.push(Reverse((self.entropy(i), i)));
+        }
+    }
+
+    /// The undecided cell with the fewest likely states.
+    fn lowest(&mut self, wave: &[States]) -> Option<usize> {
+        while let Some(Reverse((entropy, i))) = self.heap.pop() {
+            if wave[i].len() > 1 && entropy == self.entropy(i) {
+                return Some(i);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WfcKind {
+    #[default]
+    SimpleTiled,
+    Overlapping,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WfcSettings {
+    pub kind: WfcKind,
+    /// Block size of the overlapping model.
+    pub pattern_size: usize,
+    /// Learn from the rules of `tileset` instead of the example layer.
+    pub use_rules: bool,
+    pub example_layer: usize,
+    pub tileset: String,
+    /// Fill the selected cells instead of the whole map.
+    pub in_selection: bool,
+    pub seed: u32,
+    /// Seeds tried, counting up from `seed`, before giving up.
+    pub attempts: u32,
+    /// Size generated on infinite maps.
+    pub size: UVec2,
+    error: Option<String>,
+}
+
+impl Default for WfcSettings {
+    fn default() -> Self {
+        Self {
+            kind: WfcKind::default(),
+            pattern_size: 2,
+            use_rules: false,
+            example_layer: 0,
+            tileset: String::new(),
+            in_selection: false,
+            seed: 0,
+            attempts: 10,
+            size: DEFAULT_SIZE,
+            error: None,
+        }
+    }
+}
+
+impl WfcSettings {
+    pub fn model(
+        &self,
+        map: &MapData,
+        rules: Option<&Adjacency>,
+        down: IVec2,
+    ) -> Result<Model, WfcError> {
+        if self.use_rules && self.kind == WfcKind::SimpleTiled {
+            let rules = rules.ok_or_else(|| WfcError::NoRules(self.tileset.clone()))?;
+            return Model::from_rules(&self.tileset, rules, down);
+        }
+        let example = map.tiles_in(self.example_layer.min(map.layers().len() - 1));
+        match self.kind {
+            WfcKind::SimpleTiled => Model::simple_tiled(example),
+            WfcKind::Overlapping => Model::overlapping(example, self.pattern_size),
+        }
+    }
+
+    /// Fills `cells` of the active layer, trying seeds until one works.
+    pub fn generate(
+        &self,
+        model: &Model,
+        cells: &[IVec2],
+        map: &MapData,
+    ) -> Result<Generated, WfcError> {
+        if cells.is_empty() {
+            return Err(WfcError::NoCells);
+        }
+        let wave = model.initial_wave(cells, |cell| map.get(cell));
+        let mut failed = None;
+        for attempt in 0..self.attempts.max(1) {
+            let seed = self.seed.wrapping_add(attempt);
+            match model.solve(cells, wave.clone(), seed.into()) {
+                Ok(solved) => {
+                    let mut generated =
+                        Generated::new(format!("Wave function collapse, seed {seed}"));
+                    generated.cells = solved;
+                    return Ok(generated);
+                }
+                Err(contradiction) => failed = Some(contradiction),
+            }
+        }
+        let contradiction = failed.unwrap();
+        let mut generated = Generated::new(format!("Wave function collapse, seed {}", self.seed));
+        generated.message = Some(format!(
+            "Contradiction in every one of {} attempts, the last one's conflicting cells are marked",
+            self.attempts.max(1)
+        ));
+        generated.cells = contradiction.partial;
+        generated.marked = contradiction.cells;
+        Ok(generated)
+    }
+}
+
+/// The open wave function collapse window, if any.
+#[derive(Resource, Default)]
+pub struct WfcGenerator(pub Option<WfcSettings>);
+
+fn wfc_ui(
+    mut contexts: EguiContexts,
+    mut generator: ResMut<WfcGenerator>,
+    mut preview: ResMut<GeneratedPreview>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    map: Res<MapData>,
+    selection: Res<Selection>,
+    grid_size: Res<GridSize>,
+) {
+    let Some(wfc) = &mut generator.0 else {
+        return;
+    };
+    let with_rules: Vec<_> = tile_handle
+        .tiles(&tile_assets)
+        .filter(|tile| !tile.adjacency.is_empty())
+        .map(|tile| (tile.path.clone(), tile.name.clone(), tile.adjacency.clone()))
+        .collect();
+    if !with_rules.iter().any(|(path, ..)| *path == wfc.tileset) {
+        if let Some((path, ..)) = with_rules.first() {
+            wfc.tileset = path.clone();
+        }
+    }
+    let selected = selection.cells();
+    let mut open = true;
+    let mut generate = false;
+    egui::Window::new("Wave Function Collapse")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|hui| {
+                hui.radio_value(&mut wfc.kind, WfcKind::SimpleTiled, "Simple tiled");
+                hui.radio_value(&mut wfc.kind, WfcKind::Overlapping, "Overlapping");
+            });
+            if wfc.kind == WfcKind::Overlapping {
+                wfc.use_rules = false;
+                ui.horizontal(|hui| {
+                    hui.label("Pattern size");
+                    hui.add(egui::DragValue::new(&mut wfc.pattern_size).clamp_range(2..=4));
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|hui| {
+                hui.radio_value(&mut wfc.use_rules, false, "Learn from layer");
+                let layers = map.layers();
+                let name = layers
+                    .get(wfc.example_layer)
+                    .map_or("", |layer| layer.name.as_str());
+                egui::ComboBox::from_id_source("wfc-layer")
+                    .selected_text(name)
+                    .show_ui(hui, |cui| {
+                        for (i, layer) in layers.iter().enumerate() {
+                            cui.selectable_value(&mut wfc.example_layer, i, &layer.name);
+                        }
+                    });
+            });
+            if wfc.kind == WfcKind::SimpleTiled {
+                ui.horizontal(|hui| {
+                    hui.add_enabled_ui(!with_rules.is_empty(), |eui| {
+                        eui.radio_value(&mut wfc.use_rules, true, "Tileset rules");
+                        let name = with_rules
+                            .iter()
+                            .find(|(path, ..)| *path == wfc.tileset)
+                            .map_or("", |(_, name, _)| name.as_str());
+                        egui::ComboBox::from_id_source("wfc-rules")
+                            .selected_text(name)
+                            .show_ui(eui, |cui| {
+                                for (path, name, _) in &with_rules {
+                                    cui.selectable_value(&mut wfc.tileset, path.clone(), name);
+                                }
+                            });
+                    });
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|hui| {
+                hui.radio_value(&mut wfc.in_selection, false, "Whole map");
+                hui.add_enabled_ui(!selected.is_empty(), |eui| {
+                    eui.radio_value(&mut wfc.in_selection, true, "Selection");
+                });
+            });
+            if selected.is_empty() {
+                wfc.in_selection = false;
+            }
+            if !wfc.in_selection {
+                size_ui(ui, &grid_size, &mut wfc.size);
+            }
+            ui.horizontal(|hui| {
+                hui.label("Seed");
+                hui.add(egui::DragValue::new(&mut wfc.seed));
+                if hui.button("New Seed").clicked() {
+                    wfc.seed = fastrand::u32(..);
+                    generate = true;
+                }
+            });
+            ui.horizontal(|hui| {
+                hui.label("Attempts");
+                hui.add(egui::DragValue::new(&mut wfc.attempts).clamp_range(1..=100));
+            });
+            if let Some(error) = &wfc.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+            generate |= ui.button("Preview").clicked();
+        });
+    if !open {
+        generator.0 = None;
+        return;
+    }
+    if !generate {
+        return;
+    }
+    let mut cells = if wfc.in_selection {
+        selected
+    } else {
+        let (min, size) = region(&grid_size, wfc.size);
+        rect(min, min + size.as_ivec2() - 1)
+    };
+    cells.retain(|cell| grid_size.contains(*cell));
+    cells.sort_by_key(|cell| (cell.y, cell.x));
+    let rules = with_rules
+        .iter()
+        .find(|(path, ..)| *path == wfc.tileset)
+        .map(|(.., rules)| rules);
+    let generated = wfc
+        .model(&map, rules, grid_size.down())
+        .and_then(|model| wfc.generate(&model, &cells, &map));
+    match generated {
+        Ok(generated) => {
+            wfc.error = None;
+            preview.0 = Some(generated);
+        }
+        Err(e) => wfc.error = Some(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(index: usize) -> PaintedTile {
+        PaintedTile {
+            tileset: "tiles.png".into(),
+            index,
+            transform: TileTransform::default(),
+        }
+    }
+
+    fn states(states: &[usize]) -> States {
+        let mut set = States::none(3);
+        for state in states {
+            set.insert(*state);
+        }
+        set
+    }
+
+    #[test]
+    fn same_seed_gives_same_output() {
+        let example: Vec<_> = rect(IVec2::ZERO, IVec2::splat(3))
+            .into_iter()
+            .map(|cell| (cell, tile(((cell.x + cell.y) % 3) as usize)))
+            .collect();
+        let model = Model::simple_tiled(example.iter().map(|(cell, tile)| (*cell, tile))).unwrap();
+        let cells = rect(IVec2::ZERO, IVec2::splat(7));
+        let wave = model.initial_wave(&cells, |_| None);
+        let first = model.solve(&cells, wave.clone(), 7).unwrap();
+        let second = model.solve(&cells, wave, 7).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn simple_tiled_learns_painted_neighbours_only() {
+        // Row order gives 0 at the origin, 1 to its right and 2 above it.
+        let example = [
+            (IVec2::new(0, 0), tile(0)),
+            (IVec2::new(1, 0), tile(1)),
+            (IVec2::new(0, 1), tile(2)),
+        ];
+        let model = Model::simple_tiled(example.iter().map(|(cell, tile)| (*cell, tile))).unwrap();
+        let allowed = |offset, state: usize| &model.allowed[model.offset_index(offset)][state];
+        assert_eq!(allowed(IVec2::X, 0), &states(&[1]));
+        assert_eq!(allowed(IVec2::NEG_X, 1), &states(&[0]));
+        assert_eq!(allowed(IVec2::Y, 0), &states(&[2]));
+        assert_eq!(allowed(IVec2::NEG_Y, 2), &states(&[0]));
+        assert_eq!(allowed(IVec2::X, 1), &states(&[]));
+        assert_eq!(allowed(IVec2::X, 2), &states(&[]));
+        assert_eq!(allowed(IVec2::Y, 1), &states(&[]));
+    }
+
+    #[test]
+    fn contradictory_neighbours_are_marked() {
+        // Nothing was ever painted to the left of 0.
+        let example = [(IVec2::new(0, 0), tile(0)), (IVec2::new(1, 0), tile(1))];
+        let model = Model::simple_tiled(example.iter().map(|(cell, tile)| (*cell, tile))).unwrap();
+        let mut map = MapData::default();
+        map.set(IVec2::new(0, 0), Some(tile(0)));
+        map.set(IVec2::new(2, 0), Some(tile(0)));
+        let generated = WfcSettings::default()
+            .generate(&model, &[IVec2::new(1, 0)], &map)
+            .unwrap();
+        assert!(generated.message.is_some());
+        assert!(generated.marked.contains(&IVec2::new(1, 0)));
+    }
+}
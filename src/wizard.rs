@@ -0,0 +1,281 @@
+//! A wizard for slicing a spritesheet into a new tileset entry.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{asset::io::file::FileAssetReader, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+use egui_file::FileDialog;
+
+use crate::{
+    assets::{append_tile, AtlasDefinition, Tile, TileDefinition},
+    project::{asset_file, CurrentProject, ProjectRoots},
+    TilesData,
+};
+
+/// Where images picked from outside the assets folder are copied to.
+const IMPORT_DIR: &str = "tiles";
+
+pub struct WizardPlugin;
+
+impl Plugin for WizardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TilesetWizard>()
+            .add_systems(Update, wizard_ui);
+    }
+}
+
+/// The open wizard, if any.
+#[derive(Resource, Default)]
+pub struct TilesetWizard(pub Option<WizardState>);
+
+pub struct WizardState {
+    dialog: Option<FileDialog>,
+    image: Option<(String, Handle<Image>)>,
+    name: String,
+    tile_size: Vec2,
+    padding: Vec2,
+    offset: Vec2,
+    zoom: f32,
+    /// Which of the project's definition files the tileset is added to.
+    target: usize,
+    error: Option<String>,
+}
+
+fn png_dialog() -> FileDialog {
+    let mut dialog = FileDialog::open_file(None).show_files_filter(Box::new(|path: &Path| {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+    }));
+    dialog.open();
+    dialog
+}
+
+impl Default for WizardState {
+    fn default() -> Self {
+        Self {
+            dialog: Some(png_dialog()),
+            image: None,
+            name: String::new(),
+            tile_size: Vec2::splat(16.),
+            padding: Vec2::ZERO,
+            offset: Vec2::ZERO,
+            zoom: 2.,
+            target: 0,
+            error: None,
+        }
+    }
+}
+
+impl WizardState {
+    /// Columns and rows of whole frames that fit in an image of `size`.
+    fn grid(&self, size: Vec2) -> UVec2 {
+        let step = self.tile_size + self.padding;
+        if step.cmple(Vec2::ZERO).any() {
+            return UVec2::ZERO;
+        }
+        ((size - self.offset + self.padding) / step)
+            .floor()
+            .max(Vec2::ZERO)
+            .as_uvec2()
+    }
+}
+
+fn assets_dir() -> PathBuf {
+    FileAssetReader::get_base_path().join("assets")
+}
+
+/// Asset path of a picked image. Images outside both the assets folder and the
+/// project's folders are copied into the assets folder first.
+fn import_image(path: &Path, roots: &ProjectRoots) -> Result<String, String> {
+    let assets = assets_dir();
+    if let Ok(relative) = path.strip_prefix(&assets) {
+        return Ok(relative.to_string_lossy().replace('\\', "/"));
+    }
+    if let Some(asset_path) = roots.asset_path(path) {
+        return Ok(asset_path);
+    }
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file", path.display()))?;
+    let target = assets.join(IMPORT_DIR).join(file_name);
+    if target.exists() {
+        return Err(format!("{} already exists", target.display()));
+    }
+    std::fs::create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+    std::fs::copy(path, &target).map_err(|e| e.to_string())?;
+    Ok(format!("{IMPORT_DIR}/{}", file_name.to_string_lossy()))
+}
+
+fn wizard_ui(
+    mut contexts: EguiContexts,
+    mut wizard: ResMut<TilesetWizard>,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    tile_handle: Res<TilesData>,
+    tile_assets: Res<Assets<TileDefinition>>,
+    project: Res<CurrentProject>,
+    roots: Res<ProjectRoots>,
+) {
+    let Some(state) = &mut wizard.0 else {
+        return;
+    };
+    if let Some(dialog) = &mut state.dialog {
+        dialog.show(contexts.ctx_mut());
+        if dialog.selected() {
+            match dialog.path().map(|path| import_image(path, &roots)) {
+                Some(Ok(path)) => {
+                    if state.name.is_empty() {
+                        state.name = Path::new(&path)
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+                    }
+                    state.image = Some((path.clone(), asset_server.load(path)));
+                    state.error = None;
+                }
+                Some(Err(e)) => state.error = Some(e),
+                None => {}
+            }
+            state.dialog = None;
+        } else if !dialog.visible() {
+            state.dialog = None;
+        }
+    }
+    let texture = state
+        .image
+        .as_ref()
+        .map(|(_, handle)| contexts.add_image(handle.clone_weak()));
+
+    let mut open = true;
+    let mut create = false;
+    egui::Window::new("New Tileset")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|hui| {
+                let label = state
+                    .image
+                    .as_ref()
+                    .map_or("No image", |(path, _)| path.as_str());
+                hui.label(label);
+                if hui.button("Browse").clicked() && state.dialog.is_none() {
+                    state.dialog = Some(png_dialog());
+                }
+            });
+            ui.horizontal(|hui| {
+                hui.label("Name");
+                hui.text_edit_singleline(&mut state.name);
+            });
+            let definitions = &project.project.definitions;
+            state.target = state.target.min(definitions.len().saturating_sub(1));
+            egui::ComboBox::from_label("Definition File")
+                .selected_text(definitions.get(state.target).map_or("", |d| d.as_str()))
+                .show_ui(ui, |cui| {
+                    for (i, definition) in definitions.iter().enumerate() {
+                        cui.selectable_value(&mut state.target, i, definition);
+                    }
+                });
+            for (label, value) in [
+                ("Tile Size", &mut state.tile_size),
+                ("Padding", &mut state.padding),
+                ("Offset", &mut state.offset),
+            ] {
+                ui.horizontal(|hui| {
+                    hui.label(label);
+                    hui.add(egui::DragValue::new(&mut value.x).clamp_range(0.0..=4096.0));
+                    hui.add(egui::DragValue::new(&mut value.y).clamp_range(0.0..=4096.0));
+                });
+            }
+            state.tile_size = state.tile_size.max(Vec2::ONE);
+            ui.add(egui::Slider::new(&mut state.zoom, 0.25..=8.0).text("Zoom"));
+
+            let size = state
+                .image
+                .as_ref()
+                .and_then(|(_, handle)| images.get(handle))
+                .map(|image| image.size_f32());
+            let (Some(texture), Some(size)) = (texture, size) else {
+                ui.label("Pick a PNG to slice.");
+                return;
+            };
+            let grid = state.grid(size);
+            ui.label(format!(
+                "{}x{} pixels, {} columns, {} rows",
+                size.x, size.y, grid.x, grid.y
+            ));
+
+            egui::ScrollArea::both().max_height(512.).show(ui, |sui| {
+                let shown = size * state.zoom;
+                let response = sui.image((texture, egui::vec2(shown.x, shown.y)));
+                let origin = response.rect.min;
+                let stroke = egui::Stroke::new(1.0_f32, egui::Color32::YELLOW);
+                for row in 0..grid.y {
+                    for column in 0..grid.x {
+                        let cell = UVec2::new(column, row).as_vec2();
+                        let min =
+                            (state.offset + cell * (state.tile_size + state.padding)) * state.zoom;
+                        let max = min + state.tile_size * state.zoom;
+                        let rect = egui::Rect::from_min_max(
+                            origin + egui::vec2(min.x, min.y),
+                            origin + egui::vec2(max.x, max.y),
+                        );
+                        sui.painter().rect_stroke(rect, 0., stroke);
+                    }
+                }
+            });
+
+            if let Some(error) = &state.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+            let taken = tile_handle
+                .tiles(&tile_assets)
+                .any(|t| t.name == state.name);
+            let valid = !state.name.is_empty() && !taken && grid.cmpgt(UVec2::ZERO).all();
+            if taken {
+                ui.label("A tile with this name already exists.");
+            }
+            create = ui.add_enabled(valid, egui::Button::new("Create")).clicked();
+        });
+
+    if create {
+        let definition_file = tile_handle
+            .0
+            .get(state.target)
+            .and_then(|handle| asset_server.get_path(handle))
+            .and_then(|path| asset_file(&roots, &path));
+        let (Some((path, _)), Some(definition_file)) = (&state.image, definition_file) else {
+            state.error = Some("Could not find the definition file".to_string());
+            return;
+        };
+        let size = images
+            .get(&state.image.as_ref().unwrap().1)
+            .map_or(Vec2::ZERO, |image| image.size_f32());
+        let grid = state.grid(size);
+        let tile = Tile {
+            name: state.name.clone(),
+            path: path.clone(),
+            atlas_definition: Some(AtlasDefinition {
+                tile_size: state.tile_size,
+                columns: grid.x as usize,
+                rows: grid.y as usize,
+                padding: (state.padding != Vec2::ZERO).then_some(state.padding),
+                offsest: (state.offset != Vec2::ZERO).then_some(state.offset),
+                render_size: default(),
+                anchor: default(),
+                animations: Vec::new(),
+            }),
+            images: None,
+            terrains: Vec::new(),
+            adjacency: default(),
+        };
+        // The definitions are hot reloaded once the file changes.
+        match append_tile(&definition_file, tile) {
+            Ok(()) => {
+                info!("Added tileset {}", state.name);
+                wizard.0 = None;
+            }
+            Err(e) => state.error = Some(e.to_string()),
+        }
+    } else if !open {
+        wizard.0 = None;
+    }
+}